@@ -0,0 +1,245 @@
+use std::fmt;
+
+use crate::{ManageSieveError, SieveClient};
+
+/// One line of the difference between two texts, see [`diff_lines`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    /// Only in the old text
+    Removed(&'a str),
+    /// Only in the new text
+    Added(&'a str),
+}
+
+// Rendered like a unified diff without the hunk headers
+impl fmt::Display for DiffLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffLine::Same(line) => write!(f, "  {}", line),
+            DiffLine::Removed(line) => write!(f, "- {}", line),
+            DiffLine::Added(line) => write!(f, "+ {}", line),
+        }
+    }
+}
+
+// Largest table `diff_lines` builds, about 64 MiB. Beyond that the changed
+// part is shown as replaced as a whole.
+const MAX_DIFF_CELLS: usize = 16 * 1024 * 1024;
+
+/// The lines of `old` and `new` in order, with the lines only one of them has
+/// marked. Removed lines come before the added lines replacing them.
+///
+/// Texts that differ in thousands of lines in one place get all of those
+/// marked as removed and added instead of a minimal diff.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // Edits are usually in one place, only the part in between needs the table
+    let prefix = old
+        .iter()
+        .zip(&new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+
+    let mut diff = Vec::with_capacity(old.len().max(new.len()));
+    diff.extend(old[..prefix].iter().copied().map(DiffLine::Same));
+    diff_changed(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+        &mut diff,
+    );
+    diff.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .copied()
+            .map(DiffLine::Same),
+    );
+
+    diff
+}
+
+// The longest common subsequence diff of `old` and `new`, appended to `diff`
+fn diff_changed<'a>(old: &[&'a str], new: &[&'a str], diff: &mut Vec<DiffLine<'a>>) {
+    if (old.len() + 1).saturating_mul(new.len() + 1) > MAX_DIFF_CELLS {
+        diff.extend(old.iter().copied().map(DiffLine::Removed));
+        diff.extend(new.iter().copied().map(DiffLine::Added));
+        return;
+    }
+
+    // `common[i][j]` is the length of the longest common subsequence of
+    // `old[i..]` and `new[j..]`
+    let mut common = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            diff.push(DiffLine::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            diff.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    diff.extend(old[i..].iter().copied().map(DiffLine::Removed));
+    diff.extend(new[j..].iter().copied().map(DiffLine::Added));
+}
+
+/// The scripts of two accounts side by side, see [`compare_accounts`]. Names
+/// are sorted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Comparison {
+    pub only_first: Vec<String>,
+    pub only_second: Vec<String>,
+    pub identical: Vec<String>,
+    pub different: Vec<DifferentScript>,
+}
+
+/// A script both accounts have, with different content
+#[derive(Debug, Clone, PartialEq)]
+pub struct DifferentScript {
+    pub name: String,
+    pub first: String,
+    pub second: String,
+}
+
+impl DifferentScript {
+    /// How the second account's copy differs from the first one's
+    pub fn diff(&self) -> Vec<DiffLine<'_>> {
+        diff_lines(&self.first, &self.second)
+    }
+}
+
+/// Lists the scripts of both accounts and downloads the ones they have in
+/// common to find those that differ, e.g. for accounts meant to be kept in sync.
+pub async fn compare_accounts(
+    first: &SieveClient,
+    second: &SieveClient,
+) -> Result<Comparison, ManageSieveError> {
+    let mut first_names: Vec<String> = first
+        .list_scripts()
+        .await?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let mut second_names: Vec<String> = second
+        .list_scripts()
+        .await?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    first_names.sort();
+    second_names.sort();
+
+    let mut comparison = Comparison {
+        only_second: second_names
+            .iter()
+            .filter(|name| !first_names.contains(name))
+            .cloned()
+            .collect(),
+        ..Comparison::default()
+    };
+
+    for name in first_names {
+        if !second_names.contains(&name) {
+            comparison.only_first.push(name);
+            continue;
+        }
+
+        let (first_content, second_content) =
+            tokio::try_join!(first.get_script(&name), second.get_script(&name))?;
+        if first_content == second_content {
+            comparison.identical.push(name);
+        } else {
+            comparison.different.push(DifferentScript {
+                name,
+                first: first_content,
+                second: second_content,
+            });
+        }
+    }
+
+    Ok(comparison)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        let old = "require \"fileinto\";\nif true {\n    fileinto \"Old\";\n}\nkeep;\n";
+        let new = "require \"fileinto\";\nif true {\n    fileinto \"New\";\n}\n";
+
+        assert_eq!(
+            diff_lines(old, new),
+            vec![
+                DiffLine::Same("require \"fileinto\";"),
+                DiffLine::Same("if true {"),
+                DiffLine::Removed("    fileinto \"Old\";"),
+                DiffLine::Added("    fileinto \"New\";"),
+                DiffLine::Same("}"),
+                DiffLine::Removed("keep;"),
+            ]
+        );
+        assert_eq!(DiffLine::Added("stop;").to_string(), "+ stop;");
+    }
+
+    #[test]
+    fn test_diff_lines_edges() {
+        assert_eq!(diff_lines("", ""), vec![]);
+        assert_eq!(diff_lines("", "keep;"), vec![DiffLine::Added("keep;")]);
+        assert_eq!(diff_lines("keep;", ""), vec![DiffLine::Removed("keep;")]);
+        // Only the line ending differs, which doesn't count as a change
+        assert_eq!(
+            diff_lines("keep;\r\n", "keep;\n"),
+            vec![DiffLine::Same("keep;")]
+        );
+        // The same line repeated, the change could be matched anywhere
+        assert_eq!(
+            diff_lines("keep;\nkeep;\n", "keep;\nkeep;\nkeep;\n"),
+            vec![
+                DiffLine::Same("keep;"),
+                DiffLine::Same("keep;"),
+                DiffLine::Added("keep;")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_lines_large() {
+        // Too many changed lines for the table, but the unchanged ends still
+        // come out as such
+        let old: String = (0..5000).map(|i| format!("# old {}\n", i)).collect();
+        let new: String = (0..5000).map(|i| format!("# new {}\n", i)).collect();
+        let old = format!("keep;\n{}stop;\n", old);
+        let new = format!("keep;\n{}stop;\n", new);
+
+        let diff = diff_lines(&old, &new);
+        assert_eq!(diff.len(), 10002);
+        assert_eq!(diff[0], DiffLine::Same("keep;"));
+        assert_eq!(diff[1], DiffLine::Removed("# old 0"));
+        assert_eq!(diff[5001], DiffLine::Added("# new 0"));
+        assert_eq!(diff[10001], DiffLine::Same("stop;"));
+    }
+}
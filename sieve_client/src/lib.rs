@@ -1,4 +1,15 @@
-mod parser;
+mod diff;
+pub mod parser;
+mod sasl;
 mod sieve_client;
+mod tls;
 
-pub use sieve_client::{ManageSieveError, SieveClient};
+pub use diff::{Comparison, DiffLine, DifferentScript, compare_accounts, diff_lines};
+pub use rustls_pki_types::CertificateDer;
+pub use sasl::Mechanism;
+pub use secrecy::{ExposeSecret, SecretString};
+pub use sieve_client::{
+    Capabilities, CheckResult, ConnectError, ConnectOptions, CredentialNormalization,
+    ManageSieveError, ResponseCode, SieveClient, SieveError, TlsMode, validate_script_name,
+};
+pub use tls::{CertStore, ClientIdentity, certificate_fingerprint, parse_certificates};
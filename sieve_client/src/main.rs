@@ -0,0 +1,781 @@
+use std::{
+    error::Error,
+    ffi::OsString,
+    io,
+    net::IpAddr,
+    path::PathBuf,
+    pin::Pin,
+    process::ExitCode,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use sieve_client::{
+    CertStore, ClientIdentity, ConnectOptions, DiffLine, SieveClient, TlsMode, compare_accounts,
+    parse_certificates, parser, validate_script_name,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// Backups name the active script in this file, next to the scripts
+const ACTIVE_FILE: &str = ".active";
+// The scripts backups wrote into the directory, one name per line. Only these
+// files are ever deleted by `backup --prune`.
+const MANIFEST_FILE: &str = ".manifest";
+const EXTENSION: &str = "sieve";
+
+// A server that stalls in the middle of a response, or sends a script shorter
+// than it announced, would otherwise leave the command hanging
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Scripts larger than this also report the bytes transferred so far, once per step
+const BYTE_PROGRESS_STEP: usize = 64 * 1024;
+
+/// Manage the Sieve scripts of a mail account from the command line.
+///
+/// The connection settings are taken from the options, then from the SIEVE_*
+/// environment variables and last from the env file, `.env` unless
+/// `--env-file` names another one. Variables already set in the environment
+/// are never overwritten by the file.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Read SIEVE_* variables from this file instead of `.env`, e.g. to keep
+    /// the credentials of each server in its own file
+    #[arg(long, value_name = "PATH")]
+    env_file: Option<PathBuf>,
+    #[arg(long, env = "SIEVE_HOST")]
+    host: String,
+    #[arg(long, env = "SIEVE_PORT", default_value_t = 4190)]
+    port: u16,
+    #[arg(long, env = "SIEVE_USERNAME")]
+    username: String,
+    #[arg(long, env = "SIEVE_PASSWORD", hide_env_values = true)]
+    password: String,
+    /// Connect from this local IP address, e.g. to go through a VPN interface
+    #[arg(long, env = "SIEVE_LOCAL_ADDRESS")]
+    local_address: Option<IpAddr>,
+    /// How the connection is secured: `starttls`, `implicit` for servers that
+    /// speak TLS from the first byte, or `none`
+    #[arg(long, env = "SIEVE_TLS", default_value = "starttls", value_parser = parse_tls_mode)]
+    tls: TlsMode,
+    /// Send the credentials even though the connection isn't encrypted, only
+    /// meant for test servers on the local machine
+    #[arg(long, env = "SIEVE_ALLOW_PLAINTEXT")]
+    allow_plaintext: bool,
+    /// Also trust the CA certificates in this PEM file, e.g. the CA of a
+    /// private mail server
+    #[arg(long, env = "SIEVE_CA_FILE", value_name = "PATH")]
+    ca_file: Option<PathBuf>,
+    /// Trust only the certificates of `--ca-file`, not the bundled roots
+    #[arg(long, requires = "ca_file")]
+    only_ca_file: bool,
+    /// Trust any server certificate, e.g. a self-signed one in a lab. Anyone
+    /// on the network path can read the credentials and scripts.
+    #[arg(long)]
+    insecure_skip_verify: bool,
+    /// Present the certificate and private key in this PEM file to servers that
+    /// ask for one, logging in with SASL EXTERNAL where the server offers it
+    #[arg(long, env = "SIEVE_CLIENT_CERT", value_name = "PATH")]
+    client_cert: Option<PathBuf>,
+    /// Check the server certificate for this name instead of `--host`, e.g.
+    /// when connecting through an SSH tunnel
+    #[arg(long, env = "SIEVE_TLS_SERVER_NAME", value_name = "NAME")]
+    tls_server_name: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the scripts on the server, the active one is marked with `*`
+    List,
+    /// Download every script into a directory, one `.sieve` file per script
+    Backup {
+        dir: PathBuf,
+        /// Delete the files an earlier backup wrote for scripts that are no
+        /// longer on the server, so a later `restore --prune` doesn't bring
+        /// them back. Other files in the directory are left alone.
+        #[arg(long)]
+        prune: bool,
+        /// Only print what would be downloaded and deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Upload every `.sieve` file in a directory and activate the script that
+    /// was active when the backup was made
+    Restore {
+        dir: PathBuf,
+        /// Delete scripts on the server that aren't in the directory
+        #[arg(long)]
+        prune: bool,
+        /// Only print what would be uploaded, activated and deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Delete a script
+    Delete {
+        name: String,
+        /// Only print what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Make a script the active one
+    Activate {
+        name: String,
+        /// Only print what would be activated
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Look for likely mistakes in a local script, including more redirects
+    /// than the server allows
+    Lint { file: PathBuf },
+    /// Compare the scripts with those of another account, listing the scripts
+    /// only one of them has and the differences in the ones both have
+    Compare {
+        #[arg(long, env = "SIEVE_OTHER_HOST")]
+        other_host: String,
+        #[arg(long, env = "SIEVE_OTHER_PORT", default_value_t = 4190)]
+        other_port: u16,
+        #[arg(long, env = "SIEVE_OTHER_USERNAME")]
+        other_username: String,
+        #[arg(long, env = "SIEVE_OTHER_PASSWORD", hide_env_values = true)]
+        other_password: String,
+        /// `--tls-server-name` for the other account, the one of the first
+        /// account isn't reused
+        #[arg(long, env = "SIEVE_OTHER_TLS_SERVER_NAME", value_name = "NAME")]
+        other_tls_server_name: Option<String>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // Loaded before parsing so the file can provide values for the env bindings
+    match env_file_arg(std::env::args_os()) {
+        Some(path) => {
+            if let Err(err) = dotenv::from_path(&path) {
+                eprintln!("Error: Can't load {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => {
+            dotenv::dotenv().ok();
+        }
+    }
+    let args = Args::parse();
+
+    // Silent unless RUST_LOG asks for more, e.g. `sieve_client=debug` for the
+    // protocol traffic. Logged to stderr so it doesn't mix with the output.
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Finds `--env-file <path>` or `--env-file=<path>` ahead of the real parse
+fn env_file_arg(args: impl IntoIterator<Item = OsString>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--env-file" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--env-file=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+fn parse_tls_mode(mode: &str) -> Result<TlsMode, String> {
+    match mode.to_ascii_lowercase().as_str() {
+        "starttls" => Ok(TlsMode::StartTls),
+        "implicit" => Ok(TlsMode::Implicit),
+        "none" => Ok(TlsMode::None),
+        _ => Err("expected starttls, implicit or none".to_string()),
+    }
+}
+
+async fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    if args.insecure_skip_verify {
+        eprintln!(
+            "Warning: the server certificate isn't validated, the connection can be intercepted"
+        );
+    }
+    let extra_root_certificates = match &args.ca_file {
+        Some(path) => parse_certificates(&tokio::fs::read_to_string(path).await?)
+            .map_err(|err| format!("Can't read {}: {}", path.display(), err))?,
+        None => Vec::new(),
+    };
+    let client_identity = match &args.client_cert {
+        Some(path) => Some(
+            ClientIdentity::from_pem(&tokio::fs::read_to_string(path).await?)
+                .map_err(|err| format!("Can't read {}: {}", path.display(), err))?,
+        ),
+        None => None,
+    };
+    let options = ConnectOptions {
+        local_address: args.local_address,
+        tls_mode: args.tls,
+        require_tls: !args.allow_plaintext,
+        insecure_skip_verify: args.insecure_skip_verify,
+        extra_root_certificates,
+        client_identity,
+        tls_server_name: args.tls_server_name,
+        command_timeout: Some(COMMAND_TIMEOUT),
+        cert_store: if args.only_ca_file {
+            CertStore::ExtraOnly
+        } else {
+            CertStore::default()
+        },
+        ..ConnectOptions::default()
+    };
+    let client = SieveClient::connect_with_options(
+        args.host.clone(),
+        args.port,
+        &args.username,
+        &args.password,
+        options.clone(),
+    )
+    .await?;
+
+    match args.command {
+        Command::List => {
+            for (name, is_active) in client.list_scripts().await? {
+                println!("{} {}", if is_active { "*" } else { " " }, name);
+            }
+        }
+        Command::Backup {
+            dir,
+            prune,
+            dry_run,
+        } => backup(&client, dir, prune, dry_run).await?,
+        Command::Restore {
+            dir,
+            prune,
+            dry_run,
+        } => restore(&client, dir, prune, dry_run).await?,
+        Command::Delete { name, dry_run } => {
+            if dry_run {
+                println!("Would delete \"{}\"", name);
+            } else {
+                client.delete_script(&name).await?;
+            }
+        }
+        Command::Activate { name, dry_run } => {
+            if dry_run {
+                println!("Would activate \"{}\"", name);
+            } else {
+                client.set_active_script(&name).await?;
+            }
+        }
+        Command::Lint { file } => lint(&client, file).await?,
+        Command::Compare {
+            other_host,
+            other_port,
+            other_username,
+            other_password,
+            other_tls_server_name,
+        } => {
+            let options = ConnectOptions {
+                tls_server_name: other_tls_server_name,
+                ..options
+            };
+            let other = SieveClient::connect_with_options(
+                other_host.clone(),
+                other_port,
+                &other_username,
+                &other_password,
+                options,
+            )
+            .await?;
+            compare(&client, &args.host, &other, &other_host).await?;
+            let _ = other.logout().await;
+        }
+    }
+
+    // The command is done, a failed goodbye doesn't change that
+    let _ = client.logout().await;
+    Ok(())
+}
+
+// With `dry_run` nothing is written or deleted, every step is only printed
+async fn backup(
+    client: &SieveClient,
+    dir: PathBuf,
+    prune: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let scripts = client.list_scripts().await?;
+    // Script names are free text, but have to be usable as file names here
+    if let Some((name, _)) = scripts
+        .iter()
+        .find(|(name, _)| name.contains(['/', '\\']) || name.starts_with('.'))
+    {
+        return Err(format!("Script \"{}\" can't be stored as a file", name).into());
+    }
+    if !dry_run {
+        tokio::fs::create_dir_all(&dir).await?;
+    }
+
+    let mut bytes = 0;
+    for (index, (name, _)) in scripts.iter().enumerate() {
+        if dry_run {
+            progress(index, scripts.len(), "would download", name);
+            continue;
+        }
+
+        // Streamed to the file as is. Scripts should be UTF-8 (RFC 5804), but
+        // one that isn't is still backed up byte for byte, restore deals with it.
+        // It goes to a temporary file first, so a transfer that fails halfway
+        // leaves the backup of the previous run in place.
+        let path = dir.join(format!("{}.{}", name, EXTENSION));
+        let temporary = dir.join(format!("{}.{}.tmp", name, EXTENSION));
+        let file = tokio::fs::File::create(&temporary).await?;
+        let mut file = ByteProgress::new(file, None, index, scripts.len(), "downloading", name);
+        let result = client.get_script_to(name, &mut file).await;
+        drop(file);
+        if let Err(err) = result {
+            let _ = tokio::fs::remove_file(&temporary).await;
+            return Err(err.into());
+        }
+        bytes += tokio::fs::metadata(&temporary).await?.len() as usize;
+        tokio::fs::rename(&temporary, &path).await?;
+        progress(index, scripts.len(), "downloaded", name);
+    }
+
+    let written = match tokio::fs::read_to_string(dir.join(MANIFEST_FILE)).await {
+        Ok(manifest) => manifest.lines().map(str::to_string).collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let stale = stale_files(&written, &scripts);
+
+    let mut removed = Vec::new();
+    if prune && scripts.is_empty() {
+        // More likely the wrong account than every script deleted on purpose
+        eprintln!("Warning: the server has no scripts, nothing is pruned");
+    } else if prune {
+        for name in &stale {
+            let path = dir.join(format!("{}.{}", name, EXTENSION));
+            if dry_run {
+                println!("Would delete {}", path.display());
+            } else {
+                println!(
+                    "Deleting {}, \"{}\" is no longer on the server",
+                    path.display(),
+                    name
+                );
+                match tokio::fs::remove_file(&path).await {
+                    Ok(()) => {}
+                    // Already deleted by hand
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            removed.push(name);
+        }
+    }
+
+    if dry_run {
+        println!(
+            "Would download {} scripts to {}{}",
+            scripts.len(),
+            dir.display(),
+            if prune {
+                format!(", would delete {} old files", removed.len())
+            } else {
+                String::new()
+            }
+        );
+        return Ok(());
+    }
+
+    // Files that weren't pruned stay listed, a later `--prune` may still delete them
+    let manifest: String = stale
+        .iter()
+        .filter(|name| !removed.contains(name))
+        .chain(scripts.iter().map(|(name, _)| name))
+        .map(|name| format!("{}\n", name))
+        .collect();
+    tokio::fs::write(dir.join(MANIFEST_FILE), manifest).await?;
+
+    let active = scripts
+        .iter()
+        .find(|(_, is_active)| *is_active)
+        .map(|(name, _)| name.as_str());
+    let active_file = dir.join(ACTIVE_FILE);
+    match active {
+        Some(name) => tokio::fs::write(&active_file, name).await?,
+        None if active_file.exists() => tokio::fs::remove_file(&active_file).await?,
+        None => {}
+    }
+
+    println!(
+        "Downloaded {} scripts ({}) to {}{}",
+        scripts.len(),
+        format_bytes(bytes),
+        dir.display(),
+        if prune {
+            format!(", deleted {} old files", removed.len())
+        } else {
+            String::new()
+        }
+    );
+    Ok(())
+}
+
+// The scripts an earlier backup wrote, according to the manifest, that are no
+// longer on the server. Names that can't have come from a backup are skipped,
+// so an edited manifest can't point outside the directory.
+fn stale_files(written: &[String], scripts: &[(String, bool)]) -> Vec<String> {
+    written
+        .iter()
+        .filter(|name| !name.is_empty() && !name.contains(['/', '\\']) && !name.starts_with('.'))
+        .filter(|name| !scripts.iter().any(|(script, _)| script == *name))
+        .cloned()
+        .collect()
+}
+
+// With `dry_run` nothing is changed on the server, every step is only printed
+async fn restore(
+    client: &SieveClient,
+    dir: PathBuf,
+    prune: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == EXTENSION)
+            && let Some(name) = path.file_stem().and_then(|stem| stem.to_str())
+        {
+            validate_script_name(name)?;
+            files.push((name.to_string(), path.clone()));
+        }
+    }
+    files.sort();
+
+    let mut bytes = 0;
+    for (index, (name, path)) in files.iter().enumerate() {
+        // Read whole as the server only takes UTF-8, what a backup kept of a
+        // script that wasn't is uploaded with the invalid bytes replaced
+        let content = tokio::fs::read(path).await?;
+        let content = match String::from_utf8(content) {
+            Ok(content) => content,
+            Err(err) => {
+                eprintln!(
+                    "Warning: {} isn't valid UTF-8, invalid bytes are replaced with U+FFFD",
+                    path.display()
+                );
+                String::from_utf8_lossy(err.as_bytes()).into_owned()
+            }
+        };
+        bytes += content.len();
+        if !dry_run {
+            let mut input = ByteProgress::new(
+                content.as_bytes(),
+                Some(content.len()),
+                index,
+                files.len(),
+                "uploading",
+                name,
+            );
+            client
+                .put_script_from(name, content.len(), &mut input)
+                .await?;
+        }
+        progress(
+            index,
+            files.len(),
+            if dry_run { "would upload" } else { "uploaded" },
+            name,
+        );
+    }
+
+    let active = match tokio::fs::read_to_string(dir.join(ACTIVE_FILE)).await {
+        Ok(name) => Some(name.trim().to_string()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    if let Some(name) = &active {
+        if dry_run {
+            println!("Would activate \"{}\"", name);
+        } else {
+            println!("Activating \"{}\"", name);
+            client.set_active_script(name).await?;
+        }
+    }
+
+    let mut pruned = 0;
+    if prune {
+        for (name, _) in client.list_scripts().await? {
+            if !files.iter().any(|(file, _)| *file == name) {
+                if dry_run {
+                    println!("Would delete \"{}\"", name);
+                } else {
+                    println!("Deleting \"{}\"", name);
+                    client.delete_script(&name).await?;
+                }
+                pruned += 1;
+            }
+        }
+    }
+
+    println!(
+        "{} {} scripts ({}) from {}{}",
+        if dry_run { "Would upload" } else { "Uploaded" },
+        files.len(),
+        format_bytes(bytes),
+        dir.display(),
+        if prune {
+            format!(
+                ", {} {}",
+                if dry_run { "would delete" } else { "deleted" },
+                pruned
+            )
+        } else {
+            String::new()
+        }
+    );
+    Ok(())
+}
+
+async fn lint(client: &SieveClient, file: PathBuf) -> Result<(), Box<dyn Error>> {
+    let script = parser::parse(&tokio::fs::read_to_string(&file).await?)?;
+
+    let mut findings = parser::lint(&script);
+    if let Some(max) = client.capabilities().maxredirects {
+        findings.extend(parser::lint_redirects(&script, max));
+    }
+
+    for finding in &findings {
+        println!("{}: {}", file.display(), finding);
+    }
+    if findings.is_empty() {
+        println!("{}: no problems found", file.display());
+        Ok(())
+    } else {
+        Err(format!("{} problem(s) found", findings.len()).into())
+    }
+}
+
+async fn compare(
+    client: &SieveClient,
+    host: &str,
+    other_client: &SieveClient,
+    other_host: &str,
+) -> Result<(), Box<dyn Error>> {
+    let comparison = compare_accounts(client, other_client).await?;
+    for name in &comparison.only_first {
+        println!("Only on {}: \"{}\"", host, name);
+    }
+    for name in &comparison.only_second {
+        println!("Only on {}: \"{}\"", other_host, name);
+    }
+    for script in &comparison.different {
+        println!("Differs: \"{}\"", script.name);
+        let diff = script.diff();
+        if diff.iter().all(|line| matches!(line, DiffLine::Same(_))) {
+            println!("  only in line endings");
+        }
+        for line in diff
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Same(_)))
+        {
+            println!("  {}", line);
+        }
+    }
+    println!(
+        "{} identical, {} different, {} only on {}, {} only on {}",
+        comparison.identical.len(),
+        comparison.different.len(),
+        comparison.only_first.len(),
+        host,
+        comparison.only_second.len(),
+        other_host
+    );
+    Ok(())
+}
+
+// Prints e.g. `[3/20 15%] downloaded "spamfilter"` once a script is transferred,
+// so the last one reads 100%
+fn progress(index: usize, total: usize, action: &str, name: &str) {
+    println!(
+        "[{}/{} {}%] {} \"{}\"",
+        index + 1,
+        total,
+        (index + 1) * 100 / total,
+        action,
+        name
+    );
+}
+
+// Counts the bytes of a script passing through and prints e.g.
+// `[3/20] downloading "spamfilter": 128.0 KiB` every `BYTE_PROGRESS_STEP`, so a
+// big script shows it is still moving
+struct ByteProgress<'a, T> {
+    inner: T,
+    // Size of the script, if known before the transfer
+    total: Option<usize>,
+    index: usize,
+    count: usize,
+    action: &'a str,
+    name: &'a str,
+    bytes: usize,
+}
+
+impl<'a, T> ByteProgress<'a, T> {
+    fn new(
+        inner: T,
+        total: Option<usize>,
+        index: usize,
+        count: usize,
+        action: &'a str,
+        name: &'a str,
+    ) -> Self {
+        Self {
+            inner,
+            total,
+            index,
+            count,
+            action,
+            name,
+            bytes: 0,
+        }
+    }
+
+    fn add(&mut self, bytes: usize) {
+        let before = self.bytes / BYTE_PROGRESS_STEP;
+        self.bytes += bytes;
+        if self.bytes / BYTE_PROGRESS_STEP > before {
+            println!(
+                "[{}/{}] {} \"{}\": {}{}",
+                self.index + 1,
+                self.count,
+                self.action,
+                self.name,
+                format_bytes(self.bytes),
+                match self.total {
+                    Some(total) => format!(" of {}", format_bytes(total)),
+                    None => String::new(),
+                }
+            );
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for ByteProgress<'_, T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.add(written);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for ByteProgress<'_, T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            this.add(buf.filled().len() - filled);
+        }
+        result
+    }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn env_file(args: &[&str]) -> Option<PathBuf> {
+        env_file_arg(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn test_stale_files() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        let scripts = vec![("main".to_string(), true), ("vacation".to_string(), false)];
+
+        assert_eq!(
+            stale_files(&names(&["main", "old", "vacation"]), &scripts),
+            names(&["old"])
+        );
+        // Files no backup wrote aren't listed, so they are never stale
+        assert!(stale_files(&[], &scripts).is_empty());
+        assert!(stale_files(&names(&["../escape", ".active", ""]), &scripts).is_empty());
+    }
+
+    #[test]
+    fn test_env_file_arg() {
+        assert_eq!(env_file(&["sieve_client", "list"]), None);
+        assert_eq!(
+            env_file(&["sieve_client", "--env-file", "work.env", "list"]),
+            Some(PathBuf::from("work.env"))
+        );
+        assert_eq!(
+            env_file(&["sieve_client", "--env-file=home.env", "list"]),
+            Some(PathBuf::from("home.env"))
+        );
+        assert_eq!(
+            env_file(&["sieve_client", "delete", "--", "--env-file"]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_tls_mode() {
+        assert_eq!(parse_tls_mode("starttls"), Ok(TlsMode::StartTls));
+        assert_eq!(parse_tls_mode("Implicit"), Ok(TlsMode::Implicit));
+        assert_eq!(parse_tls_mode("none"), Ok(TlsMode::None));
+        assert!(parse_tls_mode("ssl").is_err());
+    }
+}
@@ -1,30 +1,45 @@
 // TODO
 
+use std::ops::Range;
+
 use nom::{
     IResult, Parser,
     branch::alt,
     bytes::complete::{is_not, tag, take_while},
-    character::streaming::char,
-    combinator::{map, opt, value, verify},
+    character::{complete::digit1, streaming::char},
+    combinator::{map, map_res, opt, value, verify},
     multi::{fold_many0, many0, separated_list0, separated_list1},
     sequence::{delimited, pair, preceded, tuple},
 };
+use thiserror::Error;
 
+mod builder;
+mod lint;
+mod serialize;
+mod simulate;
+mod summary;
 mod util;
+mod walk;
 
-use util::{multispace0, multispace1, parse_string, parse_string_array};
+pub use builder::{ScriptBuilder, used_extensions};
+pub use lint::{Finding, lint, lint_redirects, max_redirects};
+pub use serialize::{serialize, serialize_coalesced, serialize_preserving};
+pub use simulate::{Action, Message, Outcome, Unsupported, simulate, simulate_with_includes};
+pub use summary::{Summary, summarize};
+use util::{multispace0, multispace1, parse_string, parse_string_array, parse_string_list};
+pub use walk::{Node, walk};
 
 fn parse_require(input: &str) -> IResult<&str, Vec<String>> {
     delimited(
         pair(tag("require "), multispace0),
-        parse_string_array,
+        parse_string_list,
         char(';'),
     )
     .parse(input)
 }
 
 #[derive(Debug, PartialEq)]
-enum StringComparisonType {
+pub enum StringComparisonType {
     Is,
     Contains,
     Matches,
@@ -42,10 +57,10 @@ fn parse_string_comparison_type(input: &str) -> IResult<&str, StringComparisonTy
 }
 
 #[derive(Debug, PartialEq)]
-struct StringCondition {
-    comparison_type: StringComparisonType,
-    source: String,
-    value: String,
+pub struct StringCondition {
+    pub comparison_type: StringComparisonType,
+    pub source: String,
+    pub value: String,
 }
 
 fn parse_string_condition(input: &str) -> IResult<&str, StringCondition> {
@@ -78,12 +93,152 @@ fn parse_condition_list(input: &str) -> IResult<&str, Vec<Condition>> {
     .parse(input)
 }
 
+/// The part of a date compared by the `date` and `currentdate` tests (RFC 5260)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatePart {
+    Year,
+    Month,
+    Day,
+    Date,
+    Julian,
+    Hour,
+    Minute,
+    Second,
+    Time,
+    Iso8601,
+    Std11,
+    Zone,
+    Weekday,
+}
+
+impl DatePart {
+    pub const ALL: [DatePart; 13] = [
+        DatePart::Year,
+        DatePart::Month,
+        DatePart::Day,
+        DatePart::Date,
+        DatePart::Julian,
+        DatePart::Hour,
+        DatePart::Minute,
+        DatePart::Second,
+        DatePart::Time,
+        DatePart::Iso8601,
+        DatePart::Std11,
+        DatePart::Zone,
+        DatePart::Weekday,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DatePart::Year => "year",
+            DatePart::Month => "month",
+            DatePart::Day => "day",
+            DatePart::Date => "date",
+            DatePart::Julian => "julian",
+            DatePart::Hour => "hour",
+            DatePart::Minute => "minute",
+            DatePart::Second => "second",
+            DatePart::Time => "time",
+            DatePart::Iso8601 => "iso8601",
+            DatePart::Std11 => "std11",
+            DatePart::Zone => "zone",
+            DatePart::Weekday => "weekday",
+        }
+    }
+}
+
+fn parse_date_part(input: &str) -> IResult<&str, DatePart> {
+    map_res(parse_string, |part| {
+        DatePart::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str().eq_ignore_ascii_case(&part))
+            .ok_or(())
+    })
+    .parse(input)
+}
+
+/// Which time zone a date is converted to before comparing
+#[derive(Debug, PartialEq)]
+pub enum DateZone {
+    /// `:zone "+0100"`
+    Zone(String),
+    /// `:originalzone`, keep the zone the header was written in
+    Original,
+}
+
 #[derive(Debug, PartialEq)]
-enum Condition {
+pub struct DateCondition {
+    pub zone: Option<DateZone>,
+    pub comparison_type: StringComparisonType,
+    pub date_part: DatePart,
+    pub keys: Vec<String>,
+}
+
+enum DateTag {
+    Zone(DateZone),
+    Comparison(StringComparisonType),
+}
+
+// Parses the tags in any order, followed by the header name if `with_header`
+// is set (`date`), then the date part and the keys
+fn parse_date_condition<'a>(
+    with_header: bool,
+) -> impl FnMut(&'a str) -> IResult<&'a str, (Option<String>, DateCondition)> {
+    move |input| {
+        let tag_parser = alt((
+            preceded(pair(tag(":zone"), multispace1), parse_string)
+                .map(|zone| DateTag::Zone(DateZone::Zone(zone))),
+            tag(":originalzone").map(|_| DateTag::Zone(DateZone::Original)),
+            parse_string_comparison_type.map(DateTag::Comparison),
+        ));
+        let header = move |input| {
+            if with_header {
+                preceded(multispace1, parse_string).map(Some).parse(input)
+            } else {
+                Ok((input, None))
+            }
+        };
+
+        let (rest, (tags, header, date_part, keys)) = tuple((
+            many0(preceded(multispace1, tag_parser)),
+            header,
+            preceded(multispace1, parse_date_part),
+            preceded(multispace1, parse_string_list),
+        ))
+        .parse(input)?;
+
+        let mut condition = DateCondition {
+            zone: None,
+            comparison_type: StringComparisonType::Is,
+            date_part,
+            keys,
+        };
+        for tag in tags {
+            match tag {
+                DateTag::Zone(zone) => condition.zone = Some(zone),
+                DateTag::Comparison(comparison_type) => condition.comparison_type = comparison_type,
+            }
+        }
+
+        Ok((rest, (header, condition)))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Condition {
     Header(StringCondition),
     Address(StringCondition),
     AllOf(Vec<Condition>),
     AnyOf(Vec<Condition>),
+    /// `date`, compares a date taken from a header
+    Date {
+        header: String,
+        condition: DateCondition,
+    },
+    /// `currentdate`, compares the time the script runs at
+    CurrentDate(DateCondition),
+    /// `hasflag`, true if any of the flags is set on the message
+    HasFlag(Vec<Flag>),
 }
 
 fn parse_condition(input: &str) -> IResult<&str, Condition> {
@@ -97,6 +252,21 @@ fn parse_condition(input: &str) -> IResult<&str, Condition> {
         .map(Condition::Address),
         preceded(tag("allof"), preceded(multispace0, parse_condition_list)).map(Condition::AllOf),
         preceded(tag("anyof"), preceded(multispace0, parse_condition_list)).map(Condition::AnyOf),
+        // `:originalzone` makes no sense for the current date
+        map_res(
+            preceded(tag("currentdate"), parse_date_condition(false)),
+            |(_, condition)| match condition.zone {
+                Some(DateZone::Original) => Err(()),
+                _ => Ok(Condition::CurrentDate(condition)),
+            },
+        ),
+        preceded(tag("hasflag"), preceded(multispace1, parse_flags)).map(Condition::HasFlag),
+        preceded(tag("date"), parse_date_condition(true)).map(|(header, condition)| {
+            Condition::Date {
+                header: header.unwrap_or_default(),
+                condition,
+            }
+        }),
     ))
     .parse(input)
 }
@@ -118,11 +288,11 @@ fn simple_if<'a>(
 }
 
 #[derive(Debug, PartialEq)]
-struct If {
-    condition: Condition,
-    expressions: Vec<Expression>,
-    else_ifs: Vec<(Condition, Vec<Expression>)>,
-    else_block: Vec<Expression>,
+pub struct If {
+    pub condition: Condition,
+    pub expressions: Vec<Expression>,
+    pub else_ifs: Vec<(Condition, Vec<Expression>)>,
+    pub else_block: Vec<Expression>,
 }
 
 fn parse_if(input: &str) -> IResult<&str, If> {
@@ -152,8 +322,8 @@ fn parse_if(input: &str) -> IResult<&str, If> {
     ))
 }
 
-#[derive(Debug, PartialEq)]
-enum Flag {
+#[derive(Debug, Clone, PartialEq)]
+pub enum Flag {
     Seen,
     Flagged,
     Answered,
@@ -192,16 +362,569 @@ fn flag_command<'a>(command: &str) -> impl FnMut(&'a str) -> IResult<&'a str, Ve
     )
 }
 
+fn parse_keep(input: &str) -> IResult<&str, Option<Vec<Flag>>> {
+    delimited(
+        tag("keep"),
+        opt(preceded(
+            pair(multispace1, tag(":flags")),
+            preceded(multispace1, parse_flags),
+        )),
+        preceded(multispace0, char(';')),
+    )
+    .parse(input)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FileInto {
+    pub mailbox: String,
+    /// `:create` (RFC 5490), create the mailbox if it doesn't exist yet
+    pub create: bool,
+    /// `:copy` (RFC 3894), don't cancel the implicit keep
+    pub copy: bool,
+}
+
+impl FileInto {
+    pub fn new(mailbox: impl Into<String>) -> Self {
+        Self {
+            mailbox: mailbox.into(),
+            create: false,
+            copy: false,
+        }
+    }
+}
+
+fn parse_fileinto(input: &str) -> IResult<&str, FileInto> {
+    let (rest, (tags, mailbox)) = delimited(
+        tag("fileinto"),
+        pair(
+            many0(preceded(multispace1, alt((tag(":create"), tag(":copy"))))),
+            preceded(multispace1, parse_string),
+        ),
+        char(';'),
+    )
+    .parse(input)?;
+
+    Ok((
+        rest,
+        FileInto {
+            mailbox,
+            create: tags.contains(&":create"),
+            copy: tags.contains(&":copy"),
+        },
+    ))
+}
+
+/// `redirect [:copy] <address>`, forwards the message
+#[derive(Debug, PartialEq)]
+pub struct Redirect {
+    pub address: String,
+    /// `:copy` (RFC 3894), don't cancel the implicit keep
+    pub copy: bool,
+}
+
+fn parse_redirect(input: &str) -> IResult<&str, Redirect> {
+    let (rest, (copy, address)) = delimited(
+        tag("redirect"),
+        pair(
+            opt(preceded(multispace1, tag(":copy"))).map(|copy| copy.is_some()),
+            preceded(multispace1, parse_string),
+        ),
+        preceded(multispace0, char(';')),
+    )
+    .parse(input)?;
+
+    Ok((rest, Redirect { address, copy }))
+}
+
+/// `addheader [:last] <name> <value>` (RFC 5293)
+#[derive(Debug, PartialEq)]
+pub struct AddHeader {
+    /// Append the header at the end instead of inserting it at the top
+    pub last: bool,
+    pub name: String,
+    pub value: String,
+}
+
+fn parse_addheader(input: &str) -> IResult<&str, AddHeader> {
+    let (rest, (last, name, value)) = delimited(
+        tag("addheader"),
+        tuple((
+            opt(preceded(multispace1, tag(":last"))).map(|last| last.is_some()),
+            preceded(multispace1, parse_string),
+            preceded(multispace1, parse_string),
+        )),
+        preceded(multispace0, char(';')),
+    )
+    .parse(input)?;
+
+    Ok((rest, AddHeader { last, name, value }))
+}
+
+/// `deleteheader [:index <n> [:last]] [match-type] <name> [<value-patterns>]` (RFC 5293)
+#[derive(Debug, PartialEq)]
+pub struct DeleteHeader {
+    /// Only delete the n-th occurrence of the header, 1-based
+    pub index: Option<u32>,
+    /// Count `index` from the last occurrence instead of the first
+    pub last: bool,
+    pub comparison_type: Option<StringComparisonType>,
+    pub name: String,
+    /// Only delete occurrences whose value matches one of these, all of them if empty
+    pub value_patterns: Vec<String>,
+}
+
+enum DeleteHeaderTag {
+    Index(u32),
+    Last,
+    Comparison(StringComparisonType),
+}
+
+fn parse_deleteheader(input: &str) -> IResult<&str, DeleteHeader> {
+    let tag_parser = alt((
+        preceded(
+            pair(tag(":index"), multispace1),
+            map_res(digit1, str::parse),
+        )
+        .map(DeleteHeaderTag::Index),
+        tag(":last").map(|_| DeleteHeaderTag::Last),
+        parse_string_comparison_type.map(DeleteHeaderTag::Comparison),
+    ));
+
+    let (rest, (tags, name, value_patterns)) = delimited(
+        tag("deleteheader"),
+        tuple((
+            many0(preceded(multispace1, tag_parser)),
+            preceded(multispace1, parse_string),
+            opt(preceded(multispace1, parse_string_list)).map(Option::unwrap_or_default),
+        )),
+        preceded(multispace0, char(';')),
+    )
+    .parse(input)?;
+
+    let mut delete = DeleteHeader {
+        index: None,
+        last: false,
+        comparison_type: None,
+        name,
+        value_patterns,
+    };
+    for tag in tags {
+        match tag {
+            DeleteHeaderTag::Index(index) => delete.index = Some(index),
+            DeleteHeaderTag::Last => delete.last = true,
+            DeleteHeaderTag::Comparison(comparison_type) => {
+                delete.comparison_type = Some(comparison_type)
+            }
+        }
+    }
+
+    // RFC 5293 only allows `:last` together with `:index`
+    if delete.last && delete.index.is_none() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+
+    Ok((rest, delete))
+}
+
+/// `notify [:from <address>] [:importance <1-3>] [:options <strings>] [:message <text>] <method>`
+/// (RFC 5435). The older `:method <uri>` form is accepted too.
+#[derive(Debug, PartialEq)]
+pub struct Notify {
+    pub method: String,
+    pub from: Option<String>,
+    /// 1 is high, 2 normal and 3 low
+    pub importance: Option<u8>,
+    pub options: Vec<String>,
+    pub message: Option<String>,
+}
+
+impl Notify {
+    /// The URI scheme of the method, which is what the server lists in its
+    /// `NOTIFY` capability (e.g. `mailto`)
+    pub fn scheme(&self) -> Option<&str> {
+        self.method.split_once(':').map(|(scheme, _)| scheme)
+    }
+}
+
+enum NotifyTag {
+    Method(String),
+    From(String),
+    Importance(u8),
+    Options(Vec<String>),
+    Message(String),
+}
+
+fn parse_notify(input: &str) -> IResult<&str, Notify> {
+    let tag_parser = alt((
+        preceded(pair(tag(":method"), multispace1), parse_string).map(NotifyTag::Method),
+        preceded(pair(tag(":from"), multispace1), parse_string).map(NotifyTag::From),
+        preceded(
+            pair(tag(":importance"), multispace1),
+            map_res(parse_string, |importance| match importance.as_str() {
+                "1" => Ok(1),
+                "2" => Ok(2),
+                "3" => Ok(3),
+                _ => Err(()),
+            }),
+        )
+        .map(NotifyTag::Importance),
+        preceded(pair(tag(":options"), multispace1), parse_string_list).map(NotifyTag::Options),
+        preceded(pair(tag(":message"), multispace1), parse_string).map(NotifyTag::Message),
+    ));
+
+    let (rest, (tags, method)) = delimited(
+        tag("notify"),
+        pair(
+            many0(preceded(multispace1, tag_parser)),
+            opt(preceded(multispace1, parse_string)),
+        ),
+        preceded(multispace0, char(';')),
+    )
+    .parse(input)?;
+
+    let mut notify = Notify {
+        method: String::new(),
+        from: None,
+        importance: None,
+        options: Vec::new(),
+        message: None,
+    };
+    let mut tagged_method = None;
+    for tag in tags {
+        match tag {
+            NotifyTag::Method(method) => tagged_method = Some(method),
+            NotifyTag::From(from) => notify.from = Some(from),
+            NotifyTag::Importance(importance) => notify.importance = Some(importance),
+            NotifyTag::Options(options) => notify.options = options,
+            NotifyTag::Message(message) => notify.message = Some(message),
+        }
+    }
+
+    match method.or(tagged_method) {
+        Some(method) => notify.method = method,
+        None => {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Verify,
+            )));
+        }
+    }
+
+    Ok((rest, notify))
+}
+
+/// How long to wait before answering the same sender again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VacationInterval {
+    Days(u32),
+    /// `:seconds` (RFC 6131)
+    Seconds(u32),
+}
+
+/// `vacation [:days <n> | :seconds <n>] [:subject <text>] [:from <address>]
+/// [:addresses <strings>] [:mime] [:handle <id>] <reason>` (RFC 5230)
 #[derive(Debug, PartialEq)]
-enum Expression {
+pub struct Vacation {
+    /// `None` leaves the interval to the server
+    pub interval: Option<VacationInterval>,
+    pub subject: Option<String>,
+    pub from: Option<String>,
+    pub addresses: Vec<String>,
+    /// The reason is a MIME entity including headers instead of plain text
+    pub mime: bool,
+    pub handle: Option<String>,
+    pub reason: String,
+}
+
+enum VacationTag {
+    Interval(VacationInterval),
+    Subject(String),
+    From(String),
+    Addresses(Vec<String>),
+    Mime,
+    Handle(String),
+}
+
+fn parse_vacation(input: &str) -> IResult<&str, Vacation> {
+    let tag_parser = alt((
+        preceded(pair(tag(":days"), multispace1), map_res(digit1, str::parse))
+            .map(|days| VacationTag::Interval(VacationInterval::Days(days))),
+        preceded(
+            pair(tag(":seconds"), multispace1),
+            map_res(digit1, str::parse),
+        )
+        .map(|seconds| VacationTag::Interval(VacationInterval::Seconds(seconds))),
+        preceded(pair(tag(":subject"), multispace1), parse_string).map(VacationTag::Subject),
+        preceded(pair(tag(":from"), multispace1), parse_string).map(VacationTag::From),
+        preceded(pair(tag(":addresses"), multispace1), parse_string_list)
+            .map(VacationTag::Addresses),
+        tag(":mime").map(|_| VacationTag::Mime),
+        preceded(pair(tag(":handle"), multispace1), parse_string).map(VacationTag::Handle),
+    ));
+
+    let (rest, (tags, reason)) = delimited(
+        tag("vacation"),
+        pair(
+            many0(preceded(multispace1, tag_parser)),
+            preceded(multispace1, parse_string),
+        ),
+        preceded(multispace0, char(';')),
+    )
+    .parse(input)?;
+
+    let mut vacation = Vacation {
+        interval: None,
+        subject: None,
+        from: None,
+        addresses: Vec::new(),
+        mime: false,
+        handle: None,
+        reason,
+    };
+    for tag in tags {
+        match tag {
+            // `:days` and `:seconds` are mutually exclusive
+            VacationTag::Interval(_) if vacation.interval.is_some() => {
+                return Err(nom::Err::Error(nom::error::Error::new(
+                    input,
+                    nom::error::ErrorKind::Verify,
+                )));
+            }
+            VacationTag::Interval(interval) => vacation.interval = Some(interval),
+            VacationTag::Subject(subject) => vacation.subject = Some(subject),
+            VacationTag::From(from) => vacation.from = Some(from),
+            VacationTag::Addresses(addresses) => vacation.addresses = addresses,
+            VacationTag::Mime => vacation.mime = true,
+            VacationTag::Handle(handle) => vacation.handle = Some(handle),
+        }
+    }
+
+    Ok((rest, vacation))
+}
+
+fn parse_foreverypart(input: &str) -> IResult<&str, Vec<Expression>> {
+    preceded(
+        pair(tag("foreverypart"), multispace0),
+        delimited(
+            char('{'),
+            parse_expression_list,
+            preceded(multispace0, char('}')),
+        ),
+    )
+    .parse(input)
+}
+
+// `extracttext [:first <n>] <variable>` (RFC 5703)
+fn parse_extracttext(input: &str) -> IResult<&str, (Option<u32>, String)> {
+    delimited(
+        tag("extracttext"),
+        pair(
+            opt(preceded(
+                pair(multispace1, pair(tag(":first"), multispace1)),
+                map_res(digit1, str::parse),
+            )),
+            preceded(multispace1, parse_string),
+        ),
+        preceded(multispace0, char(';')),
+    )
+    .parse(input)
+}
+
+/// A `set` modifier that transforms the value before it is stored (RFC 5229
+/// section 4, `:encodeurl` is from RFC 5435)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetModifier {
+    Lower,
+    Upper,
+    LowerFirst,
+    UpperFirst,
+    QuoteWildcard,
+    EncodeUrl,
+    Length,
+}
+
+impl SetModifier {
+    /// Modifiers with a higher precedence are applied first, a `set` can't
+    /// combine two modifiers of the same precedence
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::Lower | Self::Upper => 40,
+            Self::LowerFirst | Self::UpperFirst => 30,
+            Self::QuoteWildcard => 20,
+            Self::EncodeUrl => 15,
+            Self::Length => 10,
+        }
+    }
+
+    /// The tag as written in a script, e.g. `:lower`
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::Lower => ":lower",
+            Self::Upper => ":upper",
+            Self::LowerFirst => ":lowerfirst",
+            Self::UpperFirst => ":upperfirst",
+            Self::QuoteWildcard => ":quotewildcard",
+            Self::EncodeUrl => ":encodeurl",
+            Self::Length => ":length",
+        }
+    }
+}
+
+/// `set [modifiers] <name> <value>` (RFC 5229)
+#[derive(Debug, PartialEq)]
+pub struct Set {
+    /// In the order they are written, see [`SetModifier::precedence`] for the
+    /// order they are applied in
+    pub modifiers: Vec<SetModifier>,
+    pub name: String,
+    pub value: String,
+}
+
+fn parse_set(input: &str) -> IResult<&str, Set> {
+    // The `first` variants go first so `:lower` doesn't match their prefix
+    let modifier = alt((
+        tag(":lowerfirst").map(|_| SetModifier::LowerFirst),
+        tag(":upperfirst").map(|_| SetModifier::UpperFirst),
+        tag(":lower").map(|_| SetModifier::Lower),
+        tag(":upper").map(|_| SetModifier::Upper),
+        tag(":quotewildcard").map(|_| SetModifier::QuoteWildcard),
+        tag(":encodeurl").map(|_| SetModifier::EncodeUrl),
+        tag(":length").map(|_| SetModifier::Length),
+    ));
+
+    let (rest, (modifiers, name, value)) = delimited(
+        tag("set"),
+        tuple((
+            many0(preceded(multispace1, modifier)),
+            preceded(multispace1, parse_string),
+            preceded(multispace1, parse_string),
+        )),
+        preceded(multispace0, char(';')),
+    )
+    .parse(input)?;
+
+    let clashing = modifiers.iter().enumerate().any(|(index, modifier)| {
+        modifiers[..index]
+            .iter()
+            .any(|earlier| earlier.precedence() == modifier.precedence())
+    });
+    if clashing {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+
+    Ok((
+        rest,
+        Set {
+            modifiers,
+            name,
+            value,
+        },
+    ))
+}
+
+/// Where an included script is looked up (RFC 6609)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IncludeLocation {
+    /// `:personal`, the user's own scripts
+    #[default]
+    Personal,
+    /// `:global`, scripts shared by all users of the server
+    Global,
+}
+
+enum IncludeTag {
+    Location(IncludeLocation),
+    Once,
+    Optional,
+}
+
+fn parse_include(input: &str) -> IResult<&str, Expression> {
+    let tag_parser = alt((
+        tag(":personal").map(|_| IncludeTag::Location(IncludeLocation::Personal)),
+        tag(":global").map(|_| IncludeTag::Location(IncludeLocation::Global)),
+        tag(":once").map(|_| IncludeTag::Once),
+        tag(":optional").map(|_| IncludeTag::Optional),
+    ));
+
+    let (rest, (tags, name)) = delimited(
+        tag("include"),
+        pair(
+            many0(preceded(multispace1, tag_parser)),
+            preceded(multispace1, parse_string),
+        ),
+        preceded(multispace0, char(';')),
+    )
+    .parse(input)?;
+
+    let mut location = IncludeLocation::default();
+    let mut once = false;
+    let mut optional = false;
+    for tag in tags {
+        match tag {
+            IncludeTag::Location(tagged) => location = tagged,
+            IncludeTag::Once => once = true,
+            IncludeTag::Optional => optional = true,
+        }
+    }
+
+    Ok((
+        rest,
+        Expression::Include {
+            location,
+            once,
+            optional,
+            name,
+        },
+    ))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Expression {
     Require(Vec<String>),
     If(If),
-    FileInto(String),
+    FileInto(FileInto),
+    Redirect(Redirect),
+    Vacation(Vacation),
     AddFlag(Vec<Flag>),
     RemoveFlag(Vec<Flag>),
     SetFlag(Vec<Flag>),
+    AddHeader(AddHeader),
+    DeleteHeader(DeleteHeader),
+    Notify(Notify),
+    /// `foreverypart`, runs the block once for every MIME part (RFC 5703)
+    ForEveryPart(Vec<Expression>),
+    /// `break`, leaves the innermost `foreverypart`
+    Break,
+    /// `set`, assigns a variable (RFC 5229)
+    Set(Set),
+    /// `extracttext`, stores the text of the current MIME part in a variable,
+    /// limited to the `first` characters if set
+    ExtractText {
+        first: Option<u32>,
+        variable: String,
+    },
+    /// `include`, runs another script stored on the server (RFC 6609)
+    Include {
+        location: IncludeLocation,
+        /// Skip the include if the script was already included before
+        once: bool,
+        /// Don't fail if the script doesn't exist
+        optional: bool,
+        name: String,
+    },
+    /// `return`, ends the current included script and continues in the one
+    /// that included it
+    Return,
     Discard,
-    Keep,
+    /// `keep`, with `:flags` (RFC 5232) the kept message gets these flags
+    /// instead of the ones from `addflag`/`removeflag`/`setflag`
+    Keep(Option<Vec<Flag>>),
     Stop,
 }
 
@@ -215,21 +938,119 @@ fn parse_expression(input: &str) -> IResult<&str, Expression> {
             flag_command("removeflag").map(Expression::RemoveFlag),
             flag_command("setflag").map(Expression::SetFlag),
             tag("discard;").map(|_| Expression::Discard),
-            tag("keep;").map(|_| Expression::Keep),
+            parse_keep.map(Expression::Keep),
             tag("stop;").map(|_| Expression::Stop),
-            delimited(
-                tag("fileinto"),
-                preceded(multispace1, parse_string),
-                char(';'),
-            )
-            .map(Expression::FileInto),
+            parse_fileinto.map(Expression::FileInto),
+            parse_redirect.map(Expression::Redirect),
+            parse_vacation.map(Expression::Vacation),
+            parse_addheader.map(Expression::AddHeader),
+            parse_deleteheader.map(Expression::DeleteHeader),
+            parse_notify.map(Expression::Notify),
+            parse_foreverypart.map(Expression::ForEveryPart),
+            tag("break;").map(|_| Expression::Break),
+            parse_include,
+            tag("return;").map(|_| Expression::Return),
+            parse_extracttext.map(|(first, variable)| Expression::ExtractText { first, variable }),
+            parse_set.map(Expression::Set),
         )),
     )
     .parse(input)
 }
 
+// A stray `;`, e.g. in `keep;;`, is an empty statement that doesn't do anything
+fn skip_empty_statements(input: &str) -> IResult<&str, ()> {
+    many0(preceded(multispace0, tag(";")))
+        .map(|_| ())
+        .parse(input)
+}
+
 fn parse_expression_list(input: &str) -> IResult<&str, Vec<Expression>> {
-    nom::multi::many0(parse_expression).parse(input)
+    let (rest, expressions) =
+        many0(preceded(skip_empty_statements, parse_expression)).parse(input)?;
+    let (rest, _) = skip_empty_statements(rest)?;
+    Ok((rest, expressions))
+}
+
+#[derive(Debug, PartialEq, Error)]
+#[error("Unable to parse script at line {line}, column {column}")]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ParseError {
+    // Locates `rest`, the unparsed remainder of `input`, in the original script
+    fn at(input: &str, rest: &str) -> Self {
+        let offset = input.len() - rest.trim_start().len();
+        let consumed = &input[..offset];
+        let line = consumed.matches('\n').count() + 1;
+        let column = consumed
+            .rfind('\n')
+            .map_or(consumed, |newline| &consumed[newline + 1..])
+            .chars()
+            .count()
+            + 1;
+
+        Self { line, column }
+    }
+}
+
+/// Parses a complete script, failing instead of silently dropping anything
+/// after the first statement that isn't understood.
+pub fn parse(input: &str) -> Result<Vec<Expression>, ParseError> {
+    let (rest, expressions) =
+        parse_expression_list(input).map_err(|_| ParseError::at(input, input))?;
+
+    if rest.trim().is_empty() {
+        Ok(expressions)
+    } else {
+        Err(ParseError::at(input, rest))
+    }
+}
+
+/// Like [`parse`], but also returns where each top level statement is in
+/// `input`, as byte ranges from its first to its last character.
+pub fn parse_with_spans(input: &str) -> Result<Vec<(Expression, Range<usize>)>, ParseError> {
+    let offset = |rest: &str| input.len() - rest.len();
+    let error = |rest: &str| ParseError::at(input, rest);
+
+    let mut statements = Vec::new();
+    let mut rest = input;
+    loop {
+        let (next, _) = skip_empty_statements(rest).map_err(|_| error(rest))?;
+        rest = next;
+        // Where `parse_expression` finds the statement after skipping whitespace
+        let start = offset(rest.trim_start_matches([' ', '\t', '\r', '\n']));
+        match parse_expression(rest) {
+            Ok((next, expression)) => {
+                statements.push((expression, start..offset(next)));
+                rest = next;
+            }
+            Err(_) => break,
+        }
+    }
+
+    if rest.trim().is_empty() {
+        Ok(statements)
+    } else {
+        Err(error(rest))
+    }
+}
+
+/// The extensions the script requires, merged from all of its `require`
+/// statements in the order they first appear, without duplicates.
+pub fn required_extensions(expressions: &[Expression]) -> Vec<String> {
+    let mut extensions: Vec<String> = Vec::new();
+    for expression in expressions {
+        if let Expression::Require(required) = expression {
+            for extension in required {
+                if !extensions.contains(extension) {
+                    extensions.push(extension.clone());
+                }
+            }
+        }
+    }
+    extensions
 }
 
 #[cfg(test)]
@@ -313,6 +1134,80 @@ mod test {
             parse_require(r#"require ["fileinto", "vacation"];"#),
             Ok(("", vec!["fileinto".to_string(), "vacation".to_string()]))
         );
+        assert_eq!(
+            parse_require(r#"require "fileinto";"#),
+            Ok(("", vec!["fileinto".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_empty_statements() {
+        let script = parse(
+            r#";
+            keep;;
+
+            ;
+            if header :contains "subject" "spam" { ; discard; ; };
+            stop; ;
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(script.len(), 3);
+        assert_eq!(script[0], Expression::Keep(None));
+        assert!(matches!(&script[1], Expression::If(_)));
+        assert_eq!(script[2], Expression::Stop);
+        assert!(parse("keep; ; fileinto;").is_err());
+    }
+
+    #[test]
+    fn test_keep_flags() {
+        let script = parse(
+            r#"require "imap4flags";
+            keep :flags "\\Seen";
+            keep :flags ["\\Flagged", "Muffin"];
+            keep :flags [];
+            keep;"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            script[1..],
+            [
+                Expression::Keep(Some(vec![Flag::Seen])),
+                Expression::Keep(Some(vec![
+                    Flag::Flagged,
+                    Flag::Custom("Muffin".to_string())
+                ])),
+                Expression::Keep(Some(vec![])),
+                Expression::Keep(None),
+            ]
+        );
+        assert!(parse("keep :flags;").is_err());
+    }
+
+    #[test]
+    fn test_multiple_requires() {
+        let script = parse(
+            r#"require ["fileinto", "imap4flags"];
+            require "vacation";
+            require ["fileinto"];
+            fileinto "Archive";"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            script[..3],
+            [
+                Expression::Require(vec!["fileinto".to_string(), "imap4flags".to_string()]),
+                Expression::Require(vec!["vacation".to_string()]),
+                Expression::Require(vec!["fileinto".to_string()]),
+            ]
+        );
+        assert_eq!(
+            required_extensions(&script),
+            vec!["fileinto", "imap4flags", "vacation"]
+        );
     }
 
     #[test]
@@ -373,6 +1268,353 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_fileinto() {
+        assert_eq!(
+            parse_fileinto(r#"fileinto "INBOX/New";"#),
+            Ok(("", FileInto::new("INBOX/New")))
+        );
+        for script in [
+            r#"fileinto :create :copy "INBOX/New";"#,
+            r#"fileinto :copy :create "INBOX/New";"#,
+        ] {
+            assert_eq!(
+                parse_fileinto(script),
+                Ok((
+                    "",
+                    FileInto {
+                        mailbox: "INBOX/New".to_string(),
+                        create: true,
+                        copy: true,
+                    }
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn test_redirect() {
+        assert_eq!(
+            parse_redirect(r#"redirect "boss@example.com";"#),
+            Ok((
+                "",
+                Redirect {
+                    address: "boss@example.com".to_string(),
+                    copy: false,
+                }
+            ))
+        );
+        assert_eq!(
+            parse_redirect(r#"redirect :copy "boss@example.com" ;"#),
+            Ok((
+                "",
+                Redirect {
+                    address: "boss@example.com".to_string(),
+                    copy: true,
+                }
+            ))
+        );
+        assert!(parse_redirect(r#"redirect;"#).is_err());
+    }
+
+    #[test]
+    fn test_editheader() {
+        assert_eq!(
+            parse_addheader(r#"addheader "X-Foo" "bar";"#),
+            Ok((
+                "",
+                AddHeader {
+                    last: false,
+                    name: "X-Foo".to_string(),
+                    value: "bar".to_string(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_deleteheader(r#"deleteheader :index 1 "X-Foo";"#),
+            Ok((
+                "",
+                DeleteHeader {
+                    index: Some(1),
+                    last: false,
+                    comparison_type: None,
+                    name: "X-Foo".to_string(),
+                    value_patterns: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse_deleteheader(
+                r#"deleteheader :index 2 :last :matches "Received" ["*spam*", "*bulk*"];"#
+            ),
+            Ok((
+                "",
+                DeleteHeader {
+                    index: Some(2),
+                    last: true,
+                    comparison_type: Some(StringComparisonType::Matches),
+                    name: "Received".to_string(),
+                    value_patterns: vec!["*spam*".to_string(), "*bulk*".to_string()],
+                }
+            ))
+        );
+        assert!(parse_deleteheader(r#"deleteheader :last "X-Foo";"#).is_err());
+    }
+
+    #[test]
+    fn test_vacation() {
+        assert_eq!(
+            parse_vacation(
+                r#"vacation :days 7 :subject "Out of office" :addresses ["me@example.com", "alias@example.com"] :handle "summer" "I'm away.";"#
+            ),
+            Ok((
+                "",
+                Vacation {
+                    interval: Some(VacationInterval::Days(7)),
+                    subject: Some("Out of office".to_string()),
+                    from: None,
+                    addresses: vec![
+                        "me@example.com".to_string(),
+                        "alias@example.com".to_string()
+                    ],
+                    mime: false,
+                    handle: Some("summer".to_string()),
+                    reason: "I'm away.".to_string(),
+                }
+            ))
+        );
+
+        let (_, vacation) = parse_vacation(r#"vacation :seconds 3600 :mime "Away";"#).unwrap();
+        assert_eq!(vacation.interval, Some(VacationInterval::Seconds(3600)));
+        assert!(vacation.mime);
+        assert_eq!(vacation.handle, None);
+
+        assert!(parse_vacation(r#"vacation :days 7 :seconds 3600 "Away";"#).is_err());
+        assert!(parse_vacation(r#"vacation :seconds 60 :days 1 "Away";"#).is_err());
+        assert!(parse(r#"vacation :days 7 :seconds 3600 "Away";"#).is_err());
+        assert!(parse_vacation(r#"vacation :days 7;"#).is_err());
+    }
+
+    #[test]
+    fn test_notify() {
+        let expected = Notify {
+            method: "mailto:alm@example.com".to_string(),
+            from: None,
+            importance: Some(1),
+            options: vec![],
+            message: Some("Urgent mail".to_string()),
+        };
+        assert_eq!(
+            parse_notify(
+                r#"notify :importance "1" :message "Urgent mail" "mailto:alm@example.com";"#
+            ),
+            Ok(("", expected))
+        );
+        let (_, notify) = parse_notify(
+            r#"notify :method "mailto:alm@example.com" :message "Urgent mail" :importance "1";"#,
+        )
+        .unwrap();
+        assert_eq!(notify.method, "mailto:alm@example.com");
+        assert_eq!(notify.scheme(), Some("mailto"));
+        assert_eq!(notify.importance, Some(1));
+
+        assert!(parse_notify(r#"notify :message "no method";"#).is_err());
+        assert!(parse_notify(r#"notify :importance "4" "mailto:alm@example.com";"#).is_err());
+    }
+
+    #[test]
+    fn test_date_condition() {
+        assert_eq!(
+            parse_condition(r#"currentdate :zone "+0100" :is "date" "2024-01-01""#),
+            Ok((
+                "",
+                Condition::CurrentDate(DateCondition {
+                    zone: Some(DateZone::Zone("+0100".to_string())),
+                    comparison_type: StringComparisonType::Is,
+                    date_part: DatePart::Date,
+                    keys: vec!["2024-01-01".to_string()],
+                })
+            ))
+        );
+        assert_eq!(
+            parse_condition(r#"date :originalzone :matches "received" "weekday" ["0", "6"]"#),
+            Ok((
+                "",
+                Condition::Date {
+                    header: "received".to_string(),
+                    condition: DateCondition {
+                        zone: Some(DateZone::Original),
+                        comparison_type: StringComparisonType::Matches,
+                        date_part: DatePart::Weekday,
+                        keys: vec!["0".to_string(), "6".to_string()],
+                    }
+                }
+            ))
+        );
+
+        for (part, date_part) in [
+            ("year", DatePart::Year),
+            ("month", DatePart::Month),
+            ("day", DatePart::Day),
+            ("weekday", DatePart::Weekday),
+            ("WEEKDAY", DatePart::Weekday),
+        ] {
+            let script = format!(r#"currentdate "{part}" "1""#);
+            let (rest, condition) = parse_condition(&script).unwrap();
+            assert_eq!(rest, "");
+            assert_eq!(
+                condition,
+                Condition::CurrentDate(DateCondition {
+                    zone: None,
+                    comparison_type: StringComparisonType::Is,
+                    date_part,
+                    keys: vec!["1".to_string()],
+                })
+            );
+        }
+
+        assert!(parse_condition(r#"currentdate "fortnight" "1""#).is_err());
+        assert!(parse_condition(r#"currentdate :originalzone "year" "2024""#).is_err());
+    }
+
+    #[test]
+    fn test_hasflag() {
+        assert_eq!(
+            parse_condition(r#"hasflag "\\Seen""#),
+            Ok(("", Condition::HasFlag(vec![Flag::Seen])))
+        );
+        assert_eq!(
+            parse_condition(r#"hasflag ["\\Flagged", "Muffin"]"#),
+            Ok((
+                "",
+                Condition::HasFlag(vec![Flag::Flagged, Flag::Custom("Muffin".to_string())])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_foreverypart() {
+        let script = parse(
+            r#"require ["mime", "foreverypart", "extracttext", "variables"];
+            foreverypart {
+                if header :contains "Content-Type" "text/plain" {
+                    extracttext :first 100 "preview";
+                    break;
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            script[1],
+            Expression::ForEveryPart(vec![Expression::If(If {
+                condition: Condition::Header(StringCondition {
+                    comparison_type: StringComparisonType::Contains,
+                    source: "Content-Type".to_string(),
+                    value: "text/plain".to_string(),
+                }),
+                expressions: vec![
+                    Expression::ExtractText {
+                        first: Some(100),
+                        variable: "preview".to_string(),
+                    },
+                    Expression::Break,
+                ],
+                else_ifs: vec![],
+                else_block: vec![],
+            })])
+        );
+        assert_eq!(
+            parse_expression(r#"extracttext "body";"#),
+            Ok((
+                "",
+                Expression::ExtractText {
+                    first: None,
+                    variable: "body".to_string(),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        assert_eq!(
+            parse_expression(r#"include "other";"#),
+            Ok((
+                "",
+                Expression::Include {
+                    location: IncludeLocation::Personal,
+                    once: false,
+                    optional: false,
+                    name: "other".to_string(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_expression(r#"include :once :global :optional "shared";"#),
+            Ok((
+                "",
+                Expression::Include {
+                    location: IncludeLocation::Global,
+                    once: true,
+                    optional: true,
+                    name: "shared".to_string(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_expression(r#"include :optional :personal "other";"#),
+            Ok((
+                "",
+                Expression::Include {
+                    location: IncludeLocation::Personal,
+                    once: false,
+                    optional: true,
+                    name: "other".to_string(),
+                }
+            ))
+        );
+        assert_eq!(parse_expression("return;"), Ok(("", Expression::Return)));
+    }
+
+    #[test]
+    fn test_set() {
+        assert_eq!(
+            parse_expression(r#"set "folder" "Lists";"#),
+            Ok((
+                "",
+                Expression::Set(Set {
+                    modifiers: vec![],
+                    name: "folder".to_string(),
+                    value: "Lists".to_string(),
+                })
+            ))
+        );
+        assert_eq!(
+            parse_expression(r#"set :lowerfirst :length :upper "name" "${1}";"#),
+            Ok((
+                "",
+                Expression::Set(Set {
+                    modifiers: vec![
+                        SetModifier::LowerFirst,
+                        SetModifier::Length,
+                        SetModifier::Upper
+                    ],
+                    name: "name".to_string(),
+                    value: "${1}".to_string(),
+                })
+            ))
+        );
+        // Modifiers of the same precedence contradict each other
+        assert!(parse_expression(r#"set :lower :upper "name" "value";"#).is_err());
+        assert!(parse_expression(r#"set :lowerfirst :upperfirst "name" "value";"#).is_err());
+        // Not to be confused with `setflag`
+        assert_eq!(
+            parse_expression(r#"setflag "\\Seen";"#),
+            Ok(("", Expression::SetFlag(vec![Flag::Seen])))
+        );
+    }
+
     #[test]
     fn test_if() {
         assert_eq!(
@@ -385,7 +1627,7 @@ mod test {
                         source: "Subject".to_string(),
                         value: "urgent".to_string()
                     }),
-                    expressions: vec![Expression::Keep],
+                    expressions: vec![Expression::Keep(None)],
                     else_ifs: vec![],
                     else_block: vec![],
                 }
@@ -417,7 +1659,10 @@ mod test {
                         source: "Subject".to_string(),
                         value: "urgent".to_string()
                     }),
-                    expressions: vec![Expression::FileInto("urgent".to_string()), Expression::Keep],
+                    expressions: vec![
+                        Expression::FileInto(FileInto::new("urgent")),
+                        Expression::Keep(None)
+                    ],
                     else_ifs: vec![],
                     else_block: vec![],
                 }
@@ -436,9 +1681,9 @@ mod test {
                         value: "urgent".to_string()
                     }),
                     expressions: vec![
-                        Expression::FileInto("urgent".to_string()),
+                        Expression::FileInto(FileInto::new("urgent")),
                         Expression::AddFlag(vec![Flag::Flagged]),
-                        Expression::Keep
+                        Expression::Keep(None)
                     ],
                     else_ifs: vec![
                         (
@@ -448,8 +1693,8 @@ mod test {
                                 value: "cookies".to_string()
                             }),
                             vec![
-                                Expression::FileInto("cookies".to_string()),
-                                Expression::Keep
+                                Expression::FileInto(FileInto::new("cookies")),
+                                Expression::Keep(None)
                             ]
                         ),
                         (
@@ -511,7 +1756,7 @@ mod test {
                             source: "Subject".to_string(),
                             value: "*urgent*".to_string()
                         }),
-                        expressions: vec![Expression::FileInto("Urgent".to_string())],
+                        expressions: vec![Expression::FileInto(FileInto::new("Urgent"))],
                         else_ifs: vec![
                             (
                                 Condition::Header(StringCondition {
@@ -519,7 +1764,7 @@ mod test {
                                     source: "Subject".to_string(),
                                     value: "\\[TICKET-[0-9]{4}\\]".to_string()
                                 }),
-                                vec![Expression::FileInto("Tickets".to_string())]
+                                vec![Expression::FileInto(FileInto::new("Tickets"))]
                             ),
                             (
                                 Condition::Header(StringCondition {
@@ -568,7 +1813,7 @@ mod test {
                         })]),
                         expressions: vec![
                             Expression::AddFlag(vec![Flag::Seen]),
-                            Expression::FileInto("INBOX/Proxmox Backup".to_string())
+                            Expression::FileInto(FileInto::new("INBOX/Proxmox Backup"))
                         ],
                         else_ifs: vec![],
                         else_block: vec![]
@@ -581,7 +1826,7 @@ mod test {
                         })]),
                         expressions: vec![
                             Expression::AddFlag(vec![Flag::Seen]),
-                            Expression::FileInto("INBOX/Teamviewer".to_string())
+                            Expression::FileInto(FileInto::new("INBOX/Teamviewer"))
                         ],
                         else_ifs: vec![],
                         else_block: vec![]
@@ -590,7 +1835,7 @@ mod test {
             ))
         );
 
-        assert_eq!(parse_expression_list(r#"
+        let script = r#"
             require ["imap4flags","fileinto","body"];
 
             if allof (header :contains "subject" "ALTERNATE - Rechnung") {
@@ -815,6 +2060,33 @@ mod test {
 
                 fileinto "INBOX/Werbung/Softwarebilliger";
 
-            }"#).unwrap().0.len(), 0);
+            }"#;
+        assert_eq!(parse_expression_list(script).unwrap().0.len(), 0);
+
+        // Every rule has to make it into the result, not just be consumed
+        let expressions = parse(script).unwrap();
+        assert_eq!(expressions.len(), 28);
+        assert_eq!(
+            expressions[0],
+            Expression::Require(vec![
+                "imap4flags".to_string(),
+                "fileinto".to_string(),
+                "body".to_string()
+            ])
+        );
+        assert!(
+            expressions[1..]
+                .iter()
+                .all(|expression| matches!(expression, Expression::If(_)))
+        );
+    }
+
+    #[test]
+    fn test_parse_reports_unparsed_input() {
+        assert_eq!(
+            parse("keep;\n  unknown \"command\";\nstop;"),
+            Err(ParseError { line: 2, column: 3 })
+        );
+        assert_eq!(parse("keep;\n\n"), Ok(vec![Expression::Keep(None)]));
     }
 }
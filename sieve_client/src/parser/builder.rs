@@ -0,0 +1,220 @@
+use super::{
+    Condition, Expression, If, Node, SetModifier, StringComparisonType, VacationInterval, walk,
+};
+
+/// Puts a script together from code, e.g.
+///
+/// ```
+/// use sieve_client::parser::{
+///     Condition, Expression, FileInto, ScriptBuilder, StringComparisonType, StringCondition,
+///     serialize,
+/// };
+///
+/// let script = ScriptBuilder::new()
+///     .rule(
+///         Condition::Header(StringCondition {
+///             comparison_type: StringComparisonType::Contains,
+///             source: "subject".to_string(),
+///             value: "invoice".to_string(),
+///         }),
+///         [Expression::FileInto(FileInto::new("Invoices"))],
+///     )
+///     .build();
+///
+/// assert!(serialize(&script).starts_with("require [\"fileinto\"];\n"));
+/// ```
+///
+/// [`build`](Self::build) adds the extensions the script uses to its
+/// `require`, so only extensions the commands don't imply need
+/// [`require`](Self::require).
+#[derive(Debug, Default)]
+pub struct ScriptBuilder {
+    extensions: Vec<String>,
+    expressions: Vec<Expression>,
+}
+
+impl ScriptBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires extensions in addition to the ones the script uses
+    pub fn require<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for extension in extensions {
+            let extension = extension.into();
+            if !self.extensions.contains(&extension) {
+                self.extensions.push(extension);
+            }
+        }
+        self
+    }
+
+    /// Adds `if <condition> { <actions> }`
+    pub fn rule(self, condition: Condition, actions: impl IntoIterator<Item = Expression>) -> Self {
+        self.action(Expression::If(If {
+            condition,
+            expressions: actions.into_iter().collect(),
+            else_ifs: Vec::new(),
+            else_block: Vec::new(),
+        }))
+    }
+
+    /// Adds a command at the top level, after everything added so far
+    pub fn action(mut self, expression: Expression) -> Self {
+        self.expressions.push(expression);
+        self
+    }
+
+    /// The script, starting with a single `require` for every extension that
+    /// was required or is used
+    pub fn build(self) -> Vec<Expression> {
+        let mut extensions = self.extensions;
+        for extension in used_extensions(&self.expressions) {
+            if !extensions.iter().any(|required| required == extension) {
+                extensions.push(extension.to_string());
+            }
+        }
+
+        let mut script = Vec::with_capacity(self.expressions.len() + 1);
+        if !extensions.is_empty() {
+            script.push(Expression::Require(extensions));
+        }
+        script.extend(self.expressions);
+        script
+    }
+}
+
+/// The extensions the commands and tests of a script need, in the order they
+/// are first used, without duplicates. Unlike
+/// [`required_extensions`](super::required_extensions) this doesn't look at the
+/// `require` statements.
+pub fn used_extensions(expressions: &[Expression]) -> Vec<&'static str> {
+    let mut extensions = Vec::new();
+    let mut use_extension = |extension: &'static str| {
+        if !extensions.contains(&extension) {
+            extensions.push(extension);
+        }
+    };
+
+    walk(expressions, &mut |node| match node {
+        Node::Expression(expression) => match expression {
+            Expression::FileInto(fileinto) => {
+                use_extension("fileinto");
+                if fileinto.create {
+                    use_extension("mailbox");
+                }
+                if fileinto.copy {
+                    use_extension("copy");
+                }
+            }
+            Expression::Redirect(redirect) if redirect.copy => use_extension("copy"),
+            Expression::Vacation(vacation) => {
+                use_extension("vacation");
+                if matches!(vacation.interval, Some(VacationInterval::Seconds(_))) {
+                    use_extension("vacation-seconds");
+                }
+            }
+            Expression::AddFlag(_)
+            | Expression::RemoveFlag(_)
+            | Expression::SetFlag(_)
+            | Expression::Keep(Some(_)) => use_extension("imap4flags"),
+            Expression::AddHeader(_) | Expression::DeleteHeader(_) => use_extension("editheader"),
+            Expression::Notify(_) => use_extension("enotify"),
+            Expression::ForEveryPart(_) | Expression::Break => use_extension("foreverypart"),
+            Expression::Include { .. } | Expression::Return => use_extension("include"),
+            Expression::Set(set) => {
+                use_extension("variables");
+                if set.modifiers.contains(&SetModifier::EncodeUrl) {
+                    use_extension("enotify");
+                }
+            }
+            Expression::ExtractText { .. } => {
+                use_extension("extracttext");
+                use_extension("variables");
+            }
+            _ => {}
+        },
+        Node::Condition(condition) => match condition {
+            Condition::Header(condition) | Condition::Address(condition)
+                if condition.comparison_type == StringComparisonType::Regex =>
+            {
+                use_extension("regex")
+            }
+            Condition::Date { condition, .. } | Condition::CurrentDate(condition) => {
+                use_extension("date");
+                if condition.comparison_type == StringComparisonType::Regex {
+                    use_extension("regex");
+                }
+            }
+            Condition::HasFlag(_) => use_extension("imap4flags"),
+            _ => {}
+        },
+    });
+
+    extensions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{FileInto, Flag, StringCondition, parse, serialize};
+
+    fn subject_contains(value: &str) -> Condition {
+        Condition::Header(StringCondition {
+            comparison_type: StringComparisonType::Contains,
+            source: "subject".to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_builder_adds_requires() {
+        let script = ScriptBuilder::new()
+            .require(["fileinto", "variables"])
+            .rule(
+                subject_contains("invoice"),
+                [
+                    Expression::FileInto(FileInto {
+                        create: true,
+                        ..FileInto::new("Invoices")
+                    }),
+                    Expression::AddFlag(vec![Flag::Seen]),
+                    Expression::Stop,
+                ],
+            )
+            .rule(
+                Condition::HasFlag(vec![Flag::Flagged]),
+                [Expression::Keep(None)],
+            )
+            .action(Expression::Discard)
+            .build();
+
+        let source = r#"require ["fileinto", "variables", "mailbox", "imap4flags"];
+if header :contains "subject" "invoice" {
+    fileinto :create "Invoices";
+    addflag "\\Seen";
+    stop;
+}
+if hasflag "\\Flagged" {
+    keep;
+}
+discard;
+"#;
+        assert_eq!(serialize(&script), source);
+        assert_eq!(parse(source), Ok(script));
+    }
+
+    #[test]
+    fn test_builder_without_extensions() {
+        let script = ScriptBuilder::new()
+            .rule(subject_contains("spam"), [Expression::Discard])
+            .build();
+
+        assert!(matches!(script[..], [Expression::If(_)]));
+        assert!(ScriptBuilder::new().build().is_empty());
+    }
+}
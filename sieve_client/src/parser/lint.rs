@@ -0,0 +1,285 @@
+use std::{collections::BTreeSet, fmt};
+
+use super::{Expression, If, Node, walk};
+
+/// A likely mistake found by [`lint`]. Locations are indices into the top level
+/// statements of the script, which is where rules live.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    /// The statement can never run because the statement at `stop` always
+    /// ends the script, with `stop` or `return`.
+    Unreachable { index: usize, stop: usize },
+    /// Both rules test the same condition but file into different folders, so the
+    /// second one is probably a leftover.
+    ConflictingRules { first: usize, second: usize },
+    /// A message may be redirected `count` times, more than the `max` the
+    /// server allows (its `MAXREDIRECTS` capability).
+    TooManyRedirects { count: usize, max: u32 },
+}
+
+// Statements are counted from one, as a user would count the rules
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Unreachable { index, stop } => write!(
+                f,
+                "Statement {} never runs, statement {} always ends the script",
+                index + 1,
+                stop + 1
+            ),
+            Finding::ConflictingRules { first, second } => write!(
+                f,
+                "Rules {} and {} test the same condition but file into different folders",
+                first + 1,
+                second + 1
+            ),
+            Finding::TooManyRedirects { count, max } => write!(
+                f,
+                "A message may be redirected {} times, the server allows {}",
+                count, max
+            ),
+        }
+    }
+}
+
+/// Looks for rules that can never match or contradict an earlier rule.
+pub fn lint(expressions: &[Expression]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if let Some(stop) = expressions.iter().position(always_ends) {
+        findings.extend(
+            (stop + 1..expressions.len()).map(|index| Finding::Unreachable { index, stop }),
+        );
+    }
+
+    let rules: Vec<(usize, &If)> = expressions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, expression)| match expression {
+            Expression::If(if_) => Some((index, if_)),
+            _ => None,
+        })
+        .collect();
+
+    for (position, (first, first_rule)) in rules.iter().enumerate() {
+        for (second, second_rule) in &rules[position + 1..] {
+            if first_rule.condition == second_rule.condition {
+                let first_folders = folders(&first_rule.expressions);
+                let second_folders = folders(&second_rule.expressions);
+
+                if !first_folders.is_empty()
+                    && !second_folders.is_empty()
+                    && first_folders != second_folders
+                {
+                    findings.push(Finding::ConflictingRules {
+                        first: *first,
+                        second: *second,
+                    });
+                }
+            }
+        }
+    }
+
+    findings
+}
+
+/// Checks the script against the server's `MAXREDIRECTS` limit, see
+/// [`max_redirects`].
+pub fn lint_redirects(expressions: &[Expression], max: u32) -> Option<Finding> {
+    let count = max_redirects(expressions);
+    (count > max as usize).then_some(Finding::TooManyRedirects { count, max })
+}
+
+/// The most `redirect` actions a single message can run into on its way through
+/// the script. Of the branches of an `if` only the one with the most redirects
+/// counts, and nothing after a statement that always ends the script.
+///
+/// A `foreverypart` block is counted once although it runs for every MIME part,
+/// and included scripts aren't known here, so this can be too low for those.
+pub fn max_redirects(expressions: &[Expression]) -> usize {
+    let mut count = 0;
+    for expression in expressions {
+        count += match expression {
+            Expression::Redirect(_) => 1,
+            Expression::If(if_) => std::iter::once(&if_.expressions)
+                .chain(if_.else_ifs.iter().map(|(_, expressions)| expressions))
+                .chain(std::iter::once(&if_.else_block))
+                .map(|expressions| max_redirects(expressions))
+                .max()
+                .unwrap_or(0),
+            Expression::ForEveryPart(expressions) => max_redirects(expressions),
+            _ => 0,
+        };
+        if always_ends(expression) {
+            break;
+        }
+    }
+    count
+}
+
+// Whether processing of this script never continues past this statement,
+// either because it is a `stop` or `return` or because every branch of an `if`
+// with an `else` ends the script. `stop` also ends any script that included
+// this one while `return` goes back to it, but for the rest of this script
+// both are the same. What an `include` does can't be known from here.
+fn always_ends(expression: &Expression) -> bool {
+    match expression {
+        Expression::Stop | Expression::Return => true,
+        Expression::If(if_) => {
+            if_.expressions.iter().any(always_ends)
+                && if_
+                    .else_ifs
+                    .iter()
+                    .all(|(_, expressions)| expressions.iter().any(always_ends))
+                && if_.else_block.iter().any(always_ends)
+        }
+        _ => false,
+    }
+}
+
+fn folders(expressions: &[Expression]) -> BTreeSet<&str> {
+    let mut folders = BTreeSet::new();
+    walk(expressions, &mut |node| {
+        if let Node::Expression(Expression::FileInto(fileinto)) = node {
+            folders.insert(fileinto.mailbox.as_str());
+        }
+    });
+    folders
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_unreachable_after_stop() {
+        let script = parse(
+            r#"
+            if header :contains "subject" "spam" { discard; }
+            stop;
+            if header :contains "subject" "invoice" { fileinto "Invoices"; }
+            keep;
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            lint(&script),
+            vec![
+                Finding::Unreachable { index: 2, stop: 1 },
+                Finding::Unreachable { index: 3, stop: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_after_if_stopping_in_every_branch() {
+        let script = parse(
+            r#"
+            if header :contains "subject" "spam" { discard; stop; } else { keep; stop; }
+            fileinto "Never";
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            lint(&script),
+            vec![Finding::Unreachable { index: 1, stop: 0 }]
+        );
+
+        // Without an else processing may continue
+        let script = parse(
+            r#"
+            if header :contains "subject" "spam" { discard; stop; }
+            fileinto "Sometimes";
+            "#,
+        )
+        .unwrap();
+        assert_eq!(lint(&script), vec![]);
+    }
+
+    #[test]
+    fn test_unreachable_after_return() {
+        let script = parse(
+            r#"
+            require ["include"];
+            if header :contains "subject" "spam" { discard; return; } else { return; }
+            fileinto "Never";
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            lint(&script),
+            vec![Finding::Unreachable { index: 2, stop: 1 }]
+        );
+
+        // The included script might stop, but that isn't known here
+        let script = parse(
+            r#"
+            require ["include"];
+            include "common";
+            fileinto "Maybe";
+            "#,
+        )
+        .unwrap();
+        assert_eq!(lint(&script), vec![]);
+    }
+
+    #[test]
+    fn test_conflicting_rules() {
+        let script = parse(
+            r#"
+            if header :contains "subject" "invoice" { fileinto "Invoices"; }
+            if address :contains "from" "shop@example.com" { fileinto "Shop"; }
+            if header :contains "subject" "invoice" { addflag "\\Seen"; fileinto "Belege"; }
+            if header :contains "subject" "invoice" { fileinto "Invoices"; }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            lint(&script),
+            vec![
+                Finding::ConflictingRules {
+                    first: 0,
+                    second: 2
+                },
+                Finding::ConflictingRules {
+                    first: 2,
+                    second: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_redirects() {
+        let script = parse(
+            r#"
+            redirect :copy "archive@example.com";
+            if header :contains "subject" "invoice" {
+                redirect "accounting@example.com";
+                redirect "boss@example.com";
+            } elsif header :contains "subject" "alert" {
+                redirect "oncall@example.com";
+            }
+            if header :contains "subject" "spam" { discard; stop; } else { stop; }
+            redirect "never@example.com";
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(max_redirects(&script), 3);
+        assert_eq!(lint_redirects(&script, 3), None);
+        let finding = lint_redirects(&script, 2);
+        assert_eq!(
+            finding,
+            Some(Finding::TooManyRedirects { count: 3, max: 2 })
+        );
+        assert_eq!(
+            finding.unwrap().to_string(),
+            "A message may be redirected 3 times, the server allows 2"
+        );
+        assert_eq!(max_redirects(&parse("keep;").unwrap()), 0);
+    }
+}
@@ -0,0 +1,690 @@
+use super::{
+    AddHeader, Condition, DateCondition, DateZone, DeleteHeader, Expression, FileInto, Flag,
+    IncludeLocation, Notify, Redirect, Set, StringComparisonType, StringCondition, Vacation,
+    VacationInterval, parse_with_spans, required_extensions,
+};
+
+const INDENT: &str = "    ";
+
+/// Renders `expressions` back into Sieve source that [`parse`](super::parse)
+/// reads into the same tree. Every statement gets its own line and nested
+/// blocks are indented by four spaces.
+pub fn serialize(expressions: &[Expression]) -> String {
+    let mut out = String::new();
+    write_block(&mut out, expressions, 0);
+    out
+}
+
+/// Like [`serialize`], but merges all `require` statements into a single one
+/// at the top of the script, see [`required_extensions`].
+pub fn serialize_coalesced(expressions: &[Expression]) -> String {
+    let mut out = String::new();
+
+    let extensions = required_extensions(expressions);
+    if !extensions.is_empty() {
+        write_expression(&mut out, &Expression::Require(extensions), 0);
+    }
+    for expression in expressions {
+        if !matches!(expression, Expression::Require(_)) {
+            write_expression(&mut out, expression, 0);
+        }
+    }
+
+    out
+}
+
+/// Like [`serialize`], but copies the top level statements of `source` that
+/// are still in `expressions` as they were written, with the lines around
+/// them. Only new or changed statements are rendered, so editing one rule
+/// doesn't reformat the rest of the script. If `source` doesn't parse, this is
+/// just [`serialize`].
+pub fn serialize_preserving(source: &str, expressions: &[Expression]) -> String {
+    let Ok(statements) = parse_with_spans(source) else {
+        return serialize(expressions);
+    };
+
+    // The text of each statement reaches from the end of the previous one
+    // through the end of its own line, so together they cover the source
+    let mut texts = Vec::with_capacity(statements.len());
+    let mut start = 0;
+    for (_, span) in &statements {
+        let after = &source[span.end..];
+        let gap = &after[..after.len() - after.trim_start().len()];
+        let end = span.end + gap.find('\n').map_or(0, |newline| newline + 1);
+        texts.push(start..end);
+        start = end;
+    }
+    let trailing = &source[start..];
+
+    let mut out = String::new();
+    // The first statement of `source` not copied or replaced yet
+    let mut next = 0;
+    for (index, expression) in expressions.iter().enumerate() {
+        if let Some(found) = statements[next..]
+            .iter()
+            .position(|(original, _)| original == expression)
+        {
+            next += found;
+            out.push_str(&source[texts[next].clone()]);
+            next += 1;
+            continue;
+        }
+
+        // A changed statement keeps the blank lines in front of the one it
+        // replaces, unless that one only moved further down
+        if let Some((original, span)) = statements.get(next)
+            && !expressions[index + 1..].contains(original)
+        {
+            let leading = &source[texts[next].start..span.start];
+            out.push_str(&leading[..leading.rfind('\n').map_or(0, |newline| newline + 1)]);
+            next += 1;
+        }
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        write_expression(&mut out, expression, 0);
+    }
+    out.push_str(trailing);
+
+    out
+}
+
+fn write_block(out: &mut String, expressions: &[Expression], depth: usize) {
+    for expression in expressions {
+        write_expression(out, expression, depth);
+    }
+}
+
+fn write_braced_block(out: &mut String, expressions: &[Expression], depth: usize) {
+    out.push_str("{\n");
+    write_block(out, expressions, depth + 1);
+    out.push_str(&INDENT.repeat(depth));
+    out.push('}');
+}
+
+fn write_expression(out: &mut String, expression: &Expression, depth: usize) {
+    out.push_str(&INDENT.repeat(depth));
+
+    match expression {
+        Expression::Require(capabilities) => {
+            out.push_str("require ");
+            write_string_array(out, capabilities);
+            out.push(';');
+        }
+        Expression::If(if_) => {
+            out.push_str("if ");
+            write_condition(out, &if_.condition);
+            out.push(' ');
+            write_braced_block(out, &if_.expressions, depth);
+
+            for (condition, expressions) in &if_.else_ifs {
+                out.push_str(" elsif ");
+                write_condition(out, condition);
+                out.push(' ');
+                write_braced_block(out, expressions, depth);
+            }
+
+            if !if_.else_block.is_empty() {
+                out.push_str(" else ");
+                write_braced_block(out, &if_.else_block, depth);
+            }
+        }
+        Expression::FileInto(fileinto) => write_fileinto(out, fileinto),
+        Expression::Redirect(redirect) => write_redirect(out, redirect),
+        Expression::Vacation(vacation) => write_vacation(out, vacation),
+        Expression::AddFlag(flags) => write_flag_command(out, "addflag", flags),
+        Expression::RemoveFlag(flags) => write_flag_command(out, "removeflag", flags),
+        Expression::SetFlag(flags) => write_flag_command(out, "setflag", flags),
+        Expression::AddHeader(addheader) => write_addheader(out, addheader),
+        Expression::DeleteHeader(deleteheader) => write_deleteheader(out, deleteheader),
+        Expression::Notify(notify) => write_notify(out, notify),
+        Expression::ForEveryPart(expressions) => {
+            out.push_str("foreverypart ");
+            write_braced_block(out, expressions, depth);
+        }
+        Expression::Break => out.push_str("break;"),
+        Expression::Include {
+            location,
+            once,
+            optional,
+            name,
+        } => {
+            out.push_str("include");
+            if *location == IncludeLocation::Global {
+                out.push_str(" :global");
+            }
+            if *once {
+                out.push_str(" :once");
+            }
+            if *optional {
+                out.push_str(" :optional");
+            }
+            out.push(' ');
+            write_string(out, name);
+            out.push(';');
+        }
+        Expression::Return => out.push_str("return;"),
+        Expression::Set(set) => write_set(out, set),
+        Expression::ExtractText { first, variable } => {
+            out.push_str("extracttext");
+            if let Some(first) = first {
+                out.push_str(&format!(" :first {first}"));
+            }
+            out.push(' ');
+            write_string(out, variable);
+            out.push(';');
+        }
+        Expression::Discard => out.push_str("discard;"),
+        Expression::Keep(None) => out.push_str("keep;"),
+        Expression::Keep(Some(flags)) => {
+            out.push_str("keep :flags ");
+            write_flags(out, flags);
+            out.push(';');
+        }
+        Expression::Stop => out.push_str("stop;"),
+    }
+
+    out.push('\n');
+}
+
+fn write_fileinto(out: &mut String, fileinto: &FileInto) {
+    out.push_str("fileinto");
+    if fileinto.copy {
+        out.push_str(" :copy");
+    }
+    if fileinto.create {
+        out.push_str(" :create");
+    }
+    out.push(' ');
+    write_string(out, &fileinto.mailbox);
+    out.push(';');
+}
+
+fn write_redirect(out: &mut String, redirect: &Redirect) {
+    out.push_str("redirect");
+    if redirect.copy {
+        out.push_str(" :copy");
+    }
+    out.push(' ');
+    write_string(out, &redirect.address);
+    out.push(';');
+}
+
+fn write_vacation(out: &mut String, vacation: &Vacation) {
+    out.push_str("vacation");
+    match vacation.interval {
+        Some(VacationInterval::Days(days)) => out.push_str(&format!(" :days {days}")),
+        Some(VacationInterval::Seconds(seconds)) => out.push_str(&format!(" :seconds {seconds}")),
+        None => {}
+    }
+    if let Some(subject) = &vacation.subject {
+        out.push_str(" :subject ");
+        write_string(out, subject);
+    }
+    if let Some(from) = &vacation.from {
+        out.push_str(" :from ");
+        write_string(out, from);
+    }
+    if !vacation.addresses.is_empty() {
+        out.push_str(" :addresses ");
+        write_string_list(out, &vacation.addresses);
+    }
+    if vacation.mime {
+        out.push_str(" :mime");
+    }
+    if let Some(handle) = &vacation.handle {
+        out.push_str(" :handle ");
+        write_string(out, handle);
+    }
+    out.push(' ');
+    write_string(out, &vacation.reason);
+    out.push(';');
+}
+
+fn write_set(out: &mut String, set: &Set) {
+    out.push_str("set");
+    for modifier in &set.modifiers {
+        out.push(' ');
+        out.push_str(modifier.tag());
+    }
+    out.push(' ');
+    write_string(out, &set.name);
+    out.push(' ');
+    write_string(out, &set.value);
+    out.push(';');
+}
+
+fn write_flag_command(out: &mut String, command: &str, flags: &[Flag]) {
+    out.push_str(command);
+    out.push(' ');
+    write_flags(out, flags);
+    out.push(';');
+}
+
+fn write_flags(out: &mut String, flags: &[Flag]) {
+    let flags: Vec<String> = flags.iter().map(flag_name).collect();
+    write_string_list(out, &flags);
+}
+
+fn write_addheader(out: &mut String, addheader: &AddHeader) {
+    out.push_str("addheader");
+    if addheader.last {
+        out.push_str(" :last");
+    }
+    out.push(' ');
+    write_string(out, &addheader.name);
+    out.push(' ');
+    write_string(out, &addheader.value);
+    out.push(';');
+}
+
+fn write_deleteheader(out: &mut String, deleteheader: &DeleteHeader) {
+    out.push_str("deleteheader");
+    if let Some(index) = deleteheader.index {
+        out.push_str(&format!(" :index {index}"));
+        if deleteheader.last {
+            out.push_str(" :last");
+        }
+    }
+    if let Some(comparison_type) = &deleteheader.comparison_type {
+        out.push(' ');
+        out.push_str(comparison_tag(comparison_type));
+    }
+    out.push(' ');
+    write_string(out, &deleteheader.name);
+    if !deleteheader.value_patterns.is_empty() {
+        out.push(' ');
+        write_string_list(out, &deleteheader.value_patterns);
+    }
+    out.push(';');
+}
+
+fn write_notify(out: &mut String, notify: &Notify) {
+    out.push_str("notify");
+    if let Some(from) = &notify.from {
+        out.push_str(" :from ");
+        write_string(out, from);
+    }
+    if let Some(importance) = notify.importance {
+        out.push_str(&format!(" :importance \"{importance}\""));
+    }
+    if !notify.options.is_empty() {
+        out.push_str(" :options ");
+        write_string_list(out, &notify.options);
+    }
+    if let Some(message) = &notify.message {
+        out.push_str(" :message ");
+        write_string(out, message);
+    }
+    out.push(' ');
+    write_string(out, &notify.method);
+    out.push(';');
+}
+
+fn flag_name(flag: &Flag) -> String {
+    match flag {
+        Flag::Seen => "\\Seen".to_string(),
+        Flag::Flagged => "\\Flagged".to_string(),
+        Flag::Answered => "\\Answered".to_string(),
+        Flag::Deleted => "\\Deleted".to_string(),
+        Flag::Draft => "\\Draft".to_string(),
+        Flag::Recent => "\\Recent".to_string(),
+        Flag::Custom(flag) => flag.clone(),
+    }
+}
+
+fn write_condition(out: &mut String, condition: &Condition) {
+    match condition {
+        Condition::Header(condition) => write_string_condition(out, "header", condition),
+        Condition::Address(condition) => write_string_condition(out, "address", condition),
+        Condition::AllOf(conditions) => write_condition_list(out, "allof", conditions),
+        Condition::AnyOf(conditions) => write_condition_list(out, "anyof", conditions),
+        Condition::Date { header, condition } => {
+            out.push_str("date");
+            write_date_condition(out, Some(header), condition);
+        }
+        Condition::CurrentDate(condition) => {
+            out.push_str("currentdate");
+            write_date_condition(out, None, condition);
+        }
+        Condition::HasFlag(flags) => {
+            out.push_str("hasflag ");
+            write_flags(out, flags);
+        }
+    }
+}
+
+fn write_date_condition(out: &mut String, header: Option<&str>, condition: &DateCondition) {
+    match &condition.zone {
+        Some(DateZone::Zone(zone)) => {
+            out.push_str(" :zone ");
+            write_string(out, zone);
+        }
+        Some(DateZone::Original) => out.push_str(" :originalzone"),
+        None => {}
+    }
+    out.push(' ');
+    out.push_str(comparison_tag(&condition.comparison_type));
+    if let Some(header) = header {
+        out.push(' ');
+        write_string(out, header);
+    }
+    out.push(' ');
+    write_string(out, condition.date_part.as_str());
+    out.push(' ');
+    write_string_list(out, &condition.keys);
+}
+
+fn write_string_condition(out: &mut String, test: &str, condition: &StringCondition) {
+    out.push_str(test);
+    out.push(' ');
+    out.push_str(comparison_tag(&condition.comparison_type));
+    out.push(' ');
+    write_string(out, &condition.source);
+    out.push(' ');
+    write_string(out, &condition.value);
+}
+
+fn comparison_tag(comparison_type: &StringComparisonType) -> &'static str {
+    match comparison_type {
+        StringComparisonType::Is => ":is",
+        StringComparisonType::Contains => ":contains",
+        StringComparisonType::Matches => ":matches",
+        StringComparisonType::Regex => ":regex",
+    }
+}
+
+fn write_condition_list(out: &mut String, test: &str, conditions: &[Condition]) {
+    out.push_str(test);
+    out.push_str(" (");
+    for (index, condition) in conditions.iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        write_condition(out, condition);
+    }
+    out.push(')');
+}
+
+// A single string is written bare, anything else as an array
+fn write_string_list(out: &mut String, strings: &[String]) {
+    match strings {
+        [string] => write_string(out, string),
+        strings => write_string_array(out, strings),
+    }
+}
+
+fn write_string_array(out: &mut String, strings: &[String]) {
+    out.push('[');
+    for (index, string) in strings.iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        write_string(out, string);
+    }
+    out.push(']');
+}
+
+fn write_string(out: &mut String, string: &str) {
+    out.push('"');
+    for char in string.chars() {
+        if matches!(char, '\\' | '"') {
+            out.push('\\');
+        }
+        out.push(char);
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{ParseError, parse};
+
+    #[test]
+    fn test_serialize() {
+        let script = parse(
+            r#"require ["fileinto", "imap4flags", "mailbox"];
+            if allof (header :contains "Subject" "urgent", address :is "from" "boss@example.com") {
+                addflag "\\Flagged";
+                fileinto :create "INBOX/Urgent";
+            } elsif header :matches "Subject" "*\"quoted\"*" {
+                setflag ["\\Seen", "Muffin"];
+                fileinto :copy :create "Quotes";
+            } else {
+                redirect :copy "archive@example.com";
+                keep;
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serialize(&script),
+            r#"require ["fileinto", "imap4flags", "mailbox"];
+if allof (header :contains "Subject" "urgent", address :is "from" "boss@example.com") {
+    addflag "\\Flagged";
+    fileinto :create "INBOX/Urgent";
+} elsif header :matches "Subject" "*\"quoted\"*" {
+    setflag ["\\Seen", "Muffin"];
+    fileinto :copy :create "Quotes";
+} else {
+    redirect :copy "archive@example.com";
+    keep;
+}
+"#
+        );
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+    }
+
+    #[test]
+    fn test_serialize_coalesced() {
+        let script = parse(
+            r#"require "fileinto";
+            fileinto "Archive";
+            require ["imap4flags", "fileinto"];
+            addflag "\\Seen";"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serialize_coalesced(&script),
+            r#"require ["fileinto", "imap4flags"];
+fileinto "Archive";
+addflag "\\Seen";
+"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_preserving() {
+        let source = r#"require ["fileinto"];
+
+if header :contains "subject" "invoice"
+{
+  fileinto "Invoices";
+}
+
+if header :contains "subject" "spam" { discard; stop; }
+keep;
+"#;
+        let mut script = parse(source).unwrap();
+        assert_eq!(serialize_preserving(source, &script), source);
+        assert_eq!(
+            parse_with_spans(source).unwrap()[3].1,
+            source.len() - 6..source.len() - 1
+        );
+
+        // Only the changed rule is rendered again
+        let Expression::If(rule) = &mut script[1] else {
+            panic!("not a rule: {:?}", script[1]);
+        };
+        rule.expressions = vec![Expression::FileInto(FileInto::new("Bills"))];
+        assert_eq!(
+            serialize_preserving(source, &script),
+            r#"require ["fileinto"];
+
+if header :contains "subject" "invoice" {
+    fileinto "Bills";
+}
+
+if header :contains "subject" "spam" { discard; stop; }
+keep;
+"#
+        );
+
+        // Removing a statement takes its lines along, new ones are rendered
+        script.remove(2);
+        script.push(Expression::Stop);
+        assert_eq!(
+            serialize_preserving(source, &script),
+            r#"require ["fileinto"];
+
+if header :contains "subject" "invoice" {
+    fileinto "Bills";
+}
+keep;
+stop;
+"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_preserving_unparsable_source() {
+        let script = parse("keep;").unwrap();
+        assert_eq!(serialize_preserving("keep", &script), "keep;\n");
+    }
+
+    #[test]
+    fn test_serialize_vacation() {
+        let script = parse(
+            r#"vacation :mime :handle "summer" :seconds 3600 :from "me@example.com" "Away";"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serialize(&script),
+            "vacation :seconds 3600 :from \"me@example.com\" :mime :handle \"summer\" \"Away\";\n"
+        );
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+    }
+
+    #[test]
+    fn test_serialize_notify() {
+        let script = parse(
+            r#"notify :message "New mail" :method "mailto:alm@example.com" :importance "2";"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serialize(&script),
+            "notify :importance \"2\" :message \"New mail\" \"mailto:alm@example.com\";\n"
+        );
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+    }
+
+    #[test]
+    fn test_serialize_date() {
+        let source = r#"require ["date", "relational"];
+if currentdate :zone "+0100" :is "date" "2024-01-01" {
+    discard;
+} elsif anyof (date :originalzone :matches "received" "weekday" ["0", "6"], currentdate :is "hour" "23") {
+    keep;
+}
+"#;
+        let script = parse(source).unwrap();
+
+        assert_eq!(serialize(&script), source);
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+    }
+
+    #[test]
+    fn test_serialize_hasflag() {
+        let source = r#"require ["imap4flags"];
+if hasflag "\\Seen" {
+    removeflag ["\\Seen", "Muffin"];
+} elsif hasflag ["\\Flagged", "Muffin"] {
+    stop;
+}
+"#;
+        let script = parse(source).unwrap();
+
+        assert_eq!(serialize(&script), source);
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+    }
+
+    #[test]
+    fn test_serialize_keep_flags() {
+        let source = r#"require ["imap4flags"];
+if header :contains "subject" "invoice" {
+    keep :flags ["\\Seen", "Invoice"];
+    stop;
+}
+keep :flags "\\Flagged";
+"#;
+        let script = parse(source).unwrap();
+
+        assert_eq!(serialize(&script), source);
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+    }
+
+    #[test]
+    fn test_serialize_foreverypart() {
+        let source = r#"require ["mime", "foreverypart", "extracttext", "variables"];
+foreverypart {
+    if header :contains "Content-Type" "text/plain" {
+        extracttext :first 100 "preview";
+        break;
+    }
+}
+"#;
+        let script = parse(source).unwrap();
+
+        assert_eq!(serialize(&script), source);
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+    }
+
+    #[test]
+    fn test_serialize_set() {
+        let source = r#"require ["variables"];
+set "folder" "Lists";
+set :upperfirst :lower "name" "${folder}";
+"#;
+        let script = parse(source).unwrap();
+
+        assert_eq!(serialize(&script), source);
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+    }
+
+    #[test]
+    fn test_serialize_include() {
+        let source = r#"require ["include"];
+include :once "filters";
+include :global :optional "shared";
+return;
+"#;
+        let script = parse(source).unwrap();
+
+        assert_eq!(serialize(&script), source);
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+    }
+
+    #[test]
+    fn test_serialize_editheader() {
+        let source = r#"require ["editheader"];
+addheader :last "X-Filtered" "yes";
+deleteheader :index 1 :last :contains "Received" ["spam", "bulk"];
+deleteheader "X-Spam";
+"#;
+        let script = parse(source).unwrap();
+
+        assert_eq!(serialize(&script), source);
+        assert_eq!(parse(&serialize(&script)), Ok(script));
+
+        // `:last` without `:index` isn't valid, so it's rejected instead of being
+        // parsed into a command that is written back without it
+        assert_eq!(
+            parse("require [\"editheader\"];\ndeleteheader :last \"X-Spam\";\n"),
+            Err(ParseError { line: 2, column: 1 })
+        );
+    }
+}
@@ -0,0 +1,717 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::{
+    Condition, Expression, Flag, IncludeLocation, SetModifier, StringComparisonType, Vacation,
+};
+
+/// A synthetic message to run a script against with [`simulate`].
+#[derive(Debug, Clone, Default)]
+pub struct Message {
+    /// Header fields in message order, names are matched case-insensitively
+    pub headers: Vec<(String, String)>,
+}
+
+impl Message {
+    fn header_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// What should happen to the message, in the order the script decided it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Deliver to the inbox, either from `keep` or the implicit keep
+    Keep,
+    FileInto(String),
+    /// Forward to this address
+    Redirect(String),
+    /// Answer the sender with `reason`. `handle` is the explicit `:handle`, if
+    /// any. A script only sends one response per handle, or per subject, from,
+    /// `:mime` and reason without one.
+    Vacation {
+        handle: Option<String>,
+        reason: String,
+    },
+    Discard,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outcome {
+    pub actions: Vec<Action>,
+    /// The flags set by `addflag`/`removeflag`/`setflag` when the script ended
+    pub flags: Vec<Flag>,
+}
+
+/// The script uses a command or test the simulator can't evaluate.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Testing scripts that use `{0}` is not supported")]
+pub struct Unsupported(pub &'static str);
+
+/// Runs `expressions` against `message` without talking to a server and
+/// returns what the script would do with it.
+///
+/// Scripts using `include` are unsupported here as the included scripts aren't
+/// known, see [`simulate_with_includes`].
+pub fn simulate(expressions: &[Expression], message: &Message) -> Result<Outcome, Unsupported> {
+    run(expressions, message, None)
+}
+
+/// Like [`simulate`], but `include` runs the script `resolve` returns for its
+/// location and name. `None` means the script doesn't exist, which is only
+/// allowed for `:optional` includes.
+///
+/// A `stop` in an included script ends all processing, a `return` continues
+/// after the `include` that ran it.
+pub fn simulate_with_includes(
+    expressions: &[Expression],
+    message: &Message,
+    resolve: &dyn Fn(IncludeLocation, &str) -> Option<Vec<Expression>>,
+) -> Result<Outcome, Unsupported> {
+    run(expressions, message, Some(resolve))
+}
+
+type Resolve<'a> = &'a dyn Fn(IncludeLocation, &str) -> Option<Vec<Expression>>;
+
+fn run(
+    expressions: &[Expression],
+    message: &Message,
+    resolve: Option<Resolve>,
+) -> Result<Outcome, Unsupported> {
+    let mut state = State {
+        message,
+        outcome: Outcome::default(),
+        implicit_keep: true,
+        resolve,
+        included: Vec::new(),
+        including: Vec::new(),
+        variables: HashMap::new(),
+        vacations: Vec::new(),
+    };
+
+    // A `return` in the top level script ends it just like `stop`
+    state.run(expressions)?;
+
+    if state.implicit_keep && !state.outcome.actions.contains(&Action::Keep) {
+        state.outcome.actions.push(Action::Keep);
+    }
+
+    Ok(state.outcome)
+}
+
+enum Flow {
+    Continue,
+    Stop,
+    Return,
+}
+
+struct State<'a> {
+    message: &'a Message,
+    outcome: Outcome,
+    implicit_keep: bool,
+    resolve: Option<Resolve<'a>>,
+    // Every script included so far, for `:once`
+    included: Vec<(IncludeLocation, String)>,
+    // The chain of includes currently running, to catch include loops
+    including: Vec<(IncludeLocation, String)>,
+    // Variables assigned by `set`, keyed by their lowercase name
+    variables: HashMap<String, String>,
+    // The dedup keys of the responses sent so far, see `vacation_key`
+    vacations: Vec<String>,
+}
+
+impl State<'_> {
+    fn run(&mut self, expressions: &[Expression]) -> Result<Flow, Unsupported> {
+        for expression in expressions {
+            match self.execute(expression)? {
+                Flow::Continue => {}
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    fn execute(&mut self, expression: &Expression) -> Result<Flow, Unsupported> {
+        match expression {
+            Expression::Require(_) => {}
+            Expression::If(if_) => {
+                if self.test(&if_.condition)? {
+                    return self.run(&if_.expressions);
+                }
+                for (condition, expressions) in &if_.else_ifs {
+                    if self.test(condition)? {
+                        return self.run(expressions);
+                    }
+                }
+                return self.run(&if_.else_block);
+            }
+            Expression::FileInto(fileinto) => {
+                self.push(Action::FileInto(self.expand(&fileinto.mailbox)));
+                if !fileinto.copy {
+                    self.implicit_keep = false;
+                }
+            }
+            Expression::Redirect(redirect) => {
+                self.push(Action::Redirect(self.expand(&redirect.address)));
+                if !redirect.copy {
+                    self.implicit_keep = false;
+                }
+            }
+            Expression::Vacation(vacation) => {
+                let key = vacation_key(vacation);
+                // Unlike the other actions a response doesn't cancel the implicit keep
+                if !self.vacations.contains(&key) {
+                    self.vacations.push(key);
+                    self.outcome.actions.push(Action::Vacation {
+                        handle: vacation.handle.clone(),
+                        reason: self.expand(&vacation.reason),
+                    });
+                }
+            }
+            Expression::AddFlag(flags) => {
+                for flag in flags {
+                    if !self.outcome.flags.contains(flag) {
+                        self.outcome.flags.push(flag.clone());
+                    }
+                }
+            }
+            Expression::RemoveFlag(flags) => {
+                self.outcome.flags.retain(|flag| !flags.contains(flag))
+            }
+            Expression::SetFlag(flags) => self.outcome.flags = flags.clone(),
+            Expression::Discard => {
+                self.push(Action::Discard);
+                self.implicit_keep = false;
+            }
+            // The flags of `keep :flags` only apply to the kept copy, which the
+            // outcome has no place for
+            Expression::Keep(_) => {
+                self.push(Action::Keep);
+                self.implicit_keep = false;
+            }
+            Expression::Stop => return Ok(Flow::Stop),
+            Expression::AddHeader(_) => return Err(Unsupported("addheader")),
+            Expression::DeleteHeader(_) => return Err(Unsupported("deleteheader")),
+            Expression::Notify(_) => return Err(Unsupported("notify")),
+            Expression::ForEveryPart(_) => return Err(Unsupported("foreverypart")),
+            Expression::Break => return Err(Unsupported("break")),
+            Expression::Set(set) => {
+                let value = modify(self.expand(&set.value), &set.modifiers);
+                self.variables.insert(set.name.to_ascii_lowercase(), value);
+            }
+            Expression::ExtractText { .. } => return Err(Unsupported("extracttext")),
+            Expression::Include {
+                location,
+                once,
+                optional,
+                name,
+            } => return self.include(*location, *once, *optional, name),
+            Expression::Return => return Ok(Flow::Return),
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    fn include(
+        &mut self,
+        location: IncludeLocation,
+        once: bool,
+        optional: bool,
+        name: &str,
+    ) -> Result<Flow, Unsupported> {
+        let Some(resolve) = self.resolve else {
+            return Err(Unsupported("include"));
+        };
+
+        let key = (location, name.to_string());
+        if self.including.contains(&key) {
+            return Err(Unsupported("recursive include"));
+        }
+        if once && self.included.contains(&key) {
+            return Ok(Flow::Continue);
+        }
+
+        let Some(expressions) = resolve(location, name) else {
+            return if optional {
+                Ok(Flow::Continue)
+            } else {
+                Err(Unsupported("include of a missing script"))
+            };
+        };
+
+        self.included.push(key.clone());
+        self.including.push(key);
+        let flow = self.run(&expressions)?;
+        self.including.pop();
+
+        match flow {
+            // `return` only ends the included script
+            Flow::Continue | Flow::Return => Ok(Flow::Continue),
+            Flow::Stop => Ok(Flow::Stop),
+        }
+    }
+
+    // Replaces `${name}` with the variable's value, unset variables expand to
+    // nothing and anything that isn't a variable name is left alone (RFC 5229
+    // section 3)
+    fn expand(&self, value: &str) -> String {
+        let mut expanded = String::new();
+        let mut rest = value;
+        while let Some(start) = rest.find("${") {
+            expanded.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            match after.find('}') {
+                Some(end) if is_variable_name(&after[..end]) => {
+                    if let Some(value) = self.variables.get(&after[..end].to_ascii_lowercase()) {
+                        expanded.push_str(value);
+                    }
+                    rest = &after[end + 1..];
+                }
+                _ => {
+                    expanded.push_str("${");
+                    rest = after;
+                }
+            }
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+
+    // Sieve only performs an identical action once
+    fn push(&mut self, action: Action) {
+        if !self.outcome.actions.contains(&action) {
+            self.outcome.actions.push(action);
+        }
+    }
+
+    fn test(&self, condition: &Condition) -> Result<bool, Unsupported> {
+        match condition {
+            Condition::Header(condition) | Condition::Address(condition)
+                if condition.comparison_type == StringComparisonType::Regex =>
+            {
+                Err(Unsupported(":regex"))
+            }
+            Condition::Header(condition) => {
+                let key = self.expand(&condition.value);
+                Ok(self
+                    .message
+                    .header_values(&self.expand(&condition.source))
+                    .any(|value| string_matches(&condition.comparison_type, &key, value)))
+            }
+            Condition::Address(condition) => {
+                let key = self.expand(&condition.value);
+                Ok(self
+                    .message
+                    .header_values(&self.expand(&condition.source))
+                    .flat_map(|value| value.split(','))
+                    .any(|value| string_matches(&condition.comparison_type, &key, address(value))))
+            }
+            Condition::AllOf(conditions) => {
+                for condition in conditions {
+                    if !self.test(condition)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Condition::AnyOf(conditions) => {
+                for condition in conditions {
+                    if self.test(condition)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Condition::HasFlag(flags) => {
+                Ok(flags.iter().any(|flag| self.outcome.flags.contains(flag)))
+            }
+            Condition::Date { .. } => Err(Unsupported("date")),
+            Condition::CurrentDate(_) => Err(Unsupported("currentdate")),
+        }
+    }
+}
+
+// The bare address of `Name <user@example.com>`
+fn address(value: &str) -> &str {
+    let value = value.trim();
+    match (value.rfind('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value,
+    }
+}
+
+// Identifiers or the digits of a match variable like `${1}`, which are never
+// set as the simulator doesn't keep match results
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_digit() => chars.all(|char| char.is_ascii_digit()),
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|char| char.is_ascii_alphanumeric() || char == '_')
+        }
+        _ => false,
+    }
+}
+
+// Applies `set` modifiers, those with a higher precedence first
+fn modify(value: String, modifiers: &[SetModifier]) -> String {
+    let mut modifiers = modifiers.to_vec();
+    modifiers.sort_by_key(|modifier| std::cmp::Reverse(modifier.precedence()));
+
+    modifiers
+        .into_iter()
+        .fold(value, |value, modifier| match modifier {
+            SetModifier::Lower => value.to_lowercase(),
+            SetModifier::Upper => value.to_uppercase(),
+            SetModifier::LowerFirst => map_first(&value, |first| first.to_lowercase().collect()),
+            SetModifier::UpperFirst => map_first(&value, |first| first.to_uppercase().collect()),
+            SetModifier::QuoteWildcard => {
+                let mut quoted = String::with_capacity(value.len());
+                for char in value.chars() {
+                    if matches!(char, '*' | '?' | '\\') {
+                        quoted.push('\\');
+                    }
+                    quoted.push(char);
+                }
+                quoted
+            }
+            // Percent-encodes everything but the unreserved characters of RFC 3986
+            SetModifier::EncodeUrl => value
+                .bytes()
+                .map(|byte| {
+                    if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                        char::from(byte).to_string()
+                    } else {
+                        format!("%{:02X}", byte)
+                    }
+                })
+                .collect(),
+            SetModifier::Length => value.chars().count().to_string(),
+        })
+}
+
+fn map_first(value: &str, map: impl Fn(char) -> String) -> String {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(first) => map(first) + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+// What the server tells responses apart by: a sender only gets one response per
+// handle within the interval. Without `:handle` it is derived from the other
+// arguments, as RFC 5230 section 4.2 asks.
+fn vacation_key(vacation: &Vacation) -> String {
+    match &vacation.handle {
+        Some(handle) => handle.clone(),
+        None => format!(
+            "{}\0{}\0{}\0{}",
+            vacation.subject.as_deref().unwrap_or_default(),
+            vacation.from.as_deref().unwrap_or_default(),
+            vacation.mime,
+            vacation.reason
+        ),
+    }
+}
+
+// Compares with the default `i;ascii-casemap` comparator
+fn string_matches(comparison_type: &StringComparisonType, key: &str, value: &str) -> bool {
+    let value = value.to_ascii_lowercase();
+    let key = key.to_ascii_lowercase();
+
+    match comparison_type {
+        StringComparisonType::Is => value == key,
+        StringComparisonType::Contains => value.contains(&key),
+        StringComparisonType::Matches => {
+            let key: Vec<char> = key.chars().collect();
+            let value: Vec<char> = value.chars().collect();
+            wildcard_matches(&key, &value)
+        }
+        // Rejected by `State::test` before getting here
+        StringComparisonType::Regex => false,
+    }
+}
+
+// `*` matches any sequence, `?` a single character and `\` escapes the next one.
+// On a mismatch this backtracks only to the last `*`, letting it take one more
+// character, so the run time doesn't grow exponentially with the stars.
+fn wildcard_matches(pattern: &[char], value: &[char]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    // Pattern position after the last `*` and the value position it's tried at
+    let mut star = None;
+
+    while v < value.len() {
+        match pattern.get(p..) {
+            Some(['*', ..]) => {
+                p += 1;
+                star = Some((p, v));
+                continue;
+            }
+            Some(['?', ..]) => {
+                p += 1;
+                v += 1;
+                continue;
+            }
+            Some(['\\', escaped, ..]) if *escaped == value[v] => {
+                p += 2;
+                v += 1;
+                continue;
+            }
+            Some(['\\', _, ..]) => {}
+            Some([literal, ..]) if *literal == value[v] => {
+                p += 1;
+                v += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let Some((star_p, star_v)) = star else {
+            return false;
+        };
+        p = star_p;
+        v = star_v + 1;
+        star = Some((star_p, v));
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    fn message(headers: &[(&str, &str)]) -> Message {
+        Message {
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_simulate() {
+        let script = parse(
+            r#"require ["fileinto", "imap4flags", "copy"];
+            if header :contains "subject" "invoice" {
+                addflag "\\Flagged";
+                fileinto "Invoices";
+                stop;
+            }
+            if address :is "from" "alerts@example.com" {
+                fileinto :copy "Alerts";
+                redirect "oncall@example.com";
+            } elsif header :matches "Subject" "*[spam]*" {
+                discard;
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            simulate(&script, &message(&[("Subject", "Your INVOICE for May")])),
+            Ok(Outcome {
+                actions: vec![Action::FileInto("Invoices".to_string())],
+                flags: vec![Flag::Flagged],
+            })
+        );
+        assert_eq!(
+            simulate(
+                &script,
+                &message(&[
+                    ("From", "Alerts <alerts@example.com>"),
+                    ("Subject", "Disk full")
+                ])
+            ),
+            Ok(Outcome {
+                actions: vec![
+                    Action::FileInto("Alerts".to_string()),
+                    Action::Redirect("oncall@example.com".to_string())
+                ],
+                flags: vec![],
+            })
+        );
+        assert_eq!(
+            simulate(&script, &message(&[("Subject", "Buy now [SPAM] cheap")]))
+                .unwrap()
+                .actions,
+            vec![Action::Discard]
+        );
+        assert_eq!(
+            simulate(&script, &message(&[("Subject", "Hello")]))
+                .unwrap()
+                .actions,
+            vec![Action::Keep]
+        );
+    }
+
+    #[test]
+    fn test_simulate_vacation() {
+        let script = parse(
+            r#"require "vacation";
+            vacation :handle "away" "I'm away.";
+            vacation :handle "away" "I'm still away.";
+            vacation :days 1 "Back soon.";
+            vacation :days 2 "Back soon.";"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            simulate(&script, &Message::default()).unwrap().actions,
+            vec![
+                Action::Vacation {
+                    handle: Some("away".to_string()),
+                    reason: "I'm away.".to_string()
+                },
+                Action::Vacation {
+                    handle: None,
+                    reason: "Back soon.".to_string()
+                },
+                Action::Keep,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_simulate_unsupported() {
+        let script = parse(r#"if currentdate :is "year" "2024" { discard; }"#).unwrap();
+        assert_eq!(
+            simulate(&script, &Message::default()),
+            Err(Unsupported("currentdate"))
+        );
+
+        let script = parse(r#"if header :regex "subject" "^re:" { discard; }"#).unwrap();
+        assert_eq!(
+            simulate(&script, &Message::default()),
+            Err(Unsupported(":regex"))
+        );
+    }
+
+    #[test]
+    fn test_simulate_with_includes() {
+        let script = parse(
+            r#"require ["include", "fileinto"];
+            include :optional "missing";
+            include "spam";
+            include :once "lists";
+            include :once "lists";
+            fileinto "Rest";"#,
+        )
+        .unwrap();
+        let resolve = |location: IncludeLocation, name: &str| {
+            let script = match (location, name) {
+                (IncludeLocation::Personal, "spam") => {
+                    r#"if header :contains "subject" "[spam]" { discard; stop; }"#
+                }
+                (IncludeLocation::Personal, "lists") => {
+                    r#"require ["fileinto"];
+                    if header :contains "list-id" "." { fileinto "Lists"; return; }
+                    fileinto "NoList";"#
+                }
+                _ => return None,
+            };
+            Some(parse(script).unwrap())
+        };
+
+        // `stop` in an included script ends the including one too
+        assert_eq!(
+            simulate_with_includes(&script, &message(&[("Subject", "[SPAM] hi")]), &resolve)
+                .unwrap()
+                .actions,
+            vec![Action::Discard]
+        );
+        // `return` continues after the include, which runs only once
+        assert_eq!(
+            simulate_with_includes(
+                &script,
+                &message(&[("Subject", "news"), ("List-Id", "<news.example.com>")]),
+                &resolve
+            )
+            .unwrap()
+            .actions,
+            vec![
+                Action::FileInto("Lists".to_string()),
+                Action::FileInto("Rest".to_string())
+            ]
+        );
+
+        let script = parse(r#"require ["include"]; include "missing";"#).unwrap();
+        assert_eq!(
+            simulate_with_includes(&script, &Message::default(), &resolve),
+            Err(Unsupported("include of a missing script"))
+        );
+        assert_eq!(
+            simulate(&script, &Message::default()),
+            Err(Unsupported("include"))
+        );
+    }
+
+    #[test]
+    fn test_simulate_set() {
+        let script = parse(
+            r#"require ["fileinto", "variables", "enotify"];
+            set "list" "news";
+            set :upperfirst :lower "a" "hELLO wORLD";
+            set :length :quotewildcard "b" "a*b?";
+            set :upper :encodeurl "c" "a b/ä";
+            set :upperfirst "d" "${LIST}";
+            fileinto "${a}";
+            fileinto "${b}";
+            fileinto "${c}";
+            fileinto "${d}/${unset}/${not a name}";
+            if header :is "subject" "${list}" { discard; }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            simulate(&script, &message(&[("Subject", "News")]))
+                .unwrap()
+                .actions,
+            vec![
+                // :lower before :upperfirst
+                Action::FileInto("Hello world".to_string()),
+                // :quotewildcard before :length
+                Action::FileInto("6".to_string()),
+                // :upper before :encodeurl
+                Action::FileInto("A%20B%2F%C3%84".to_string()),
+                Action::FileInto("News//${not a name}".to_string()),
+                Action::Discard,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wildcard_matches() {
+        let matches = |pattern: &str, value: &str| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            let value: Vec<char> = value.chars().collect();
+            wildcard_matches(&pattern, &value)
+        };
+
+        assert!(matches("*", ""));
+        assert!(matches("a*c", "abbbc"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("a\\", "a\\"));
+        assert!(matches("*a*", "bab"));
+        assert!(!matches("*a*b", "aaa"));
+    }
+
+    #[test]
+    fn test_wildcard_matches_many_stars() {
+        let pattern: Vec<char> = "*a*a*a*a*a*a*a*a*a*a*a*a*b".chars().collect();
+        let value: Vec<char> = "a".repeat(10_000).chars().collect();
+        assert!(!wildcard_matches(&pattern, &value));
+
+        let value: Vec<char> = ("a".repeat(10_000) + "b").chars().collect();
+        assert!(wildcard_matches(&pattern, &value));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{collections::BTreeSet, fmt};
+
+use super::{Expression, Node, required_extensions, walk};
+
+/// An overview of what a script does, see [`summarize`].
+///
+/// The [`Display`](fmt::Display) implementation renders it as a sentence, e.g.
+/// `3 rules, files into 5 folders, 1 discard, uses fileinto, imap4flags`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    /// Number of top level `if` statements
+    pub rules: usize,
+    /// Every folder the script files into, without duplicates
+    pub folders: BTreeSet<String>,
+    pub discards: usize,
+    /// The extensions from all `require` statements, in the order first required
+    pub extensions: Vec<String>,
+}
+
+/// Counts the rules, folders, discards and required extensions of a script.
+pub fn summarize(expressions: &[Expression]) -> Summary {
+    let mut summary = Summary {
+        rules: expressions
+            .iter()
+            .filter(|expression| matches!(expression, Expression::If(_)))
+            .count(),
+        extensions: required_extensions(expressions),
+        ..Summary::default()
+    };
+
+    walk(expressions, &mut |node| match node {
+        Node::Expression(Expression::FileInto(fileinto)) => {
+            summary.folders.insert(fileinto.mailbox.clone());
+        }
+        Node::Expression(Expression::Discard) => summary.discards += 1,
+        _ => {}
+    });
+
+    summary
+}
+
+fn plural(count: usize, singular: &str, plural: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, singular)
+    } else {
+        format!("{} {}", count, plural)
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![plural(self.rules, "rule", "rules")];
+        if !self.folders.is_empty() {
+            parts.push(format!(
+                "files into {}",
+                plural(self.folders.len(), "folder", "folders")
+            ));
+        }
+        if self.discards > 0 {
+            parts.push(plural(self.discards, "discard", "discards"));
+        }
+        if !self.extensions.is_empty() {
+            parts.push(format!("uses {}", self.extensions.join(", ")));
+        }
+
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_summarize() {
+        let script = parse(
+            r#"
+            require ["fileinto", "imap4flags"];
+            if header :contains "subject" "invoice" {
+                fileinto "Invoices";
+                addflag "\\Seen";
+            } elsif header :contains "subject" "receipt" {
+                fileinto "Invoices";
+            }
+            if header :contains "subject" "spam" {
+                discard;
+                stop;
+            }
+            if header :contains "subject" "backup" {
+                if header :contains "subject" "failed" {
+                    fileinto "Alerts";
+                }
+                fileinto "Backups";
+            }
+            "#,
+        )
+        .unwrap();
+
+        let summary = summarize(&script);
+        assert_eq!(summary.rules, 3);
+        assert_eq!(
+            summary
+                .folders
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            vec!["Alerts", "Backups", "Invoices"]
+        );
+        assert_eq!(summary.discards, 1);
+        assert_eq!(summary.extensions, vec!["fileinto", "imap4flags"]);
+        assert_eq!(
+            summary.to_string(),
+            "3 rules, files into 3 folders, 1 discard, uses fileinto, imap4flags"
+        );
+    }
+
+    #[test]
+    fn test_summarize_empty() {
+        assert_eq!(summarize(&[]).to_string(), "0 rules");
+
+        let script = parse("keep;").unwrap();
+        assert_eq!(summarize(&script).to_string(), "0 rules");
+    }
+}
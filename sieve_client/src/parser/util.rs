@@ -71,3 +71,8 @@ pub fn parse_string_array(input: &str) -> IResult<&str, Vec<String>> {
     )
     .parse(input)
 }
+
+/// A single string or a string array, wherever the grammar takes a `string-list`
+pub fn parse_string_list(input: &str) -> IResult<&str, Vec<String>> {
+    alt((parse_string.map(|string| vec![string]), parse_string_array)).parse(input)
+}
@@ -0,0 +1,129 @@
+use super::{Condition, Expression};
+
+/// A node of the script tree handed to the [`walk`] callback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Node<'a> {
+    Expression(&'a Expression),
+    Condition(&'a Condition),
+}
+
+/// Calls `visit` for every expression and condition in `expressions`, including
+/// those nested in `if`/`elsif`/`else` blocks and `allof`/`anyof` lists.
+///
+/// Nodes are visited depth first in source order, a parent before its children.
+pub fn walk<'a>(expressions: &'a [Expression], visit: &mut impl FnMut(Node<'a>)) {
+    for expression in expressions {
+        walk_expression(expression, visit);
+    }
+}
+
+fn walk_expression<'a>(expression: &'a Expression, visit: &mut impl FnMut(Node<'a>)) {
+    visit(Node::Expression(expression));
+
+    match expression {
+        Expression::If(if_) => {
+            walk_condition(&if_.condition, visit);
+            walk(&if_.expressions, visit);
+
+            for (condition, expressions) in &if_.else_ifs {
+                walk_condition(condition, visit);
+                walk(expressions, visit);
+            }
+
+            walk(&if_.else_block, visit);
+        }
+        Expression::ForEveryPart(expressions) => walk(expressions, visit),
+        Expression::Require(_)
+        | Expression::FileInto(_)
+        | Expression::Redirect(_)
+        | Expression::Vacation(_)
+        | Expression::AddFlag(_)
+        | Expression::RemoveFlag(_)
+        | Expression::SetFlag(_)
+        | Expression::AddHeader(_)
+        | Expression::DeleteHeader(_)
+        | Expression::Notify(_)
+        | Expression::Break
+        | Expression::Set(_)
+        | Expression::ExtractText { .. }
+        | Expression::Include { .. }
+        | Expression::Return
+        | Expression::Discard
+        | Expression::Keep(_)
+        | Expression::Stop => {}
+    }
+}
+
+fn walk_condition<'a>(condition: &'a Condition, visit: &mut impl FnMut(Node<'a>)) {
+    visit(Node::Condition(condition));
+
+    match condition {
+        Condition::AllOf(conditions) | Condition::AnyOf(conditions) => {
+            for condition in conditions {
+                walk_condition(condition, visit);
+            }
+        }
+        Condition::Header(_)
+        | Condition::Address(_)
+        | Condition::Date { .. }
+        | Condition::CurrentDate(_)
+        | Condition::HasFlag(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_walk() {
+        let script = parse(
+            r#"
+            require ["fileinto"];
+            if allof (header :contains "subject" "invoice", anyof (address :is "from" "a@example.com", address :is "from" "b@example.com")) {
+                fileinto "Invoices";
+            } elsif header :contains "subject" "backup" {
+                if header :contains "subject" "failed" {
+                    fileinto "Alerts";
+                    stop;
+                }
+                fileinto "Backups";
+            } else {
+                keep;
+            }
+            "#,
+        )
+        .unwrap();
+
+        let mut folders = Vec::new();
+        let mut sources = Vec::new();
+        let mut expressions = 0;
+        walk(&script, &mut |node| match node {
+            Node::Expression(expression) => {
+                expressions += 1;
+                if let Expression::FileInto(fileinto) = expression {
+                    folders.push(fileinto.mailbox.as_str());
+                }
+            }
+            Node::Condition(Condition::Header(condition) | Condition::Address(condition)) => {
+                sources.push((condition.source.as_str(), condition.value.as_str()));
+            }
+            Node::Condition(_) => {}
+        });
+
+        assert_eq!(folders, vec!["Invoices", "Alerts", "Backups"]);
+        assert_eq!(
+            sources,
+            vec![
+                ("subject", "invoice"),
+                ("from", "a@example.com"),
+                ("from", "b@example.com"),
+                ("subject", "backup"),
+                ("subject", "failed"),
+            ]
+        );
+        // require, the two ifs, three fileintos, stop and keep
+        assert_eq!(expressions, 8);
+    }
+}
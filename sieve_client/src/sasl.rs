@@ -0,0 +1,556 @@
+use std::{mem, num::NonZeroU32};
+
+use aws_lc_rs::{digest, hmac, pbkdf2, rand};
+use base64::{Engine as _, engine::general_purpose};
+use secrecy::zeroize::Zeroizing;
+
+use crate::sieve_client::{ConnectError, CredentialNormalization};
+
+/// A SASL mechanism [`SieveClient`](crate::SieveClient) can log in with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    /// RFC 7677, proves knowledge of the password without sending it and
+    /// checks that the server knows it too
+    ScramSha256,
+    /// RFC 4616, the credentials in a single message
+    Plain,
+    /// The username and password each in answer to a challenge of the server,
+    /// for servers that don't offer PLAIN
+    Login,
+    /// RFC 4422 appendix A, the identity established by the TLS client
+    /// certificate. Never chosen by [`choose`](Self::choose), it is used
+    /// whenever [`ConnectOptions::client_identity`](crate::ConnectOptions::client_identity)
+    /// is set and the server offers it.
+    External,
+}
+
+impl Mechanism {
+    /// The name the server lists in its `SASL` capability
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ScramSha256 => "SCRAM-SHA-256",
+            Self::Plain => "PLAIN",
+            Self::Login => "LOGIN",
+            Self::External => "EXTERNAL",
+        }
+    }
+
+    /// The preferred mechanism among those the server offers
+    pub fn choose(offered: &[String]) -> Option<Self> {
+        [Self::ScramSha256, Self::Plain, Self::Login]
+            .into_iter()
+            .find(|mechanism| {
+                offered
+                    .iter()
+                    .any(|offered| offered.eq_ignore_ascii_case(mechanism.name()))
+            })
+    }
+
+    // A fresh client side of the exchange, one per AUTHENTICATE command
+    pub(crate) fn client(
+        self,
+        username: &str,
+        password: &str,
+        normalization: CredentialNormalization,
+    ) -> Result<Box<dyn SaslClient + Send>, ConnectError> {
+        // RFC 7677 requires SASLprep for SCRAM, whatever the option says, as
+        // the server derives its keys from the prepared password
+        let normalization = match self {
+            Self::ScramSha256 => CredentialNormalization::SaslPrep,
+            _ => normalization,
+        };
+        let username = Zeroizing::new(normalization.apply(username)?.into_owned());
+        let password = Zeroizing::new(normalization.apply(password)?.into_owned());
+        Ok(match self {
+            Self::ScramSha256 => Box::new(ScramSha256::new(username, password)?),
+            Self::Plain => Box::new(Plain::new(&username, &password)),
+            Self::Login => Box::new(Login {
+                answers: vec![password, username],
+            }),
+            Self::External => Box::new(External),
+        })
+    }
+}
+
+/// The client side of a SASL exchange. Messages are raw, the base64 encoding
+/// is left to the caller.
+pub(crate) trait SaslClient {
+    /// What is sent along with the AUTHENTICATE command, if anything
+    fn initial_response(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>, ConnectError>;
+
+    /// The answer to a challenge of the server
+    fn respond(&mut self, challenge: &[u8]) -> Result<Zeroizing<Vec<u8>>, ConnectError>;
+
+    /// Called once the server accepted the credentials, with the data of its
+    /// `SASL` response code if it sent one
+    fn finish(&mut self, _data: Option<&[u8]>) -> Result<(), ConnectError> {
+        Ok(())
+    }
+}
+
+fn unexpected_challenge(mechanism: Mechanism) -> ConnectError {
+    ConnectError::ProtocolError(format!(
+        "Unexpected challenge during {} authentication",
+        mechanism.name()
+    ))
+}
+
+/// SASL PLAIN, everything is sent in the initial response.
+pub(crate) struct Plain {
+    message: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl Plain {
+    fn new(username: &str, password: &str) -> Self {
+        Self {
+            message: Some(plain_message(username, password)),
+        }
+    }
+}
+
+impl SaslClient for Plain {
+    fn initial_response(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>, ConnectError> {
+        Ok(self.message.take())
+    }
+
+    fn respond(&mut self, _challenge: &[u8]) -> Result<Zeroizing<Vec<u8>>, ConnectError> {
+        Err(unexpected_challenge(Mechanism::Plain))
+    }
+}
+
+/// The SASL PLAIN message (RFC 4616) for already normalized credentials.
+fn plain_message(username: &str, password: &str) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(format!("\0{}\0{}", username, password).into_bytes())
+}
+
+// SASL LOGIN: the server asks for the username and then for the password. What
+// the challenges say is ignored, servers differ in the prompts they send.
+pub(crate) struct Login {
+    // In reverse, the next answer is popped off the end
+    answers: Vec<Zeroizing<String>>,
+}
+
+impl SaslClient for Login {
+    fn initial_response(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>, ConnectError> {
+        Ok(None)
+    }
+
+    fn respond(&mut self, _challenge: &[u8]) -> Result<Zeroizing<Vec<u8>>, ConnectError> {
+        self.answers
+            .pop()
+            .map(|answer| Zeroizing::new(answer.as_bytes().to_vec()))
+            .ok_or_else(|| unexpected_challenge(Mechanism::Login))
+    }
+}
+
+// SASL EXTERNAL with an empty authorization identity, the server takes the one
+// of the client certificate
+pub(crate) struct External;
+
+impl SaslClient for External {
+    fn initial_response(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>, ConnectError> {
+        Ok(Some(Zeroizing::new(Vec::new())))
+    }
+
+    fn respond(&mut self, _challenge: &[u8]) -> Result<Zeroizing<Vec<u8>>, ConnectError> {
+        Err(unexpected_challenge(Mechanism::External))
+    }
+}
+
+// Length of the random part of the client nonce before base64 encoding
+const SCRAM_NONCE_LENGTH: usize = 18;
+
+// Servers use a few thousand iterations. A count far beyond that would keep the
+// client busy hashing for minutes, so it is refused.
+const SCRAM_MAX_ITERATIONS: u32 = 1_000_000;
+
+/// SCRAM-SHA-256 (RFC 5802, RFC 7677) without channel binding.
+///
+/// Every step consumes the state of the previous one, so the nonce goes out
+/// in exactly one client-first-message and the exchange can't be replayed with
+/// the same client.
+pub(crate) struct ScramSha256 {
+    username: Zeroizing<String>,
+    password: Zeroizing<String>,
+    state: ScramState,
+}
+
+enum ScramState {
+    Initial {
+        nonce: String,
+    },
+    /// The client-first-message was sent
+    ClientFirst {
+        nonce: String,
+        client_first_bare: String,
+    },
+    /// The client-final-message was sent, the server still has to prove that
+    /// it knows the password as well
+    ClientFinal {
+        server_key: Zeroizing<Vec<u8>>,
+        auth_message: String,
+    },
+    /// The server signature checked out
+    Verified,
+    /// Something went wrong, nothing more can be sent
+    Failed,
+}
+
+impl ScramSha256 {
+    fn new(username: Zeroizing<String>, password: Zeroizing<String>) -> Result<Self, ConnectError> {
+        let mut random = [0u8; SCRAM_NONCE_LENGTH];
+        rand::fill(&mut random).map_err(|_| {
+            ConnectError::AuthenticationFailed(
+                "No random numbers for the SCRAM nonce available".to_string(),
+            )
+        })?;
+        Ok(Self::with_nonce(
+            username,
+            password,
+            general_purpose::STANDARD.encode(random),
+        ))
+    }
+
+    pub(crate) fn with_nonce(
+        username: Zeroizing<String>,
+        password: Zeroizing<String>,
+        nonce: String,
+    ) -> Self {
+        Self {
+            username,
+            password,
+            state: ScramState::Initial { nonce },
+        }
+    }
+
+    fn client_final(
+        &self,
+        nonce: &str,
+        client_first_bare: &str,
+        server_first: &[u8],
+    ) -> Result<(Zeroizing<Vec<u8>>, ScramState), ConnectError> {
+        let server_first = std::str::from_utf8(server_first)
+            .map_err(|_| scram_error("the server-first-message isn't UTF-8"))?;
+
+        if attribute(server_first, 'm').is_some() {
+            return Err(scram_error("the server requires an unsupported extension"));
+        }
+        let server_nonce =
+            attribute(server_first, 'r').ok_or_else(|| scram_error("the server sent no nonce"))?;
+        // The server only appends to the client's nonce, anything else means
+        // the message doesn't belong to this exchange
+        if server_nonce.len() <= nonce.len() || !server_nonce.starts_with(nonce) {
+            return Err(scram_error(
+                "the server nonce doesn't extend the client nonce",
+            ));
+        }
+        let salt = attribute(server_first, 's')
+            .and_then(|salt| general_purpose::STANDARD.decode(salt).ok())
+            .ok_or_else(|| scram_error("the server sent no valid salt"))?;
+        let iterations = attribute(server_first, 'i')
+            .and_then(|iterations| iterations.parse().ok())
+            .and_then(NonZeroU32::new)
+            .ok_or_else(|| scram_error("the server sent no valid iteration count"))?;
+        if iterations.get() > SCRAM_MAX_ITERATIONS {
+            return Err(scram_error("the server asks for too many iterations"));
+        }
+
+        let mut salted_password = Zeroizing::new([0u8; digest::SHA256_OUTPUT_LEN]);
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            self.password.as_bytes(),
+            salted_password.as_mut(),
+        );
+        let salted_password = hmac::Key::new(hmac::HMAC_SHA256, salted_password.as_ref());
+        let client_key = hmac::sign(&salted_password, b"Client Key");
+        let stored_key = digest::digest(&digest::SHA256, client_key.as_ref());
+        let server_key = Zeroizing::new(
+            hmac::sign(&salted_password, b"Server Key")
+                .as_ref()
+                .to_vec(),
+        );
+
+        // "biws" is the base64 encoded GS2 header "n,,"
+        let without_proof = format!("c=biws,r={}", server_nonce);
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let client_signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, stored_key.as_ref()),
+            auth_message.as_bytes(),
+        );
+        let proof: Zeroizing<Vec<u8>> = Zeroizing::new(
+            client_key
+                .as_ref()
+                .iter()
+                .zip(client_signature.as_ref())
+                .map(|(key, signature)| key ^ signature)
+                .collect(),
+        );
+
+        let message = format!(
+            "{},p={}",
+            without_proof,
+            general_purpose::STANDARD.encode(&*proof)
+        );
+        Ok((
+            Zeroizing::new(message.into_bytes()),
+            ScramState::ClientFinal {
+                server_key,
+                auth_message,
+            },
+        ))
+    }
+}
+
+impl SaslClient for ScramSha256 {
+    fn initial_response(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>, ConnectError> {
+        let ScramState::Initial { nonce } = mem::replace(&mut self.state, ScramState::Failed)
+        else {
+            return Err(scram_error("the exchange was already started"));
+        };
+
+        let client_first_bare = format!("n={},r={}", saslname(&self.username), nonce);
+        let message = format!("n,,{}", client_first_bare);
+        self.state = ScramState::ClientFirst {
+            nonce,
+            client_first_bare,
+        };
+        Ok(Some(Zeroizing::new(message.into_bytes())))
+    }
+
+    fn respond(&mut self, challenge: &[u8]) -> Result<Zeroizing<Vec<u8>>, ConnectError> {
+        match mem::replace(&mut self.state, ScramState::Failed) {
+            ScramState::ClientFirst {
+                nonce,
+                client_first_bare,
+            } => {
+                let (message, state) = self.client_final(&nonce, &client_first_bare, challenge)?;
+                self.state = state;
+                Ok(message)
+            }
+            // Servers that send the server-final-message as a challenge expect
+            // an empty response before they answer OK
+            state @ ScramState::ClientFinal { .. } => {
+                self.state = state;
+                self.finish(Some(challenge))?;
+                Ok(Zeroizing::new(Vec::new()))
+            }
+            _ => Err(unexpected_challenge(Mechanism::ScramSha256)),
+        }
+    }
+
+    fn finish(&mut self, data: Option<&[u8]>) -> Result<(), ConnectError> {
+        match mem::replace(&mut self.state, ScramState::Failed) {
+            ScramState::ClientFinal {
+                server_key,
+                auth_message,
+            } => {
+                let server_final = data
+                    .and_then(|data| std::str::from_utf8(data).ok())
+                    .ok_or_else(|| scram_error("the server didn't send its signature"))?;
+                if let Some(error) = attribute(server_final, 'e') {
+                    return Err(scram_error(&format!("the server reported \"{}\"", error)));
+                }
+                let signature = attribute(server_final, 'v')
+                    .and_then(|signature| general_purpose::STANDARD.decode(signature).ok())
+                    .ok_or_else(|| scram_error("the server didn't send its signature"))?;
+                let server_key = hmac::Key::new(hmac::HMAC_SHA256, &server_key);
+                hmac::verify(&server_key, auth_message.as_bytes(), &signature).map_err(|_| {
+                    ConnectError::AuthenticationFailed(
+                        "The server's SCRAM signature doesn't match, it doesn't know the \
+                         password and may not be the server it claims to be"
+                            .to_string(),
+                    )
+                })?;
+                self.state = ScramState::Verified;
+                Ok(())
+            }
+            ScramState::Verified => {
+                self.state = ScramState::Verified;
+                Ok(())
+            }
+            _ => Err(scram_error(
+                "the server accepted before the exchange was done",
+            )),
+        }
+    }
+}
+
+fn scram_error(reason: &str) -> ConnectError {
+    ConnectError::AuthenticationFailed(format!("SCRAM-SHA-256 failed: {}", reason))
+}
+
+// The value of `key=value` in a comma separated SCRAM message
+fn attribute(message: &str, key: char) -> Option<&str> {
+    message.split(',').find_map(|attribute| {
+        attribute
+            .strip_prefix(key)
+            .and_then(|rest| rest.strip_prefix('='))
+    })
+}
+
+// RFC 5802 section 5.1: `,` and `=` can't appear in a username as they are
+fn saslname(username: &str) -> String {
+    username.replace('=', "=3D").replace(',', "=2C")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn secret(value: &str) -> Zeroizing<String> {
+        Zeroizing::new(value.to_string())
+    }
+
+    // The example exchange of RFC 7677 section 3
+    fn rfc_client() -> ScramSha256 {
+        ScramSha256::with_nonce(
+            secret("user"),
+            secret("pencil"),
+            "rOprNGfwEbeRWgbNEkqO".to_string(),
+        )
+    }
+
+    const SERVER_FIRST: &[u8] =
+        b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+
+    #[test]
+    fn test_choose_mechanism() {
+        let offered = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            Mechanism::choose(&offered(&["LOGIN", "PLAIN"])),
+            Some(Mechanism::Plain)
+        );
+        assert_eq!(
+            Mechanism::choose(&offered(&["DIGEST-MD5", "login"])),
+            Some(Mechanism::Login)
+        );
+        assert_eq!(
+            Mechanism::choose(&offered(&["PLAIN", "scram-sha-256"])),
+            Some(Mechanism::ScramSha256)
+        );
+        assert_eq!(Mechanism::choose(&offered(&["GSSAPI"])), None);
+    }
+
+    #[test]
+    fn test_plain_message_normalization() {
+        fn message(
+            username: &str,
+            password: &str,
+            normalization: CredentialNormalization,
+        ) -> Vec<u8> {
+            let mut client = Mechanism::Plain
+                .client(username, password, normalization)
+                .unwrap();
+            client.initial_response().unwrap().unwrap().to_vec()
+        }
+
+        // "José" typed with a combining acute accent
+        let decomposed = "Jose\u{301}";
+        assert_eq!(
+            message(decomposed, "pass", CredentialNormalization::None),
+            b"\0Jose\xcc\x81\0pass"
+        );
+        assert_eq!(
+            message(decomposed, "pass", CredentialNormalization::Nfc),
+            b"\0Jos\xc3\xa9\0pass"
+        );
+
+        // RFC 4013 section 3 examples: soft hyphen is dropped, ª maps to a,
+        // and a no-break space becomes a plain space
+        assert_eq!(
+            message(
+                "I\u{AD}X",
+                "\u{AA}b\u{A0}c",
+                CredentialNormalization::SaslPrep
+            ),
+            b"\0IX\0ab c"
+        );
+    }
+
+    #[test]
+    fn test_scram_always_saslprep() {
+        let mut client = Mechanism::ScramSha256
+            .client("I\u{AD}X", "pencil", CredentialNormalization::None)
+            .unwrap();
+        let first = client.initial_response().unwrap().unwrap();
+        assert!(first.starts_with(b"n,,n=IX,r="));
+    }
+
+    #[test]
+    fn test_scram_exchange() {
+        let mut client = rfc_client();
+        assert_eq!(
+            client.initial_response().unwrap().unwrap().as_slice(),
+            b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO"
+        );
+        assert_eq!(
+            client.respond(SERVER_FIRST).unwrap().as_slice(),
+            b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+              p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        client
+            .finish(Some(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="))
+            .unwrap();
+
+        // The nonce can't go out a second time
+        assert!(client.initial_response().is_err());
+    }
+
+    #[test]
+    fn test_scram_bad_server_signature() {
+        let mut client = rfc_client();
+        client.initial_response().unwrap();
+        client.respond(SERVER_FIRST).unwrap();
+        let Err(ConnectError::AuthenticationFailed(message)) =
+            client.finish(Some(b"v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="))
+        else {
+            panic!("a wrong server signature was accepted");
+        };
+        assert!(message.contains("signature"));
+
+        // Accepting without a signature is just as suspicious
+        let mut client = rfc_client();
+        client.initial_response().unwrap();
+        client.respond(SERVER_FIRST).unwrap();
+        assert!(client.finish(None).is_err());
+    }
+
+    #[test]
+    fn test_scram_rejects_server_first() {
+        let reject = |server_first: &[u8]| {
+            let mut client = rfc_client();
+            client.initial_response().unwrap();
+            client.respond(server_first).is_err()
+        };
+
+        // A nonce that doesn't start with the client's
+        assert!(reject(b"r=somethingElse,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"));
+        // The client nonce without the server's part
+        assert!(reject(
+            b"r=rOprNGfwEbeRWgbNEkqO,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+        ));
+        assert!(reject(
+            b"r=rOprNGfwEbeRWgbNEkqO%hv,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=0"
+        ));
+        assert!(reject(
+            b"r=rOprNGfwEbeRWgbNEkqO%hv,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=1000001"
+        ));
+        assert!(reject(
+            b"m=ext,r=rOprNGfwEbeRWgbNEkqO%hv,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+        ));
+    }
+
+    #[test]
+    fn test_saslname() {
+        assert_eq!(saslname("a=b,c"), "a=3Db=2Cc");
+        assert_eq!(saslname("user"), "user");
+    }
+}
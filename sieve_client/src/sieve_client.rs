@@ -1,25 +1,46 @@
 use base64::{Engine as _, engine::general_purpose};
 use nom::{
     IResult,
-    bytes::complete::take_until,
     character::complete::{char, space0},
     combinator::opt,
+    error::{Error as NomError, ErrorKind},
+};
+use rustls_pki_types::{CertificateDer, ServerName};
+use secrecy::{ExposeSecret, SecretString, zeroize::Zeroizing};
+use std::time::Duration;
+use std::{borrow::Cow, collections::HashMap, fmt, fmt::Debug, ops::Deref};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Arc, PoisonError, RwLock, RwLockReadGuard, Weak,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
 };
-use rustls::{ClientConfig, RootCertStore};
-use rustls_pki_types::ServerName;
-use std::{collections::HashMap, fmt::Debug};
-use std::{io, sync::Arc};
 use thiserror::Error;
-use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::sync::{Mutex, MutexGuard, mpsc};
+use tokio::task::JoinHandle;
 use tokio_rustls::{TlsConnector, client::TlsStream};
+use tracing::{Instrument, Span, debug, instrument, trace};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::sasl::{Mechanism, SaslClient};
+use crate::tls::{self, CertStore, ClientIdentity};
 
 // Type aliases for cleaner code
 type TlsReader = tokio::io::ReadHalf<TlsStream<TcpStream>>;
 type TlsWriter = tokio::io::WriteHalf<TlsStream<TcpStream>>;
+// Boxed so the same session code runs over TLS and plain TCP
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+type Connection = (BufReader<Reader>, Writer);
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Capabilities {
     pub implementation: Option<String>,
     pub sasl: Vec<String>,
@@ -28,11 +49,44 @@ pub struct Capabilities {
     pub maxredirects: Option<u32>,
     pub notify: Vec<String>,
     pub language: Option<String>,
+    /// The user whose scripts the session works on, which can differ from the
+    /// login when a proxy or admin acts on someone else's behalf, see
+    /// [`Capabilities::is_writable`].
     pub owner: Option<String>,
     pub version: Option<String>,
     pub other: HashMap<String, String>,
 }
 
+impl Capabilities {
+    /// Whether the server advertised a capability without a dedicated field,
+    /// e.g. `UNAUTHENTICATE`.
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.other.keys().any(|key| key.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether `username` may change the scripts of the session. ManageSieve
+    /// has no capability for read-only sessions, so this goes by `OWNER`:
+    /// scripts owned by someone other than the login, e.g. when an admin looks
+    /// at a user's scripts through a proxy, are taken as read-only. Without
+    /// `OWNER` the scripts are assumed to be the login's own.
+    pub fn is_writable(&self, username: &str) -> bool {
+        self.owner
+            .as_ref()
+            .is_none_or(|owner| owner.eq_ignore_ascii_case(username))
+    }
+}
+
+// Shares the `Arc` of a capability snapshot and derefs to its SASL list
+struct SaslMechanisms(Arc<Capabilities>);
+
+impl Deref for SaslMechanisms {
+    type Target = [String];
+
+    fn deref(&self) -> &[String] {
+        &self.0.sasl
+    }
+}
+
 impl Default for Capabilities {
     fn default() -> Self {
         Self {
@@ -50,17 +104,264 @@ impl Default for Capabilities {
     }
 }
 
+/// How [`SieveClient`] secures the connection to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsMode {
+    /// Connect in plain text and upgrade with `STARTTLS`, as RFC 5804 intends.
+    #[default]
+    StartTls,
+    /// Negotiate TLS right after connecting, for servers behind a TLS proxy.
+    Implicit,
+    /// Never use TLS. Credentials are only sent if
+    /// [`require_tls`](ConnectOptions::require_tls) is turned off.
+    None,
+}
+
+/// How the username and password are normalized before they are sent with SASL
+/// PLAIN or LOGIN. SCRAM-SHA-256 always prepares them with SASLprep, as RFC 7677
+/// requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CredentialNormalization {
+    /// Send the credentials exactly as given.
+    #[default]
+    None,
+    /// Compose the credentials to Unicode Normalization Form C.
+    Nfc,
+    /// Prepare the credentials with the SASLprep profile of stringprep (RFC 4013),
+    /// which is what RFC 5804 asks servers to compare against.
+    SaslPrep,
+}
+
+impl CredentialNormalization {
+    pub(crate) fn apply<'a>(&self, value: &'a str) -> Result<Cow<'a, str>, ConnectError> {
+        match self {
+            Self::None => Ok(Cow::Borrowed(value)),
+            Self::Nfc => Ok(Cow::Owned(value.nfc().collect())),
+            Self::SaslPrep => stringprep::saslprep(value).map_err(|err| {
+                ConnectError::AuthenticationFailed(format!(
+                    "Credentials can't be prepared with SASLprep: {}",
+                    err
+                ))
+            }),
+        }
+    }
+}
+
+/// Options controlling how [`SieveClient::connect_with_options`] sets up a session.
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    /// Refuse to send credentials unless the connection is protected by TLS.
+    pub require_tls: bool,
+    /// How the connection is secured.
+    pub tls_mode: TlsMode,
+    /// Trust any server certificate without validating it, e.g. a self-signed
+    /// one in a lab. This leaves the connection open to interception, so a
+    /// warning is logged whenever it is used. Ignored when
+    /// [`pinned_certificate`](Self::pinned_certificate) is set.
+    pub insecure_skip_verify: bool,
+    /// Trust only the server certificate with this SHA-256 fingerprint instead of
+    /// validating its chain, see [`certificate_fingerprint`](crate::certificate_fingerprint).
+    pub pinned_certificate: Option<[u8; 32]>,
+    /// Trust anchors used to validate the server certificate when it isn't pinned.
+    pub cert_store: CertStore,
+    /// Trusted in addition to [`cert_store`](Self::cert_store), e.g. the CA of a
+    /// private mail server, see [`parse_certificates`](crate::parse_certificates).
+    pub extra_root_certificates: Vec<CertificateDer<'static>>,
+    /// Send a `NOOP` at this interval while the client is alive so idle sessions
+    /// aren't closed by the server.
+    pub keepalive: Option<Duration>,
+    /// Normalization applied to the username and password before authenticating
+    /// with PLAIN or LOGIN, see [`CredentialNormalization`].
+    pub credential_normalization: CredentialNormalization,
+    /// How many sessions to open, so commands from different tasks (e.g. a
+    /// background refresh and a save) run in parallel instead of waiting for each
+    /// other. Every session does its own TLS handshake and authentication.
+    /// Values below 1 are treated as 1.
+    pub pool_size: usize,
+    /// Connect from this local address, e.g. to go out through a particular
+    /// interface or VPN on a machine with several. `None` leaves the choice to
+    /// the system.
+    pub local_address: Option<IpAddr>,
+    /// Present this certificate when the server asks for one. If the server
+    /// offers SASL `EXTERNAL`, the login uses the identity of the certificate
+    /// instead of the credentials.
+    pub client_identity: Option<ClientIdentity>,
+    /// With STARTTLS, refuse the connection when SASL mechanisms or Sieve
+    /// extensions offered before TLS are no longer offered afterwards, a sign the
+    /// connection is being tampered with. Anything added after TLS, e.g. PLAIN,
+    /// is expected and accepted. On by default, only meant to be turned off for
+    /// servers known to drop capabilities once TLS is up.
+    pub compare_tls_capabilities: bool,
+    /// Give up on a command when the server hasn't answered it completely
+    /// within this time, with [`ManageSieveError::Timeout`]. `None` waits forever.
+    pub command_timeout: Option<Duration>,
+    /// The name sent in SNI and checked against the server certificate, when
+    /// it isn't the host connected to, e.g. through an SSH tunnel to
+    /// `localhost` or when connecting to an IP address.
+    pub tls_server_name: Option<String>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            require_tls: true,
+            tls_mode: TlsMode::default(),
+            insecure_skip_verify: false,
+            pinned_certificate: None,
+            cert_store: CertStore::default(),
+            extra_root_certificates: Vec::new(),
+            keepalive: None,
+            credential_normalization: CredentialNormalization::default(),
+            pool_size: 1,
+            local_address: None,
+            client_identity: None,
+            compare_tls_capabilities: true,
+            command_timeout: None,
+            tls_server_name: None,
+        }
+    }
+}
+
 pub struct SieveClient {
-    connection: Mutex<(BufReader<TlsReader>, TlsWriter)>,
+    // The pool of sessions, never empty. Capabilities and certificates are
+    // those of the first one.
+    connections: Vec<Arc<Mutex<Connection>>>,
+    // Where to wait when every session is busy, so waiting commands spread
+    next_connection: AtomicUsize,
+    // Replaced as a whole by `reconnect`, so a `&self` is enough
+    server: RwLock<Server>,
+    options: ConnectOptions,
+    // Kept so the session can be re-established by `reconnect`
+    host: String,
+    port: u16,
+    // `None` for sessions opened by `connect_anonymous`
+    credentials: Option<(String, SecretString)>,
+    authenticated: bool,
+    keepalive: Option<JoinHandle<()>>,
+    // Set by `logout`, so dropping the client doesn't log out a second time
+    logged_out: AtomicBool,
+    // Protocol traffic of every session is logged within this span
+    span: Span,
+}
+
+// What setting up the sessions told about the server
+struct Server {
+    capabilities: Arc<Capabilities>,
+    tls_active: bool,
+    peer_certificates: Vec<CertificateDer<'static>>,
+    cert_store: Option<CertStore>,
+    // The SASL mechanism `authenticated` was reached with
+    mechanism: Option<&'static str>,
+}
+
+impl Server {
+    fn new(established: Established, mechanism: Option<&'static str>) -> (Self, Connection) {
+        let server = Self {
+            capabilities: Arc::new(established.capabilities),
+            tls_active: established.tls_active,
+            peer_certificates: established.peer_certificates,
+            cert_store: established.cert_store,
+            mechanism,
+        };
+        (server, established.connection)
+    }
+}
+
+// Upper bound for `ConnectOptions::pool_size`, servers limit the sessions per user
+const MAX_POOL_SIZE: usize = 8;
+
+// Everything produced by setting up the transport, before authentication
+struct Established {
+    connection: Connection,
     capabilities: Capabilities,
+    peer_certificates: Vec<CertificateDer<'static>>,
+    cert_store: Option<CertStore>,
+    tls_active: bool,
+}
+
+// How long a dropped client waits for the server to acknowledge LOGOUT
+const LOGOUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Drop for SieveClient {
+    fn drop(&mut self) {
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.abort();
+        }
+        if *self.logged_out.get_mut() {
+            return;
+        }
+
+        // `drop` can't wait for the server, so the session is closed in the
+        // background. Outside of a runtime the socket is just closed.
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            for connection in self.connections.drain(..) {
+                runtime.spawn(async move {
+                    let mut connection = connection.lock().await;
+                    let _ =
+                        tokio::time::timeout(LOGOUT_TIMEOUT, Self::close(&mut connection)).await;
+                });
+            }
+        }
+    }
 }
 
+// Leaves out the password
 impl Debug for SieveClient {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("SieveClient")
+        f.debug_struct("SieveClient")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field(
+                "username",
+                &self.credentials.as_ref().map(|(username, _)| username),
+            )
+            .field("authenticated", &self.authenticated)
+            .finish_non_exhaustive()
     }
 }
 
+// Connects from `local_address` if one is set, trying the server's addresses of
+// the same IP version in turn like `TcpStream::connect` does
+async fn connect_tcp(address: &str, local_address: Option<IpAddr>) -> io::Result<TcpStream> {
+    let Some(local_address) = local_address else {
+        return TcpStream::connect(address).await;
+    };
+
+    let mut last_error = None;
+    for remote in tokio::net::lookup_host(address).await? {
+        if remote.is_ipv4() != local_address.is_ipv4() {
+            continue;
+        }
+
+        let socket = if remote.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket.bind(SocketAddr::new(local_address, 0))?;
+        match socket.connect(remote).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!(
+                "{} has no {} address to connect to from {}",
+                address,
+                if local_address.is_ipv4() {
+                    "IPv4"
+                } else {
+                    "IPv6"
+                },
+                local_address
+            ),
+        )
+    }))
+}
+
 #[derive(Debug, Error)]
 pub enum ConnectError {
     #[error("Connection failed: {0}")]
@@ -73,18 +374,217 @@ pub enum ConnectError {
     AuthenticationFailed(String),
 }
 
+/// The server's verdict on a script it accepted in `CHECKSCRIPT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckResult {
+    /// A plain `OK`
+    Clean,
+    /// `OK (WARNINGS)`, with the warnings text, which servers may leave out
+    Warnings { message: Option<String> },
+}
+
+/// A response code (RFC 5804 section 1.3), the machine readable reason the
+/// server gives in parentheses after `OK`, `NO` or `BYE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseCode {
+    /// A quota would be exceeded, without saying which one
+    Quota,
+    /// The maximum number of scripts is reached
+    QuotaMaxScripts,
+    /// The script is larger than the server allows
+    QuotaMaxSize,
+    /// The active script can't be deleted
+    Active,
+    /// The script doesn't exist
+    NonExistent,
+    /// A script with the new name exists already
+    AlreadyExists,
+    /// A temporary failure, the command may work when sent again later
+    TryLater,
+    /// The string given to `NOOP`, echoed back
+    Tag(String),
+    /// `CHECKSCRIPT` or `PUTSCRIPT` accepted the script with warnings
+    Warnings,
+    /// Base64 encoded data that completes a SASL exchange
+    Sasl(String),
+    /// Any other code, as sent
+    Other(String),
+}
+
+impl ResponseCode {
+    /// Parses the code between the parentheses, e.g. `QUOTA/MAXSIZE` or
+    /// `TAG "x"`. Names are case-insensitive.
+    pub fn parse(code: &str) -> Self {
+        let code = code.trim();
+        let (name, argument) = code
+            .split_once(' ')
+            .map_or((code, ""), |(name, argument)| (name, argument.trim()));
+        let string = || parse_quoted_string(argument).ok().map(|(_, value)| value);
+
+        match name.to_ascii_uppercase().as_str() {
+            "QUOTA/MAXSCRIPTS" => Self::QuotaMaxScripts,
+            "QUOTA/MAXSIZE" => Self::QuotaMaxSize,
+            // Sub-codes not defined yet are still about a quota
+            name if name == "QUOTA" || name.starts_with("QUOTA/") => Self::Quota,
+            "ACTIVE" => Self::Active,
+            "NONEXISTENT" => Self::NonExistent,
+            "ALREADYEXISTS" => Self::AlreadyExists,
+            "TRYLATER" => Self::TryLater,
+            "WARNINGS" => Self::Warnings,
+            "TAG" => string().map_or_else(|| Self::Other(code.to_string()), Self::Tag),
+            // Some servers leave the data unquoted
+            "SASL" => Self::Sasl(string().unwrap_or_else(|| argument.to_string())),
+            _ => Self::Other(code.to_string()),
+        }
+    }
+
+    /// Whether this is `QUOTA` or one of its sub-codes
+    pub fn is_quota(&self) -> bool {
+        matches!(
+            self,
+            Self::Quota | Self::QuotaMaxScripts | Self::QuotaMaxSize
+        )
+    }
+}
+
+// As it would be sent by the server
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Quota => f.write_str("QUOTA"),
+            Self::QuotaMaxScripts => f.write_str("QUOTA/MAXSCRIPTS"),
+            Self::QuotaMaxSize => f.write_str("QUOTA/MAXSIZE"),
+            Self::Active => f.write_str("ACTIVE"),
+            Self::NonExistent => f.write_str("NONEXISTENT"),
+            Self::AlreadyExists => f.write_str("ALREADYEXISTS"),
+            Self::TryLater => f.write_str("TRYLATER"),
+            Self::Warnings => f.write_str("WARNINGS"),
+            Self::Tag(tag) => write!(f, "TAG {}", quote_string(tag)),
+            Self::Sasl(data) => write!(f, "SASL \"{}\"", data),
+            Self::Other(code) => f.write_str(code),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ManageSieveError {
     #[error("IO error: {0}")]
     IoError(#[from] io::Error),
     #[error("Protocol error: {0}")]
     ProtocolError(String),
-    #[error("Server error: {0}")]
-    ServerError(String),
+    /// The server refused the command. `message` is the human readable text of
+    /// the response and `code` its response code, e.g. [`ResponseCode::TryLater`].
+    #[error("Server error: {message}")]
+    ServerError {
+        code: Option<ResponseCode>,
+        message: String,
+    },
     #[error("Script not found: {0}")]
     ScriptNotFound(String),
+    /// The script is the active one, which can't be deleted
+    #[error("Script is active: {0}")]
+    ScriptActive(String),
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
+    #[error("Invalid script name: {0}")]
+    InvalidScriptName(String),
+    /// Any refusal with a `QUOTA` response code. `code` tells which quota, e.g.
+    /// [`ResponseCode::QuotaMaxScripts`] or [`ResponseCode::QuotaMaxSize`].
+    #[error("Quota exceeded: {message}")]
+    Quota { code: ResponseCode, message: String },
+    /// The server answered with `BYE` and ended the session, e.g. because it
+    /// is shutting down or the connection was idle for too long
+    #[error("Server closed the connection: {0}")]
+    ServerClosed(String),
+    /// The server didn't finish its response within
+    /// [`ConnectOptions::command_timeout`]. The session is unusable afterwards,
+    /// see [`SieveClient::reconnect`].
+    #[error("No response from the server within {} seconds", .0.as_secs())]
+    Timeout(Duration),
+    /// The response didn't have the announced shape, e.g. a script longer than
+    /// its literal, so there is no telling where the next one starts. The
+    /// session is unusable afterwards, like after [`ManageSieveError::Timeout`].
+    #[error("Response out of sync: {0}")]
+    OutOfSync(String),
+}
+
+/// [`ConnectError`] and [`ManageSieveError`] combined, for applications that
+/// show errors to users. The messages avoid protocol details and the variants
+/// tell apart what the user can do about it.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SieveError {
+    #[error("Could not reach the server: {0}")]
+    Network(String),
+    #[error("Secure connection failed: {0}")]
+    Tls(String),
+    #[error("Login failed: {0}")]
+    Authentication(String),
+    #[error("Not enough space on the server: {0}")]
+    Quota(String),
+    #[error("The script \"{0}\" does not exist")]
+    ScriptNotFound(String),
+    #[error("The script \"{0}\" is active, activate another one before deleting it")]
+    ScriptActive(String),
+    #[error("Invalid script name: {0}")]
+    InvalidScriptName(String),
+    #[error("The server refused the request: {0}")]
+    Server(String),
+    #[error("The server sent an unexpected reply: {0}")]
+    Protocol(String),
+    #[error("The server closed the connection: {0}")]
+    Disconnected(String),
+    #[error("The server stopped responding: {0}")]
+    Timeout(String),
+}
+
+impl From<ConnectError> for SieveError {
+    fn from(err: ConnectError) -> Self {
+        match err {
+            ConnectError::ConnectionFailed(err) => Self::Network(err.to_string()),
+            ConnectError::ProtocolError(message) => Self::Protocol(message),
+            ConnectError::TlsError(err) => Self::Tls(err.to_string()),
+            ConnectError::AuthenticationFailed(message) => Self::Authentication(message),
+        }
+    }
+}
+
+impl From<ManageSieveError> for SieveError {
+    fn from(err: ManageSieveError) -> Self {
+        match err {
+            ManageSieveError::IoError(err) => Self::Network(err.to_string()),
+            ManageSieveError::ProtocolError(message)
+            | ManageSieveError::InvalidResponse(message) => Self::Protocol(message),
+            ManageSieveError::ServerError { message, .. } => Self::Server(message),
+            ManageSieveError::ScriptNotFound(script) => Self::ScriptNotFound(script),
+            ManageSieveError::ScriptActive(script) => Self::ScriptActive(script),
+            ManageSieveError::InvalidScriptName(reason) => Self::InvalidScriptName(reason),
+            ManageSieveError::Quota { message, .. } => Self::Quota(message),
+            ManageSieveError::ServerClosed(message) => Self::Disconnected(message),
+            err @ ManageSieveError::Timeout(_) => Self::Timeout(err.to_string()),
+            ManageSieveError::OutOfSync(message) => Self::Protocol(message),
+        }
+    }
+}
+
+/// Checks `name` against the restrictions RFC 5804 section 1.6 places on
+/// script names, so a malformed command is never sent. Every command method
+/// taking a script name calls this.
+pub fn validate_script_name(name: &str) -> Result<(), ManageSieveError> {
+    if name.is_empty() {
+        return Err(ManageSieveError::InvalidScriptName(
+            "name must not be empty".to_string(),
+        ));
+    }
+    if name
+        .chars()
+        .any(|c| c.is_control() || matches!(c, '\u{2028}' | '\u{2029}'))
+    {
+        return Err(ManageSieveError::InvalidScriptName(
+            "name must not contain control characters or line breaks".to_string(),
+        ));
+    }
+
+    Ok(())
 }
 
 impl SieveClient {
@@ -94,390 +594,959 @@ impl SieveClient {
         username: &str,
         password: &str,
     ) -> Result<Self, ConnectError> {
-        // Connect to specified host and port
-        let address = format!("{}:{}", host, port);
-
-        // Establish TCP connection
-        let mut stream = TcpStream::connect(&address).await?;
-
-        // Ignore initial capabilities greeting - just read until OK
-        Self::ignore_initial_greeting(&mut stream).await?;
-
-        // Send STARTTLS command immediately
-        stream.write_all(b"STARTTLS\r\n").await?;
-        stream.flush().await?;
-
-        // Read STARTTLS response
-        let mut reader = BufReader::new(&mut stream);
-        let mut response = String::new();
-        reader.read_line(&mut response).await?;
-
-        if !response.trim().to_uppercase().starts_with("OK") {
-            return Err(ConnectError::ProtocolError(format!(
-                "STARTTLS failed: {}",
-                response.trim()
-            )));
-        }
-
-        // Set up TLS configuration
-        let mut root_store = RootCertStore::empty();
-        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-
-        let config = ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
+        Self::connect_with_options(host, port, username, password, ConnectOptions::default()).await
+    }
 
-        let connector = TlsConnector::from(Arc::new(config));
-        let domain = ServerName::try_from(host.as_str())
-            .map_err(|_| ConnectError::ProtocolError("Invalid hostname".to_string()))?
-            .to_owned();
+    pub async fn connect_with_options(
+        host: String,
+        port: u16,
+        username: &str,
+        password: &str,
+        options: ConnectOptions,
+    ) -> Result<Self, ConnectError> {
+        let credentials = (username.to_string(), SecretString::from(password));
+        Self::open(host, port, Some(credentials), options).await
+    }
 
-        // Perform TLS handshake
-        let tls_stream = connector.connect(domain, stream).await?;
+    /// Connects without credentials, for servers that don't require them.
+    ///
+    /// Authenticates with SASL `ANONYMOUS` if the server offers it. A server that
+    /// advertises no SASL mechanisms at all is taken to not need authentication
+    /// and is used as is.
+    pub async fn connect_anonymous(
+        host: String,
+        port: u16,
+        options: ConnectOptions,
+    ) -> Result<Self, ConnectError> {
+        Self::open(host, port, None, options).await
+    }
 
-        // Split the TLS stream
-        let (tls_read, tls_write) = tokio::io::split(tls_stream);
-        let mut tls_reader = BufReader::new(tls_read);
+    /// Checks that a server is reachable without logging in: connects, does the
+    /// TLS handshake `options` asks for, reads the capabilities and logs out.
+    #[instrument(name = "probe", level = "debug", skip_all, fields(host = %host, port = port))]
+    pub async fn probe(
+        host: &str,
+        port: u16,
+        options: &ConnectOptions,
+    ) -> Result<Capabilities, ConnectError> {
+        let mut established = Self::establish(host, port, options).await?;
+        // The server was reachable, how the goodbye went doesn't matter
+        let _ = Self::close(&mut established.connection).await;
+        Ok(established.capabilities)
+    }
 
-        // Read capabilities after TLS
-        let capabilities = Self::read_capabilities(&mut tls_reader).await?;
+    #[instrument(name = "connection", level = "debug", skip_all, fields(host = %host, port = port))]
+    async fn open(
+        host: String,
+        port: u16,
+        credentials: Option<(String, SecretString)>,
+        options: ConnectOptions,
+    ) -> Result<Self, ConnectError> {
+        let (server, connection) = Server::new(Self::establish(&host, port, &options).await?, None);
 
         // Create the client instance
-        let client = SieveClient {
-            connection: Mutex::new((tls_reader, tls_write)),
-            capabilities,
+        let mut client = SieveClient {
+            connections: vec![Arc::new(Mutex::new(connection))],
+            next_connection: AtomicUsize::new(0),
+            server: RwLock::new(server),
+            options,
+            host,
+            port,
+            credentials,
+            authenticated: false,
+            keepalive: None,
+            logged_out: AtomicBool::new(false),
+            span: Span::current(),
         };
 
         // Authenticate with the server
-        client.authenticate(username, password).await?;
+        let (capabilities, tls_active) = (client.capabilities(), client.is_tls_active());
+        let mechanism = client
+            .authenticate_connection(
+                &mut *client.connections[0].lock().await,
+                &capabilities,
+                tls_active,
+                client.credentials.as_ref(),
+            )
+            .await?;
+        client.server_mut().mechanism = mechanism;
+        client.authenticated = true;
+
+        // The rest of the pool goes through the same setup, one at a time
+        for _ in 1..client.options.pool_size.clamp(1, MAX_POOL_SIZE) {
+            let mut established =
+                Self::establish(&client.host, client.port, &client.options).await?;
+            client
+                .authenticate_connection(
+                    &mut established.connection,
+                    &established.capabilities,
+                    established.tls_active,
+                    client.credentials.as_ref(),
+                )
+                .await?;
+            client
+                .connections
+                .push(Arc::new(Mutex::new(established.connection)));
+        }
+
+        if let Some(interval) = client.options.keepalive {
+            client.keepalive = Some(Self::spawn_keepalive(
+                client.connections.iter().map(Arc::downgrade).collect(),
+                interval,
+                client.options.command_timeout,
+                client.span.clone(),
+            ));
+        }
 
         Ok(client)
     }
 
-    pub async fn list_scripts(&self) -> Result<Vec<(String, bool)>, ManageSieveError> {
-        let mut connection = self.connection.lock().await;
-        let (reader, writer) = &mut *connection;
+    /// Drops the current connection and establishes a new one, authenticating
+    /// again with the credentials given to `connect`, e.g. after the network
+    /// dropped or a command timed out.
+    ///
+    /// Every session of the pool is set up before any is replaced, so when
+    /// this fails the client is left as it was. Commands already in progress on
+    /// the old connection still fail, they are not repeated. Commands waiting
+    /// for the connection will run on the new session once this returns.
+    /// Capabilities and certificate details are those of the new session.
+    #[instrument(name = "reconnect", level = "debug", parent = &self.span, skip_all)]
+    pub async fn reconnect(&self) -> Result<(), ConnectError> {
+        let mut server = None;
+        let mut connections = Vec::with_capacity(self.connections.len());
+        for _ in &self.connections {
+            let mut established = Self::establish(&self.host, self.port, &self.options).await?;
+            let mechanism = if self.authenticated {
+                self.authenticate_connection(
+                    &mut established.connection,
+                    &established.capabilities,
+                    established.tls_active,
+                    self.credentials.as_ref(),
+                )
+                .await?
+            } else {
+                None
+            };
 
-        // Send LISTSCRIPTS command
-        writer.write_all(b"LISTSCRIPTS\r\n").await?;
-        writer.flush().await?;
+            // The capabilities are those of the first session, see `connections`
+            let (session, connection) = Server::new(established, mechanism);
+            server.get_or_insert(session);
+            connections.push(connection);
+        }
 
-        let mut scripts = Vec::new();
-        let mut response = String::new();
+        // Swapped while holding every session, so no command sees the new
+        // sessions with the details of the old ones
+        let mut guards = Vec::with_capacity(self.connections.len());
+        for connection in &self.connections {
+            guards.push(connection.lock().await);
+        }
+        for (guard, connection) in guards.iter_mut().zip(connections) {
+            **guard = connection;
+        }
+        if let Some(server) = server {
+            *self.server.write().unwrap_or_else(PoisonError::into_inner) = server;
+        }
 
-        loop {
-            response.clear();
-            reader.read_line(&mut response).await?;
-            let line = response.trim();
+        Ok(())
+    }
 
-            if line.is_empty() {
-                continue;
+    // Takes an idle session from the pool, or queues up for the next one in
+    // turn when all of them are busy
+    async fn connection(&self) -> MutexGuard<'_, Connection> {
+        for connection in &self.connections {
+            if let Ok(connection) = connection.try_lock() {
+                return connection;
             }
+        }
 
-            let line_upper = line.to_uppercase();
-            if line_upper.starts_with("OK") {
-                break;
-            } else if line_upper.starts_with("NO") || line_upper.starts_with("BYE") {
-                return Err(ManageSieveError::ServerError(line.to_string()));
-            } else if line.starts_with("\"") {
-                // Parse quoted script name
-                if let Some(script_name) = self.parse_script_line(line) {
-                    scripts.push(script_name);
+        let next = self.next_connection.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+        self.connections[next].lock().await
+    }
+
+    /// Authenticates the session as another user after [`SieveClient::unauthenticate`].
+    ///
+    /// Every session of the pool is authenticated. If one of them fails, those
+    /// already logged in are unauthenticated again and the client keeps the
+    /// credentials it had before.
+    #[instrument(name = "authenticate", level = "debug", parent = &self.span, skip_all)]
+    pub async fn authenticate(
+        &mut self,
+        username: &str,
+        password: &str,
+    ) -> Result<(), ConnectError> {
+        if self.authenticated {
+            return Err(ConnectError::AuthenticationFailed(
+                "Already authenticated, call unauthenticate first".to_string(),
+            ));
+        }
+
+        let credentials = (username.to_string(), SecretString::from(password));
+        let (capabilities, tls_active) = (self.capabilities(), self.is_tls_active());
+
+        // Every session of the pool has to end up logged in as the same user
+        let mut guards = Vec::with_capacity(self.connections.len());
+        for connection in &self.connections {
+            guards.push(connection.lock().await);
+        }
+        let mut mechanism = None;
+        for index in 0..guards.len() {
+            let result = self
+                .authenticate_connection(
+                    &mut guards[index],
+                    &capabilities,
+                    tls_active,
+                    Some(&credentials),
+                )
+                .await;
+            match result {
+                Ok(used) => mechanism = used,
+                Err(err) => {
+                    // A rejected login leaves the session as it was, anything
+                    // else may have stopped halfway through the exchange
+                    if !matches!(err, ConnectError::AuthenticationFailed(_)) {
+                        Self::discard(&mut guards[index]);
+                    }
+                    // The sessions logged in already go back to the
+                    // non-authenticated state, or are dropped if they can't
+                    for guard in guards[..index].iter_mut() {
+                        if Self::send_unauthenticate(guard).await.is_err() {
+                            Self::discard(guard);
+                        }
+                    }
+                    return Err(err);
                 }
             }
         }
+        drop(guards);
 
-        Ok(scripts)
-    }
+        // Only kept once accepted, `reconnect` logs in with them again
+        self.credentials = Some(credentials);
+        self.server_mut().mechanism = mechanism;
+        self.authenticated = true;
 
-    pub async fn get_script(&self, script: &str) -> Result<String, ManageSieveError> {
-        let mut connection = self.connection.lock().await;
-        let (reader, writer) = &mut *connection;
+        Ok(())
+    }
 
-        // Send GETSCRIPT command
-        let command = format!("GETSCRIPT \"{}\"\r\n", script);
-        writer.write_all(command.as_bytes()).await?;
-        writer.flush().await?;
+    /// Returns the session to the non-authenticated state (RFC 5804 section 2.14.1)
+    /// so it can be authenticated again as a different user.
+    ///
+    /// Every session of the pool is unauthenticated. If one of them fails, the
+    /// others may already be unauthenticated, so all of them are discarded and
+    /// commands fail until [`SieveClient::reconnect`] logs in again as before.
+    #[instrument(name = "unauthenticate", level = "debug", parent = &self.span, skip_all)]
+    pub async fn unauthenticate(&mut self) -> Result<(), ManageSieveError> {
+        if !self.capabilities().has_capability("UNAUTHENTICATE") {
+            return Err(ManageSieveError::ProtocolError(
+                "Server does not support UNAUTHENTICATE".to_string(),
+            ));
+        }
+        if !self.authenticated {
+            return Err(ManageSieveError::ProtocolError(
+                "Session is not authenticated".to_string(),
+            ));
+        }
 
-        let mut response = String::new();
-        reader.read_line(&mut response).await?;
-        let line = response.trim();
-
-        // Check if we got a literal string response
-        if line.starts_with("{") {
-            // Parse literal string length
-            if let Some(length) = self.parse_literal_length(line) {
-                // Read the exact number of bytes for the script content
-                let mut script_content = vec![0u8; length];
-                reader.read_exact(&mut script_content).await?;
-
-                // Read the CRLF that follows the literal content
-                let mut crlf = [0u8; 2];
-                reader.read_exact(&mut crlf).await?;
-
-                // Read the final OK response line
-                response.clear();
-                reader.read_line(&mut response).await?;
-                let final_line = response.trim().to_uppercase();
-
-                if final_line.starts_with("OK") {
-                    return Ok(String::from_utf8_lossy(&script_content).to_string());
-                } else {
-                    return Err(ManageSieveError::ServerError(final_line.to_string()));
+        // Every session of the pool has to be back in the same state
+        let mut guards = Vec::with_capacity(self.connections.len());
+        for connection in &self.connections {
+            guards.push(connection.lock().await);
+        }
+        for guard in guards.iter_mut() {
+            if let Err(err) = Self::send_unauthenticate(guard).await {
+                for guard in guards.iter_mut() {
+                    Self::discard(guard);
                 }
-            } else {
-                return Err(ManageSieveError::ProtocolError(
-                    "Invalid literal length format".to_string(),
-                ));
+                return Err(err);
             }
         }
+        drop(guards);
 
-        // Handle non-literal responses (errors)
-        let line_upper = line.to_uppercase();
-        if line_upper.starts_with("NO") {
-            Err(ManageSieveError::ScriptNotFound(script.to_string()))
-        } else if line_upper.starts_with("BYE") {
-            Err(ManageSieveError::ServerError(line.to_string()))
-        } else {
-            Err(ManageSieveError::InvalidResponse(line.to_string()))
-        }
+        self.authenticated = false;
+        self.server_mut().mechanism = None;
+        Ok(())
     }
 
-    pub async fn put_script(&self, script: &str, content: &str) -> Result<(), ManageSieveError> {
-        let mut connection = self.connection.lock().await;
-        let (reader, writer) = &mut *connection;
+    async fn send_unauthenticate(connection: &mut Connection) -> Result<(), ManageSieveError> {
+        let (reader, writer) = connection;
 
-        // Send PUTSCRIPT command with literal string
-        let command = format!("PUTSCRIPT \"{}\" {{{}}}\r\n", script, content.len());
-        writer.write_all(command.as_bytes()).await?;
-        writer.write_all(content.as_bytes()).await?;
+        send_command(writer, "UNAUTHENTICATE\r\n").await?;
         writer.flush().await?;
 
-        let mut response = String::new();
-        reader.read_line(&mut response).await?;
-        let line = response.trim().to_uppercase();
-
-        if line.starts_with("OK") {
-            Ok(())
-        } else if line.starts_with("NO") {
-            Err(ManageSieveError::ServerError(response.trim().to_string()))
-        } else if line.starts_with("BYE") {
-            Err(ManageSieveError::ServerError(response.trim().to_string()))
-        } else {
-            Err(ManageSieveError::InvalidResponse(
-                response.trim().to_string(),
-            ))
+        let response = read_response(reader).await?;
+        match response.status {
+            Status::Ok => Ok(()),
+            Status::No | Status::Bye => Err(response.error()),
         }
     }
 
-    pub async fn delete_script(&self, script: &str) -> Result<(), ManageSieveError> {
-        let mut connection = self.connection.lock().await;
-        let (reader, writer) = &mut *connection;
+    /// Ends every session with `LOGOUT` (RFC 5804 section 2.3) and waits for
+    /// the server to acknowledge it. Commands sent afterwards fail.
+    ///
+    /// Dropping the client logs out as well, but in the background and without
+    /// reporting whether it worked.
+    #[instrument(name = "logout", level = "debug", parent = &self.span, skip_all)]
+    pub async fn logout(&self) -> Result<(), ManageSieveError> {
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.abort();
+        }
+        self.logged_out.store(true, Ordering::Relaxed);
+
+        let mut result = Ok(());
+        for connection in &self.connections {
+            let closed = Self::close(&mut *connection.lock().await).await;
+            // Every session is closed even if one of them fails
+            if result.is_ok() {
+                result = closed;
+            }
+        }
+        result
+    }
 
-        // Send DELETESCRIPT command
-        let command = format!("DELETESCRIPT \"{}\"\r\n", script);
-        writer.write_all(command.as_bytes()).await?;
-        writer.flush().await?;
+    // Sends a NOOP on every session every `interval` until the client is
+    // dropped. Sessions in the middle of a command are busy anyway and skipped,
+    // holding the lock keeps the NOOP from interleaving with other commands.
+    fn spawn_keepalive(
+        connections: Vec<Weak<Mutex<Connection>>>,
+        interval: Duration,
+        command_timeout: Option<Duration>,
+        span: Span,
+    ) -> JoinHandle<()> {
+        tokio::spawn(
+            async move {
+                let mut ticker = tokio::time::interval(interval);
+                // The first tick completes immediately, right after authenticating
+                ticker.tick().await;
+
+                loop {
+                    ticker.tick().await;
+
+                    for connection in &connections {
+                        let Some(connection) = connection.upgrade() else {
+                            return;
+                        };
+                        let Ok(mut connection) = connection.try_lock() else {
+                            continue;
+                        };
+                        let result = Self::with_timeout(
+                            command_timeout,
+                            Self::send_noop(&mut connection, None),
+                        )
+                        .await;
+                        // A broken session is left to `reconnect`, the keepalive
+                        // goes on with it afterwards
+                        let _ = Self::discard_if_broken(&mut connection, result);
+                    }
+                }
+            }
+            .instrument(span),
+        )
+    }
 
-        let mut response = String::new();
-        reader.read_line(&mut response).await?;
-        let line = response.trim().to_uppercase();
+    // Runs `command` within the command timeout and the client's span
+    async fn timed<T>(
+        &self,
+        command: impl Future<Output = Result<T, ManageSieveError>>,
+    ) -> Result<T, ManageSieveError> {
+        Self::with_timeout(self.options.command_timeout, command)
+            .instrument(self.span.clone())
+            .await
+    }
 
-        if line.starts_with("OK") {
-            Ok(())
-        } else if line.starts_with("NO") {
-            Err(ManageSieveError::ScriptNotFound(script.to_string()))
-        } else if line.starts_with("BYE") {
-            Err(ManageSieveError::ServerError(response.trim().to_string()))
-        } else {
-            Err(ManageSieveError::InvalidResponse(
-                response.trim().to_string(),
-            ))
+    // Gives up on `command` after `timeout`, see `ConnectOptions::command_timeout`
+    async fn with_timeout<T>(
+        timeout: Option<Duration>,
+        command: impl Future<Output = Result<T, ManageSieveError>>,
+    ) -> Result<T, ManageSieveError> {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, command)
+                .await
+                .unwrap_or(Err(ManageSieveError::Timeout(timeout))),
+            None => command.await,
         }
     }
 
-    pub async fn rename_script(
-        &self,
-        old_name: &str,
-        new_name: &str,
-    ) -> Result<(), ManageSieveError> {
-        let mut connection = self.connection.lock().await;
-        let (reader, writer) = &mut *connection;
-
-        // Send RENAMESCRIPT command
-        let command = format!("RENAMESCRIPT \"{}\" \"{}\"\r\n", old_name, new_name);
-        writer.write_all(command.as_bytes()).await?;
-        writer.flush().await?;
+    // A session that timed out may still get the rest of the response, and one
+    // out of sync has some of it left, which would be taken for the answer to the
+    // next command. It is replaced by one that fails every command until
+    // `reconnect`.
+    fn discard_if_broken<T>(
+        connection: &mut Connection,
+        result: Result<T, ManageSieveError>,
+    ) -> Result<T, ManageSieveError> {
+        if let Err(ManageSieveError::Timeout(_) | ManageSieveError::OutOfSync(_)) = result {
+            Self::discard(connection);
+        }
+        result
+    }
 
-        let mut response = String::new();
-        reader.read_line(&mut response).await?;
-        let line = response.trim().to_uppercase();
+    fn discard(connection: &mut Connection) {
+        *connection = (
+            BufReader::new(Box::new(tokio::io::empty())),
+            Box::new(tokio::io::sink()),
+        );
+    }
 
-        if line.starts_with("OK") {
-            Ok(())
-        } else if line.starts_with("NO") {
-            Err(ManageSieveError::ScriptNotFound(old_name.to_string()))
-        } else if line.starts_with("BYE") {
-            Err(ManageSieveError::ServerError(response.trim().to_string()))
-        } else {
-            Err(ManageSieveError::InvalidResponse(
-                response.trim().to_string(),
-            ))
-        }
+    /// Sends `NOOP`, e.g. on a timer so the server doesn't close an idle
+    /// session. With a `tag` the server has to echo it in a `TAG` response code
+    /// (RFC 5804 section 2.13), which is checked.
+    ///
+    /// [`ConnectOptions::keepalive`] does this in the background.
+    pub async fn noop(&self, tag: Option<&str>) -> Result<(), ManageSieveError> {
+        let mut connection = self.connection().await;
+        let result = self.timed(Self::send_noop(&mut connection, tag)).await;
+        Self::discard_if_broken(&mut connection, result)
     }
 
-    pub async fn set_active_script(&self, script: &str) -> Result<(), ManageSieveError> {
-        let mut connection = self.connection.lock().await;
-        let (reader, writer) = &mut *connection;
+    async fn send_noop(
+        connection: &mut Connection,
+        tag: Option<&str>,
+    ) -> Result<(), ManageSieveError> {
+        let (reader, writer) = connection;
 
-        // Send SETACTIVE command
-        let command = format!("SETACTIVE \"{}\"\r\n", script);
-        writer.write_all(command.as_bytes()).await?;
+        let command = match tag {
+            Some(tag) => format!("NOOP {}\r\n", quote_string(tag)),
+            None => "NOOP\r\n".to_string(),
+        };
+        send_command(writer, &command).await?;
         writer.flush().await?;
 
-        let mut response = String::new();
-        reader.read_line(&mut response).await?;
-        let line = response.trim().to_uppercase();
-
-        if line.starts_with("OK") {
-            Ok(())
-        } else if line.starts_with("NO") {
-            Err(ManageSieveError::ScriptNotFound(script.to_string()))
-        } else if line.starts_with("BYE") {
-            Err(ManageSieveError::ServerError(response.trim().to_string()))
-        } else {
-            Err(ManageSieveError::InvalidResponse(
-                response.trim().to_string(),
-            ))
+        let response = read_response(reader).await?;
+        if response.status != Status::Ok {
+            return Err(response.error());
+        }
+        match tag {
+            Some(tag) if response_tag(&response) != Some(tag) => {
+                Err(ManageSieveError::InvalidResponse(format!(
+                    "NOOP answered without the tag \"{}\"",
+                    tag
+                )))
+            }
+            _ => Ok(()),
         }
     }
 
-    pub async fn check_script(&self, script: &str) -> Result<Option<String>, ManageSieveError> {
-        let mut connection = self.connection.lock().await;
-        let (reader, writer) = &mut *connection;
+    // Ends the session with LOGOUT and shuts the TLS stream down, so the server
+    // sees a clean close instead of a dropped connection
+    async fn close(connection: &mut Connection) -> Result<(), ManageSieveError> {
+        let (reader, writer) = connection;
 
-        // Send CHECKSCRIPT command with literal string
-        let command = format!("CHECKSCRIPT {{{}}}\r\n", script.len());
-        writer.write_all(command.as_bytes()).await?;
-        writer.write_all(script.as_bytes()).await?;
+        send_command(writer, "LOGOUT\r\n").await?;
         writer.flush().await?;
-
-        let mut response = String::new();
-        reader.read_line(&mut response).await?;
-        let line = response.trim();
-
-        if line.to_uppercase().starts_with("OK") {
-            // Check for WARNINGS response code in the OK response
-            if line.to_uppercase().contains("(WARNINGS)") {
-                // Extract warning message - it might be on the same line or a separate literal
-                let warning_msg = if let Some(start) = line.find('"') {
-                    // Warning message is quoted on the same line
-                    if let Some(end) = line.rfind('"') {
-                        if start != end {
-                            line[start + 1..end].to_string()
-                        } else {
-                            "Script has warnings".to_string()
-                        }
-                    } else {
-                        "Script has warnings".to_string()
-                    }
-                } else if line.contains("{") {
-                    // Warning message might be a literal string
-                    if let Some(length) = self.parse_literal_length(line) {
-                        let mut warning_content = vec![0u8; length];
-                        reader.read_exact(&mut warning_content).await?;
-                        String::from_utf8_lossy(&warning_content).to_string()
-                    } else {
-                        "Script has warnings".to_string()
-                    }
-                } else {
-                    "Script has warnings".to_string()
-                };
-                Ok(Some(warning_msg))
-            } else {
-                Ok(None)
+        // The server answers with OK (or BYE) and closes its side, either is
+        // fine, and the stream is shut down even if it didn't answer properly.
+        // Shutting down can fail once the server is gone, which is no problem.
+        let response = read_response(reader).await;
+        let _ = writer.shutdown().await;
+
+        match response {
+            Ok(_) => Ok(()),
+            // Some servers close the connection without answering at all
+            Err(ManageSieveError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                Ok(())
             }
-        } else if line.to_uppercase().starts_with("NO") {
-            // Extract error message from NO response
-            let error_msg = if let Some(start) = line.find('"') {
-                // Error message is quoted on the same line
-                if let Some(end) = line.rfind('"') {
-                    if start != end {
-                        line[start + 1..end].to_string()
-                    } else {
-                        line.to_string()
-                    }
-                } else {
-                    line.to_string()
-                }
-            } else if line.contains("{") {
-                // Error message might be a literal string
-                if let Some(length) = self.parse_literal_length(line) {
-                    let mut error_content = vec![0u8; length];
-                    reader.read_exact(&mut error_content).await?;
-                    String::from_utf8_lossy(&error_content).to_string()
-                } else {
-                    line.to_string()
-                }
-            } else {
-                line.to_string()
-            };
-            Err(ManageSieveError::ServerError(error_msg))
-        } else if line.to_uppercase().starts_with("BYE") {
-            Err(ManageSieveError::ServerError(line.to_string()))
-        } else {
-            Err(ManageSieveError::InvalidResponse(line.to_string()))
+            Err(err) => Err(err),
         }
     }
 
-    async fn ignore_initial_greeting(stream: &mut TcpStream) -> Result<(), ConnectError> {
-        let mut reader = BufReader::new(stream);
-        loop {
-            let mut line = String::new();
-            reader.read_line(&mut line).await?;
+    async fn establish(
+        host: &str,
+        port: u16,
+        options: &ConnectOptions,
+    ) -> Result<Established, ConnectError> {
+        // Connect to specified host and port
+        let address = format!("{}:{}", host, port);
 
-            if line.trim().is_empty() {
-                continue;
-            }
+        // Establish TCP connection
+        let mut stream = connect_tcp(&address, options.local_address).await?;
+
+        match options.tls_mode {
+            TlsMode::StartTls => {
+                // Kept only to compare them with the ones sent over TLS
+                let greeting = Self::read_initial_greeting(&mut stream).await?;
+
+                // Send STARTTLS command immediately
+                send_command(&mut stream, "STARTTLS\r\n").await?;
+                stream.flush().await?;
+
+                // Read STARTTLS response
+                let mut reader = BufReader::new(&mut stream);
+                let response = read_response(&mut reader)
+                    .await
+                    .map_err(|e| ConnectError::ProtocolError(e.to_string()))?;
+                if response.status != Status::Ok {
+                    return Err(ConnectError::ProtocolError(format!(
+                        "STARTTLS failed: {}",
+                        response.message()
+                    )));
+                }
 
-            // Check for OK response (end of greeting)
-            if line.trim().to_uppercase().starts_with("OK") {
-                break;
+                let established = Self::start_tls(host, stream, options).await?;
+                if options.compare_tls_capabilities {
+                    Self::compare_tls_capabilities(&greeting, &established.capabilities)?;
+                }
+                Ok(established)
+            }
+            // The greeting is only sent once TLS is up
+            TlsMode::Implicit => Self::start_tls(host, stream, options).await,
+            TlsMode::None => {
+                let (read, write) = stream.into_split();
+                let mut reader = BufReader::new(Box::new(read) as Reader);
+                let capabilities = Self::read_capabilities(&mut reader).await?;
+
+                Ok(Established {
+                    connection: (reader, Box::new(write)),
+                    capabilities,
+                    peer_certificates: Vec::new(),
+                    cert_store: None,
+                    tls_active: false,
+                })
             }
         }
-        Ok(())
     }
 
-    async fn read_capabilities(
-        reader: &mut BufReader<impl AsyncRead + Unpin>,
-    ) -> Result<Capabilities, ConnectError> {
-        let mut capabilities = Capabilities::default();
+    // Performs the TLS handshake on `stream` and reads the capabilities the
+    // server sends over the secured connection
+    async fn start_tls(
+        host: &str,
+        stream: TcpStream,
+        options: &ConnectOptions,
+    ) -> Result<Established, ConnectError> {
+        // Set up TLS configuration
+        let (config, cert_store) = tls::client_config(options)?;
 
-        loop {
-            let mut line = String::new();
-            reader.read_line(&mut line).await?;
+        let connector = TlsConnector::from(Arc::new(config));
+        let domain = tls_server_name(host, options)?;
 
-            if line.trim().is_empty() {
-                continue;
-            }
+        // Perform TLS handshake
+        let tls_stream = connector.connect(domain, stream).await?;
 
-            // Check for OK response (end of capabilities)
-            if line.trim().to_uppercase().starts_with("OK") {
-                break;
-            }
+        // Keep the server's certificate chain around, the split halves can't reach it anymore
+        let peer_certificates = tls_stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .map(|certs| certs.iter().map(|cert| cert.clone().into_owned()).collect())
+            .unwrap_or_default();
+
+        // Split the TLS stream
+        let (tls_read, tls_write): (TlsReader, TlsWriter) = tokio::io::split(tls_stream);
+        let mut tls_reader = BufReader::new(Box::new(tls_read) as Reader);
+
+        // Read capabilities after TLS and make sure they weren't tampered with
+        let capabilities = Self::read_capabilities(&mut tls_reader).await?;
+        Self::verify_tls_capabilities(&capabilities)?;
+
+        Ok(Established {
+            connection: (tls_reader, Box::new(tls_write)),
+            capabilities,
+            peer_certificates,
+            cert_store,
+            tls_active: true,
+        })
+    }
+
+    /// Sends a single command line as-is and returns every response line up to and
+    /// including the terminating `OK`, `NO` or `BYE`.
+    ///
+    /// This is an escape hatch for debugging and for server specific commands. It
+    /// bypasses literal handling entirely: literals are neither sent nor decoded, so
+    /// their content shows up as plain lines in the result.
+    pub async fn raw_command(&self, line: &str) -> Result<Vec<String>, ManageSieveError> {
+        let mut connection = self.connection().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                send_command(writer, &format!("{}\r\n", line.trim_end())).await?;
+                writer.flush().await?;
+
+                Ok(read_response_lines(reader).await?)
+            })
+            .await;
+        Self::discard_if_broken(&mut connection, result)
+    }
+
+    pub async fn list_scripts(&self) -> Result<Vec<(String, bool)>, ManageSieveError> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        self.list_scripts_streaming(sender).await?;
+
+        let mut scripts = Vec::new();
+        while let Some(script) = receiver.recv().await {
+            scripts.push(script);
+        }
+        Ok(scripts)
+    }
+
+    /// Like [`list_scripts`](Self::list_scripts), but sends each script name and
+    /// whether it is active as soon as it has been read, so a long list can be
+    /// shown while it is still arriving. Dropping the receiver early doesn't
+    /// abort the command, the rest of the list is still read from the server.
+    pub async fn list_scripts_streaming(
+        &self,
+        scripts: mpsc::UnboundedSender<(String, bool)>,
+    ) -> Result<(), ManageSieveError> {
+        let mut connection = self.connection().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                // Send LISTSCRIPTS command
+                send_command(writer, "LISTSCRIPTS\r\n").await?;
+                writer.flush().await?;
+
+                read_script_list(reader, &scripts).await
+            })
+            .await;
+        Self::discard_if_broken(&mut connection, result)
+    }
+
+    pub async fn get_script(&self, script: &str) -> Result<String, ManageSieveError> {
+        let mut content = Vec::new();
+        self.get_script_to(script, &mut content).await?;
+        Ok(String::from_utf8_lossy(&content).into_owned())
+    }
+
+    /// Downloads a script into `out` as it arrives, without holding all of it
+    /// in memory or requiring it to be UTF-8.
+    ///
+    /// If writing to `out` fails the rest of the script is still read, so the
+    /// connection stays usable, and the write error is returned afterwards.
+    pub async fn get_script_to<W: AsyncWrite + Unpin>(
+        &self,
+        script: &str,
+        out: &mut W,
+    ) -> Result<(), ManageSieveError> {
+        validate_script_name(script)?;
+
+        let mut connection = self.connection().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                // Send GETSCRIPT command
+                let command = format!("GETSCRIPT {}\r\n", quote_string(script));
+                send_command(writer, &command).await?;
+                writer.flush().await?;
+
+                let line = match read_response_line(reader).await? {
+                    ResponseLine::Data(line) => line,
+                    ResponseLine::Status(response) => {
+                        return match (response.status, &response.code) {
+                            (Status::No, Some(ResponseCode::NonExistent)) => {
+                                Err(ManageSieveError::ScriptNotFound(script.to_string()))
+                            }
+                            (Status::No | Status::Bye, _) => Err(response.error()),
+                            (Status::Ok, _) => {
+                                Err(ManageSieveError::InvalidResponse(response.message()))
+                            }
+                        };
+                    }
+                };
+
+                // The script is sent as a literal string, Cyrus timsieved uses the
+                // non-synchronizing `{n+}` form
+                let Some(length) = literal_length(&line) else {
+                    return Err(ManageSieveError::ProtocolError(
+                        "Invalid literal length format".to_string(),
+                    ));
+                };
+
+                read_script_literal(reader, length, out).await
+            })
+            .await;
+        Self::discard_if_broken(&mut connection, result)
+    }
+
+    pub async fn put_script(&self, script: &str, content: &str) -> Result<(), ManageSieveError> {
+        self.put_script_from(script, content.len(), &mut content.as_bytes())
+            .await
+    }
+
+    /// Uploads `length` bytes read from `input` as a script, copying them as
+    /// they are sent instead of holding all of it in memory.
+    ///
+    /// The length is announced before the content, so if `input` fails or ends
+    /// early the session is out of sync and unusable afterwards, see
+    /// [`ManageSieveError::OutOfSync`].
+    pub async fn put_script_from<R: AsyncRead + Unpin>(
+        &self,
+        script: &str,
+        length: usize,
+        input: &mut R,
+    ) -> Result<(), ManageSieveError> {
+        validate_script_name(script)?;
+
+        let mut connection = self.connection().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                // Send PUTSCRIPT command with the script as a non-synchronizing literal,
+                // the only kind a client may send (RFC 5804, section 4)
+                let command = format!("PUTSCRIPT {} {{{}+}}\r\n", quote_string(script), length);
+                send_command(writer, &command).await?;
+                write_script_literal(input, length, writer).await?;
+                writer.flush().await?;
+
+                let response = read_response(reader).await?;
+                match response.status {
+                    Status::Ok => Ok(()),
+                    Status::No | Status::Bye => Err(response.error()),
+                }
+            })
+            .await;
+        Self::discard_if_broken(&mut connection, result)
+    }
+
+    pub async fn delete_script(&self, script: &str) -> Result<(), ManageSieveError> {
+        validate_script_name(script)?;
+
+        let mut connection = self.connection().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                // Send DELETESCRIPT command
+                let command = format!("DELETESCRIPT {}\r\n", quote_string(script));
+                send_command(writer, &command).await?;
+                writer.flush().await?;
+
+                let response = read_response(reader).await?;
+                match (response.status, &response.code) {
+                    (Status::Ok, _) => Ok(()),
+                    (Status::No, Some(ResponseCode::NonExistent)) => {
+                        Err(ManageSieveError::ScriptNotFound(script.to_string()))
+                    }
+                    (Status::No, Some(ResponseCode::Active)) => {
+                        Err(ManageSieveError::ScriptActive(script.to_string()))
+                    }
+                    (Status::No | Status::Bye, _) => Err(response.error()),
+                }
+            })
+            .await;
+        Self::discard_if_broken(&mut connection, result)
+    }
 
-            // Try to parse as capability line
-            match Self::parse_capability_line(&line) {
-                Ok((capability, value)) => {
-                    Self::update_capabilities(&mut capabilities, capability, value);
+    pub async fn rename_script(
+        &self,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), ManageSieveError> {
+        validate_script_name(old_name)?;
+        validate_script_name(new_name)?;
+
+        let mut connection = self.connection().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                // Send RENAMESCRIPT command
+                let command = format!(
+                    "RENAMESCRIPT {} {}\r\n",
+                    quote_string(old_name),
+                    quote_string(new_name)
+                );
+                send_command(writer, &command).await?;
+                writer.flush().await?;
+
+                let response = read_response(reader).await?;
+                match (response.status, &response.code) {
+                    (Status::Ok, _) => Ok(()),
+                    (Status::No, Some(ResponseCode::NonExistent)) => {
+                        Err(ManageSieveError::ScriptNotFound(old_name.to_string()))
+                    }
+                    (Status::No | Status::Bye, _) => Err(response.error()),
                 }
-                Err(_) => {
-                    continue;
+            })
+            .await;
+        Self::discard_if_broken(&mut connection, result)
+    }
+
+    pub async fn set_active_script(&self, script: &str) -> Result<(), ManageSieveError> {
+        // An empty name deactivates all scripts
+        if !script.is_empty() {
+            validate_script_name(script)?;
+        }
+
+        let mut connection = self.connection().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                // Send SETACTIVE command
+                let command = format!("SETACTIVE {}\r\n", quote_string(script));
+                send_command(writer, &command).await?;
+                writer.flush().await?;
+
+                let response = read_response(reader).await?;
+                match (response.status, &response.code) {
+                    (Status::Ok, _) => Ok(()),
+                    (Status::No, Some(ResponseCode::NonExistent)) => {
+                        Err(ManageSieveError::ScriptNotFound(script.to_string()))
+                    }
+                    (Status::No | Status::Bye, _) => Err(response.error()),
+                }
+            })
+            .await;
+        Self::discard_if_broken(&mut connection, result)
+    }
+
+    /// Asks the server whether a script of `size` bytes could be stored under
+    /// `script` without exceeding a quota. `Ok(false)` means the server answered
+    /// with a `QUOTA` response code, other refusals are errors.
+    pub async fn have_space(&self, script: &str, size: usize) -> Result<bool, ManageSieveError> {
+        validate_script_name(script)?;
+
+        let mut connection = self.connection().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                // Send HAVESPACE command
+                let command = format!("HAVESPACE {} {}\r\n", quote_string(script), size);
+                send_command(writer, &command).await?;
+                writer.flush().await?;
+
+                let response = read_response(reader).await?;
+                match response.status {
+                    Status::Ok => Ok(true),
+                    Status::No if response.is_quota() => Ok(false),
+                    Status::No | Status::Bye => Err(response.error()),
+                }
+            })
+            .await;
+        Self::discard_if_broken(&mut connection, result)
+    }
+
+    pub async fn check_script(&self, script: &str) -> Result<CheckResult, ManageSieveError> {
+        let mut connection = self.connection().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                // Send CHECKSCRIPT command with the script as a non-synchronizing literal
+                let command = format!("CHECKSCRIPT {{{}+}}\r\n", script.len());
+                send_command(writer, &command).await?;
+                trace!("C: literal of {} bytes", script.len());
+                writer.write_all(script.as_bytes()).await?;
+                writer.write_all(b"\r\n").await?;
+                writer.flush().await?;
+
+                let response = read_response(reader).await?;
+                match response.status {
+                    // The warnings are in the text of an OK with the WARNINGS response code
+                    Status::Ok if response.code == Some(ResponseCode::Warnings) => {
+                        Ok(CheckResult::Warnings {
+                            message: response.text,
+                        })
+                    }
+                    Status::Ok => Ok(CheckResult::Clean),
+                    Status::No | Status::Bye => Err(response.error()),
+                }
+            })
+            .await;
+        Self::discard_if_broken(&mut connection, result)
+    }
+
+    // The capabilities before STARTTLS can't be trusted, they are read again
+    // once TLS is up
+    async fn read_initial_greeting(stream: &mut TcpStream) -> Result<Capabilities, ConnectError> {
+        Self::read_capabilities(&mut BufReader::new(stream)).await
+    }
+
+    // Reads capability lines up to the status line. Servers differ in the
+    // framing, some put an empty line before the `OK` or after it, and the
+    // `OK` may or may not carry a text. Empty lines are skipped wherever they
+    // are, a trailing one is skipped by whatever reads the next response.
+    async fn read_capabilities(
+        reader: &mut BufReader<impl AsyncRead + Unpin>,
+    ) -> Result<Capabilities, ConnectError> {
+        Self::read_capability_response(reader)
+            .await
+            .map_err(|err| match err {
+                ManageSieveError::IoError(err) => ConnectError::ConnectionFailed(err),
+                ManageSieveError::ServerError { message, .. }
+                | ManageSieveError::ServerClosed(message)
+                | ManageSieveError::Quota { message, .. } => ConnectError::ProtocolError(format!(
+                    "Server refused the connection: {}",
+                    message
+                )),
+                err => ConnectError::ProtocolError(err.to_string()),
+            })
+    }
+
+    // `read_capabilities` with the errors of a command, for the response to
+    // CAPABILITY
+    async fn read_capability_response(
+        reader: &mut BufReader<impl AsyncRead + Unpin>,
+    ) -> Result<Capabilities, ManageSieveError> {
+        let mut capabilities = Capabilities::default();
+
+        loop {
+            let line = read_response_line(reader).await?;
+
+            match line {
+                ResponseLine::Data(line) => {
+                    // Lines that aren't capabilities are ignored
+                    if let Ok((capability, value)) = Self::parse_capability_line(&line) {
+                        Self::update_capabilities(&mut capabilities, capability, value);
+                    }
+                }
+                ResponseLine::Status(response) => {
+                    return match response.status {
+                        Status::Ok => Ok(capabilities),
+                        Status::No | Status::Bye => Err(response.error()),
+                    };
                 }
             }
         }
+    }
 
-        Ok(capabilities)
+    // A server that still offers STARTTLS once TLS is up is most likely being
+    // tampered with by a man in the middle. Offering no SASL mechanisms is only
+    // suspicious when there are credentials to send, see `authenticate_connection`.
+    fn verify_tls_capabilities(capabilities: &Capabilities) -> Result<(), ConnectError> {
+        if capabilities.starttls {
+            return Err(ConnectError::ProtocolError(
+                "Server still advertises STARTTLS after TLS negotiation, possible downgrade attack"
+                    .to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // A server commonly offers more once TLS is up, e.g. PLAIN, but never less.
+    // SASL mechanisms or Sieve extensions that disappear point to a man in the
+    // middle stripping the capabilities sent in the clear (RFC 5804 section 2.2).
+    fn compare_tls_capabilities(
+        before: &Capabilities,
+        after: &Capabilities,
+    ) -> Result<(), ConnectError> {
+        let missing = |from: &[String], other: &[String]| {
+            from.iter()
+                .filter(|name| !other.iter().any(|o| o.eq_ignore_ascii_case(name)))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let mechanisms = missing(&before.sasl, &after.sasl);
+        if !mechanisms.is_empty() {
+            return Err(ConnectError::ProtocolError(format!(
+                "SASL mechanisms offered before TLS are missing after it, possible downgrade attack: {}",
+                mechanisms.join(", ")
+            )));
+        }
+
+        let extensions = missing(&before.sieve, &after.sieve);
+        if !extensions.is_empty() {
+            return Err(ConnectError::ProtocolError(format!(
+                "Sieve extensions offered before TLS are missing after it, possible downgrade attack: {}",
+                extensions.join(", ")
+            )));
+        }
+
+        Ok(())
     }
 
     fn parse_capability_line(line: &str) -> Result<(String, Option<String>), String> {
@@ -552,85 +1621,768 @@ impl SieveClient {
         }
     }
 
-    pub fn capabilities(&self) -> &Capabilities {
-        &self.capabilities
+    // Can't be held across an `.await`, the lock isn't async
+    fn server(&self) -> RwLockReadGuard<'_, Server> {
+        self.server.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn server_mut(&mut self) -> &mut Server {
+        self.server
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The capabilities of the current session. The returned `Arc` is a
+    /// snapshot: a later [`SieveClient::reconnect`] or
+    /// [`SieveClient::refresh_capabilities`] replaces the capabilities returned
+    /// by later calls but doesn't update one already handed out.
+    pub fn capabilities(&self) -> Arc<Capabilities> {
+        self.server().capabilities.clone()
+    }
+
+    /// The username the session logged in with, `None` for a session without
+    /// credentials.
+    pub fn username(&self) -> Option<&str> {
+        self.credentials
+            .as_ref()
+            .map(|(username, _)| username.as_str())
+    }
+
+    /// Whether the session is encrypted with TLS.
+    pub fn is_tls_active(&self) -> bool {
+        self.server().tls_active
+    }
+
+    /// Asks the server for its capabilities again (RFC 5804 section 2.4) and
+    /// replaces the ones read when connecting, e.g. to see extensions a server
+    /// only offers after authentication.
+    pub async fn refresh_capabilities(&self) -> Result<Arc<Capabilities>, ManageSieveError> {
+        // The capabilities are those of the first session, see `connections`
+        let mut connection = self.connections[0].lock().await;
+        let result = self
+            .timed(async {
+                let (reader, writer) = &mut *connection;
+
+                send_command(writer, "CAPABILITY\r\n").await?;
+                writer.flush().await?;
+
+                Self::read_capability_response(reader).await
+            })
+            .await;
+        let capabilities = Arc::new(Self::discard_if_broken(&mut connection, result)?);
+        self.server
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .capabilities = capabilities.clone();
+        Ok(capabilities)
+    }
+
+    /// The SASL mechanism the session authenticated with, e.g. `PLAIN`. `None`
+    /// while unauthenticated or when the server offers no SASL mechanisms and
+    /// needed no login.
+    pub fn sasl_mechanism(&self) -> Option<&'static str> {
+        self.server().mechanism
+    }
+
+    /// Certificate chain presented by the server during the TLS handshake, leaf first.
+    pub fn peer_certificates(&self) -> Vec<CertificateDer<'static>> {
+        self.server().peer_certificates.clone()
+    }
+
+    /// Certificate store the server certificate was validated against, or `None`
+    /// if it was checked against a pinned fingerprint instead.
+    pub fn cert_store(&self) -> Option<CertStore> {
+        self.server().cert_store
+    }
+
+    /// SASL mechanisms the server advertised after the TLS handshake. The list
+    /// is borrowed from the current capability snapshot rather than copied.
+    pub fn sasl_mechanisms(&self) -> impl Deref<Target = [String]> + use<> {
+        SaslMechanisms(self.server().capabilities.clone())
     }
 
     // Note: These methods are removed as they would break the Mutex encapsulation
     // Access to reader/writer should be done through the async methods
 
-    // Helper method to parse script names from LISTSCRIPTS response
-    fn parse_script_line(&self, line: &str) -> Option<(String, bool)> {
-        if let Ok((_, script_name)) = parse_quoted_string(line) {
-            let is_active = line.to_uppercase().contains("ACTIVE");
-            Some((script_name.to_string(), is_active))
+    // Logs in on `connection`, a session with `capabilities`, with
+    // `credentials` and returns the SASL mechanism that was used
+    async fn authenticate_connection(
+        &self,
+        connection: &mut Connection,
+        capabilities: &Capabilities,
+        tls_active: bool,
+        credentials: Option<&(String, SecretString)>,
+    ) -> Result<Option<&'static str>, ConnectError> {
+        let (reader, writer) = connection;
+        let (mechanism, mut client) = if self.uses_external(capabilities, tls_active) {
+            let client = Mechanism::External.client("", "", CredentialNormalization::None)?;
+            (Mechanism::External, client)
         } else {
-            None
+            // Without credentials the session logs in with SASL ANONYMOUS or is
+            // pre-authenticated, see `connect_anonymous`
+            let Some((username, password)) = credentials else {
+                return Self::authenticate_anonymous(capabilities, connection).await;
+            };
+            let password = password.expose_secret();
+
+            if self.options.require_tls && !tls_active {
+                return Err(ConnectError::AuthenticationFailed(
+                    "Refusing to send credentials over an unencrypted connection".to_string(),
+                ));
+            }
+            // A server that offers no SASL mechanism after TLS can't be logged
+            // in to
+            if tls_active && capabilities.sasl.is_empty() {
+                return Err(ConnectError::ProtocolError(
+                    "Server advertised no SASL mechanisms after TLS negotiation".to_string(),
+                ));
+            }
+
+            let Some(mechanism) = Mechanism::choose(&capabilities.sasl) else {
+                return Err(ConnectError::AuthenticationFailed(format!(
+                    "None of the server's SASL mechanisms is supported: {}",
+                    capabilities.sasl.join(", ")
+                )));
+            };
+            let client =
+                mechanism.client(username, password, self.options.credential_normalization)?;
+            (mechanism, client)
+        };
+
+        let response = sasl_exchange(reader, writer, mechanism, &mut *client).await?;
+        match response.status {
+            Status::Ok => Ok(Some(mechanism.name())),
+            Status::No => Err(ConnectError::AuthenticationFailed(format!(
+                "Server rejected credentials: {}",
+                response.message()
+            ))),
+            Status::Bye => Err(ConnectError::AuthenticationFailed(format!(
+                "Server disconnected: {}",
+                response.message()
+            ))),
         }
     }
 
-    // Helper method to parse literal string length from server response
-    fn parse_literal_length(&self, line: &str) -> Option<usize> {
-        if line.starts_with("{") && line.ends_with("}") {
-            let length_str = &line[1..line.len() - 1];
-            length_str.parse().ok()
+    // The TLS client certificate already identifies the user
+    fn uses_external(&self, capabilities: &Capabilities, tls_active: bool) -> bool {
+        self.options.client_identity.is_some()
+            && tls_active
+            && capabilities
+                .sasl
+                .iter()
+                .any(|offered| offered.eq_ignore_ascii_case(Mechanism::External.name()))
+    }
+
+    async fn authenticate_anonymous(
+        capabilities: &Capabilities,
+        connection: &mut Connection,
+    ) -> Result<Option<&'static str>, ConnectError> {
+        if !Self::needs_anonymous_login(capabilities)? {
+            return Ok(None);
+        }
+
+        let (reader, writer) = connection;
+
+        // ANONYMOUS takes an optional trace token, there is nothing to send
+        send_command(writer, "AUTHENTICATE \"ANONYMOUS\" \"\"\r\n").await?;
+        writer.flush().await?;
+
+        let response = read_response(reader)
+            .await
+            .map_err(|e| ConnectError::ProtocolError(e.to_string()))?;
+        match response.status {
+            Status::Ok => Ok(Some("ANONYMOUS")),
+            Status::No | Status::Bye => Err(ConnectError::AuthenticationFailed(format!(
+                "Server rejected anonymous login: {}",
+                response.message()
+            ))),
+        }
+    }
+
+    // Whether a session without credentials has to log in with SASL ANONYMOUS
+    // (`true`) or is usable right away because the server offers no SASL
+    // mechanisms (`false`)
+    fn needs_anonymous_login(capabilities: &Capabilities) -> Result<bool, ConnectError> {
+        if capabilities.sasl.is_empty() {
+            Ok(false)
+        } else if capabilities
+            .sasl
+            .iter()
+            .any(|mechanism| mechanism.eq_ignore_ascii_case("ANONYMOUS"))
+        {
+            Ok(true)
         } else {
-            None
+            Err(ConnectError::AuthenticationFailed(
+                "Server requires credentials, SASL ANONYMOUS is not supported".to_string(),
+            ))
         }
     }
+}
 
-    async fn authenticate(&self, username: &str, password: &str) -> Result<(), ConnectError> {
-        let mut connection = self.connection.lock().await;
-        let (reader, writer) = &mut *connection;
-        // Check if SASL PLAIN is supported
-        if !self.capabilities.sasl.contains(&"PLAIN".to_string()) {
-            return Err(ConnectError::AuthenticationFailed(
-                "SASL PLAIN mechanism not supported".to_string(),
+// Reads lines until one starts with a status atom, returning all of them
+// except empty ones, which some servers put around the status line
+async fn read_response_lines(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<Vec<String>, io::Error> {
+    let mut lines = Vec::new();
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed before the response was complete",
             ));
         }
 
-        // Prepare SASL PLAIN authentication
-        let auth_string = format!("\0{}\0{}", username, password);
-        let auth_b64 = general_purpose::STANDARD.encode(&auth_string);
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.trim().is_empty() {
+            continue;
+        }
+        let is_status = matches!(
+            line.split_whitespace()
+                .next()
+                .map(|atom| atom.to_uppercase())
+                .as_deref(),
+            Some("OK" | "NO" | "BYE")
+        );
 
-        // Send AUTHENTICATE command
-        let auth_command = format!("AUTHENTICATE \"PLAIN\" \"{}\"\r\n", auth_b64);
-        writer.write_all(auth_command.as_bytes()).await?;
-        writer.flush().await?;
+        lines.push(line);
+        if is_status {
+            return Ok(lines);
+        }
+    }
+}
 
-        // Read response
-        let mut response = String::new();
-        reader.read_line(&mut response).await?;
+/// How the server completed a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    No,
+    Bye,
+}
 
-        // Check if authentication was successful
-        let response_upper = response.trim().to_uppercase();
-        if response_upper.starts_with("OK") {
-            Ok(())
-        } else if response_upper.starts_with("NO") {
-            Err(ConnectError::AuthenticationFailed(format!(
-                "Server rejected credentials: {}",
-                response.trim()
-            )))
-        } else if response_upper.starts_with("BYE") {
-            Err(ConnectError::AuthenticationFailed(format!(
-                "Server disconnected: {}",
-                response.trim()
-            )))
-        } else {
-            Err(ConnectError::AuthenticationFailed(format!(
-                "Unexpected response: {}",
-                response.trim()
-            )))
+/// The status line that terminates a response, e.g. `NO (QUOTA/MAXSIZE) "Too big"`
+#[derive(Debug, Clone, PartialEq)]
+struct Response {
+    status: Status,
+    code: Option<ResponseCode>,
+    /// The human readable text, already unquoted or read from its literal
+    text: Option<String>,
+}
+
+impl Response {
+    fn is_quota(&self) -> bool {
+        self.code.as_ref().is_some_and(ResponseCode::is_quota)
+    }
+
+    // What to tell the user when the command failed
+    fn message(&self) -> String {
+        match (&self.text, &self.code) {
+            (Some(text), _) => text.clone(),
+            (None, Some(code)) => code.to_string(),
+            (None, None) => format!("{:?}", self.status).to_uppercase(),
+        }
+    }
+
+    // The error for a failed command, a `BYE` means the session is gone
+    // rather than that the command was refused
+    fn error(&self) -> ManageSieveError {
+        match (&self.status, &self.code) {
+            (Status::Bye, _) => ManageSieveError::ServerClosed(self.message()),
+            (Status::Ok | Status::No, Some(code)) if code.is_quota() => ManageSieveError::Quota {
+                code: code.clone(),
+                message: self.message(),
+            },
+            (Status::Ok | Status::No, _) => ManageSieveError::ServerError {
+                code: self.code.clone(),
+                message: self.message(),
+            },
+        }
+    }
+}
+
+// Reads up to and including the status line of a command that returns no
+// data. Anything before it, like the updated capabilities some servers send
+// after certain commands, is skipped.
+async fn read_response(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<Response, ManageSieveError> {
+    loop {
+        if let ResponseLine::Status(response) = read_response_line(reader).await? {
+            return Ok(response);
         }
     }
 }
 
+// Sends exactly `length` bytes of `input` and the CRLF after the literal
+async fn write_script_literal(
+    input: &mut (impl AsyncRead + Unpin),
+    length: usize,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), ManageSieveError> {
+    trace!("C: literal of {} bytes", length);
+
+    let mut buffer = vec![0; length.clamp(1, 8 * 1024)];
+    let mut remaining = length;
+    while remaining > 0 {
+        let chunk = buffer.len().min(remaining);
+        let read = match input.read(&mut buffer[..chunk]).await {
+            Ok(0) => {
+                return Err(ManageSieveError::OutOfSync(format!(
+                    "Script ended {} bytes short of the announced {} bytes",
+                    remaining, length
+                )));
+            }
+            Ok(read) => read,
+            Err(err) => {
+                return Err(ManageSieveError::OutOfSync(format!(
+                    "Can't read the rest of the script: {}",
+                    err
+                )));
+            }
+        };
+        writer.write_all(&buffer[..read]).await?;
+        remaining -= read;
+    }
+    writer.write_all(b"\r\n").await?;
+    Ok(())
+}
+
+// Copies a GETSCRIPT literal of `length` bytes to `out` and reads the status
+// line after it. A script longer than `length` is reported instead of returned
+// truncated. One shorter than `length` can't be told apart from a slow server,
+// the status line is taken for part of it and the read only ends with
+// `ConnectOptions::command_timeout`.
+async fn read_script_literal(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    length: usize,
+    out: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), ManageSieveError> {
+    trace!("S: literal of {} bytes", length);
+
+    // Copy exactly the announced number of bytes, chunk by chunk as they are
+    // buffered. After a failed write the rest is only skipped.
+    let mut remaining = length;
+    let mut write_error = None;
+    while remaining > 0 {
+        let chunk = reader.fill_buf().await?;
+        if chunk.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let chunk = &chunk[..chunk.len().min(remaining)];
+        if write_error.is_none()
+            && let Err(err) = out.write_all(chunk).await
+        {
+            write_error = Some(err);
+        }
+        let consumed = chunk.len();
+        reader.consume(consumed);
+        remaining -= consumed;
+    }
+    if write_error.is_none()
+        && let Err(err) = out.flush().await
+    {
+        write_error = Some(err);
+    }
+
+    // Read the CRLF that follows the literal content
+    let mut rest = String::new();
+    reader.read_line(&mut rest).await?;
+    if rest != "\r\n" {
+        return Err(ManageSieveError::OutOfSync(format!(
+            "Script is longer than the announced {} bytes, unexpected data: {:?}",
+            length,
+            rest.trim_end()
+        )));
+    }
+
+    match read_response_line(reader).await? {
+        ResponseLine::Status(response) => match response.status {
+            Status::Ok => match write_error {
+                Some(err) => Err(err.into()),
+                None => Ok(()),
+            },
+            Status::No | Status::Bye => Err(response.error()),
+        },
+        ResponseLine::Data(line) => Err(ManageSieveError::OutOfSync(format!(
+            "Expected OK after the script, got: {:?}",
+            line
+        ))),
+    }
+}
+
+// Reads the LISTSCRIPTS response, sending each script as soon as its line arrives
+async fn read_script_list(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    scripts: &mpsc::UnboundedSender<(String, bool)>,
+) -> Result<(), ManageSieveError> {
+    loop {
+        match read_response_line(reader).await? {
+            ResponseLine::Data(line) => {
+                if let Some(script) = parse_script_line(&line) {
+                    // Nobody listening anymore, keep reading to stay in sync
+                    let _ = scripts.send(script);
+                }
+            }
+            ResponseLine::Status(response) => {
+                return match response.status {
+                    Status::Ok => Ok(()),
+                    Status::No | Status::Bye => Err(response.error()),
+                };
+            }
+        }
+    }
+}
+
+// A LISTSCRIPTS line is the quoted name, optionally followed by the ACTIVE
+// atom. Anything else after the name makes the line invalid.
+fn parse_script_line(line: &str) -> Option<(String, bool)> {
+    let (rest, script_name) = parse_quoted_string(line.trim_start()).ok()?;
+    let rest = rest.trim();
+    if rest.is_empty() {
+        Some((script_name, false))
+    } else if rest.eq_ignore_ascii_case("ACTIVE") {
+        Some((script_name, true))
+    } else {
+        None
+    }
+}
+
+// The name the server certificate has to be valid for, which is the host
+// connected to unless `ConnectOptions::tls_server_name` says otherwise
+fn tls_server_name(
+    host: &str,
+    options: &ConnectOptions,
+) -> Result<ServerName<'static>, ConnectError> {
+    let name = options.tls_server_name.as_deref().unwrap_or(host);
+    ServerName::try_from(name)
+        .map(|name| name.to_owned())
+        .map_err(|_| ConnectError::ProtocolError(format!("Invalid hostname: {}", name)))
+}
+
+// Writes a command line and logs it. Commands carrying credentials are sent
+// and logged by `sasl_exchange` instead.
+async fn send_command(writer: &mut (impl AsyncWrite + Unpin), command: &str) -> io::Result<()> {
+    debug!("C: {}", command.trim_end());
+    writer.write_all(command.as_bytes()).await
+}
+
+enum ResponseLine {
+    /// A line before the status line, without the trailing CRLF
+    Data(String),
+    Status(Response),
+}
+
+// Reads the next line of a response, including the literal text of a status line
+async fn read_response_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<ResponseLine, ManageSieveError> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed before the response was complete",
+            )
+            .into());
+        }
+
+        let trimmed = line.trim_end_matches(['\r', '\n']);
+        if trimmed.trim().is_empty() {
+            continue;
+        }
+        debug!("S: {}", trimmed);
+        let Some((status, code, text)) = parse_status_line(trimmed) else {
+            return Ok(ResponseLine::Data(trimmed.to_string()));
+        };
+
+        let text = match text {
+            ResponseText::None => None,
+            ResponseText::Quoted(text) => Some(text),
+            ResponseText::Literal(length) => {
+                trace!("S: literal of {} bytes", length);
+                if length > MAX_TEXT_LITERAL {
+                    return Err(ManageSieveError::OutOfSync(format!(
+                        "Response text of {} bytes is too long",
+                        length
+                    )));
+                }
+                let mut content = vec![0u8; length];
+                reader.read_exact(&mut content).await?;
+                // The CRLF that ends the status line
+                line.clear();
+                reader.read_line(&mut line).await?;
+                Some(String::from_utf8_lossy(&content).to_string())
+            }
+        };
+
+        return Ok(ResponseLine::Status(Response {
+            status,
+            code: code.as_deref().map(ResponseCode::parse),
+            text,
+        }));
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ResponseText {
+    None,
+    Quoted(String),
+    /// The text follows the status line as a literal of this many bytes
+    Literal(usize),
+}
+
+// Responses longer than this are sent as a literal, some servers limit the
+// length of a command line and long tokens would exceed it
+const SASL_QUOTED_LIMIT: usize = 256;
+
+/// A base64 encoded SASL response as a quoted string or as a
+/// non-synchronizing literal, followed by the CRLF that ends the line.
+fn sasl_response(response: &str) -> Zeroizing<String> {
+    Zeroizing::new(if response.len() > SASL_QUOTED_LIMIT {
+        format!("{{{}+}}\r\n{}\r\n", response.len(), response)
+    } else {
+        format!("\"{}\"\r\n", response)
+    })
+}
+
+/// Builds the AUTHENTICATE command for `mechanism` with a base64 encoded
+/// initial response.
+fn authenticate_command(mechanism: &str, initial_response: &str) -> Zeroizing<String> {
+    Zeroizing::new(format!(
+        "AUTHENTICATE \"{}\" {}",
+        mechanism,
+        sasl_response(initial_response).as_str()
+    ))
+}
+
+/// What the server sends while an AUTHENTICATE exchange is going on
+#[derive(Debug, PartialEq)]
+enum SaslStep {
+    /// A challenge to answer, decoded from base64
+    Challenge(Vec<u8>),
+    /// The status response that ends the exchange
+    Done(Response),
+}
+
+// Reads the next challenge or the final response of an AUTHENTICATE exchange.
+// RFC 5804 sends challenges as a string, but a `+ ` in front as in IMAP is
+// accepted as well.
+async fn read_sasl_step(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<SaslStep, ManageSieveError> {
+    let line = match read_response_line(reader).await? {
+        ResponseLine::Status(response) => return Ok(SaslStep::Done(response)),
+        ResponseLine::Data(line) => line,
+    };
+    let line = line
+        .strip_prefix('+')
+        .map_or(line.as_str(), str::trim_start);
+
+    let encoded = if let Some(length) = literal_length(line) {
+        // SCRAM challenges are a few hundred bytes at most
+        if length > MAX_TEXT_LITERAL {
+            return Err(ManageSieveError::OutOfSync(format!(
+                "SASL challenge of {} bytes is too long",
+                length
+            )));
+        }
+        let mut content = vec![0u8; length];
+        reader.read_exact(&mut content).await?;
+        // The CRLF that ends the literal
+        let mut rest = String::new();
+        reader.read_line(&mut rest).await?;
+        content
+    } else if let Ok((_, quoted)) = parse_quoted_string(line) {
+        quoted.into_bytes()
+    } else {
+        line.as_bytes().to_vec()
+    };
+
+    general_purpose::STANDARD
+        .decode(encoded.trim_ascii())
+        .map(SaslStep::Challenge)
+        .map_err(|err| {
+            ManageSieveError::InvalidResponse(format!("SASL challenge isn't base64: {}", err))
+        })
+}
+
+// Runs an AUTHENTICATE exchange with `client` up to the server's final
+// response. Challenges and responses are base64 encoded here, and the data of
+// an `OK (SASL "...")` is handed to the client to check.
+async fn sasl_exchange(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    mechanism: Mechanism,
+    client: &mut (impl SaslClient + ?Sized),
+) -> Result<Response, ConnectError> {
+    let protocol_error = |err: ManageSieveError| ConnectError::ProtocolError(err.to_string());
+    let encode = |message: &[u8]| Zeroizing::new(general_purpose::STANDARD.encode(message));
+
+    // Every copy of the credentials is scrubbed from memory once it was sent
+    let command = match client.initial_response()? {
+        Some(initial) => authenticate_command(mechanism.name(), &encode(&initial)),
+        None => Zeroizing::new(format!("AUTHENTICATE \"{}\"\r\n", mechanism.name())),
+    };
+    // The initial response and answers may contain the password
+    debug!("C: AUTHENTICATE \"{}\" <redacted>", mechanism.name());
+    writer.write_all(command.as_bytes()).await?;
+    writer.flush().await?;
+
+    loop {
+        match read_sasl_step(reader).await.map_err(protocol_error)? {
+            SaslStep::Challenge(challenge) => {
+                let answer = match client.respond(&challenge) {
+                    Ok(answer) => answer,
+                    Err(err) => {
+                        // Cancelled so the server doesn't wait for an answer
+                        send_command(writer, "\"*\"\r\n").await?;
+                        writer.flush().await?;
+                        let _ = read_response(reader).await;
+                        return Err(err);
+                    }
+                };
+                debug!("C: <redacted>");
+                writer
+                    .write_all(sasl_response(&encode(&answer)).as_bytes())
+                    .await?;
+                writer.flush().await?;
+            }
+            // Refused early, e.g. an unknown username
+            SaslStep::Done(response) if response.status != Status::Ok => return Ok(response),
+            SaslStep::Done(response) => {
+                let data = match response.code.as_ref().and_then(sasl_code_data) {
+                    Some(data) => Some(data.map_err(protocol_error)?),
+                    None => None,
+                };
+                client.finish(data.as_deref())?;
+                return Ok(response);
+            }
+        }
+    }
+}
+
+// The string of a `TAG "..."` response code
+fn response_tag(response: &Response) -> Option<&str> {
+    match &response.code {
+        Some(ResponseCode::Tag(tag)) => Some(tag),
+        _ => None,
+    }
+}
+
+// The decoded data of a `SASL "<base64>"` response code, `None` for other codes
+fn sasl_code_data(code: &ResponseCode) -> Option<Result<Vec<u8>, ManageSieveError>> {
+    let ResponseCode::Sasl(data) = code else {
+        return None;
+    };
+    Some(general_purpose::STANDARD.decode(data).map_err(|err| {
+        ManageSieveError::InvalidResponse(format!("SASL response code isn't base64: {}", err))
+    }))
+}
+
+// Splits `OK (CODE) "text"` into its parts, or returns `None` if the line
+// isn't a status line
+fn parse_status_line(line: &str) -> Option<(Status, Option<String>, ResponseText)> {
+    let atom_end = line
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(line.len());
+    let status = match line[..atom_end].to_uppercase().as_str() {
+        "OK" => Status::Ok,
+        "NO" => Status::No,
+        "BYE" => Status::Bye,
+        _ => return None,
+    };
+
+    let mut rest = line[atom_end..].trim_start();
+    let mut code = None;
+    if rest.starts_with('(') {
+        // Codes may contain quoted strings with parentheses, e.g. `(SASL "...")`
+        let mut depth = 0;
+        let mut quoted = false;
+        let mut escaped = false;
+        let mut end = rest.len();
+        for (index, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                '(' if !quoted => depth += 1,
+                ')' if !quoted => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = index;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        code = Some(rest[1..end].trim().to_string());
+        rest = rest.get(end + 1..).unwrap_or_default().trim_start();
+    }
+
+    let text = if let Some(quoted) = rest.strip_prefix('"') {
+        let mut text = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => text.extend(chars.next()),
+                c => text.push(c),
+            }
+        }
+        ResponseText::Quoted(text)
+    } else if let Some(length) = literal_length(rest) {
+        ResponseText::Literal(length)
+    } else if rest.is_empty() {
+        ResponseText::None
+    } else {
+        ResponseText::Quoted(rest.to_string())
+    };
+
+    Some((status, code, text))
+}
+
+// Response texts and SASL challenges are far shorter than this. A longer
+// literal is refused instead of allocated.
+const MAX_TEXT_LITERAL: usize = 64 * 1024;
+
+// The length announced by a literal's `{length}` or `{length+}` prefix
+fn literal_length(text: &str) -> Option<usize> {
+    text.strip_prefix('{')
+        .and_then(|text| text.strip_suffix('}'))
+        .map(|length| length.trim_end_matches('+'))
+        .and_then(|length| length.parse().ok())
+}
+
 // Nom parsers for ManageSieve protocol
 fn parse_quoted_string(input: &str) -> IResult<&str, String> {
     let (input, _) = char('"')(input)?;
-    let (input, content) = take_until("\"")(input)?;
-    let (input, _) = char('"')(input)?;
-    Ok((input, content.to_string()))
+    let mut content = String::new();
+    let mut chars = input.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((&input[index + 1..], content)),
+            '\\' => content.extend(chars.next().map(|(_, escaped)| escaped)),
+            c => content.push(c),
+        }
+    }
+    Err(nom::Err::Error(NomError::new(input, ErrorKind::Char)))
+}
+
+/// `value` as a quoted string (RFC 5804 section 4), with `"` and `\` escaped.
+fn quote_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
 }
 
 fn parse_capability(input: &str) -> IResult<&str, (String, Option<String>)> {
@@ -646,6 +2398,8 @@ fn parse_capability(input: &str) -> IResult<&str, (String, Option<String>)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sasl::ScramSha256;
+    use rustls_pki_types::pem::PemObject;
 
     #[test]
     fn test_parse_quoted_string() {
@@ -661,6 +2415,25 @@ mod tests {
             parse_quoted_string("\"test\" remaining"),
             Ok((" remaining", "test".to_string()))
         );
+        assert_eq!(
+            parse_quoted_string("\"say \\\"hi\\\" \\\\o/\""),
+            Ok(("", "say \"hi\" \\o/".to_string()))
+        );
+        assert!(parse_quoted_string("\"unterminated").is_err());
+    }
+
+    #[test]
+    fn test_quote_string() {
+        assert_eq!(quote_string("my script"), "\"my script\"");
+        assert_eq!(quote_string("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote_string("back\\slash"), "\"back\\\\slash\"");
+
+        for name in ["my script", "say \"hi\"", "back\\slash", "\\\""] {
+            assert_eq!(
+                parse_quoted_string(&quote_string(name)),
+                Ok(("", name.to_string()))
+            );
+        }
     }
 
     #[test]
@@ -781,6 +2554,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_has_capability() {
+        let mut capabilities = Capabilities::default();
+        assert!(!capabilities.has_capability("UNAUTHENTICATE"));
+
+        SieveClient::update_capabilities(&mut capabilities, "Unauthenticate".to_string(), None);
+        assert!(capabilities.has_capability("UNAUTHENTICATE"));
+        assert!(capabilities.has_capability("unauthenticate"));
+    }
+
+    #[test]
+    fn test_owner_capability() {
+        let mut capabilities = Capabilities::default();
+        SieveClient::update_capabilities(
+            &mut capabilities,
+            "OWNER".to_string(),
+            Some("alice@example.com".to_string()),
+        );
+        assert_eq!(capabilities.owner.as_deref(), Some("alice@example.com"));
+    }
+
+    #[test]
+    fn test_is_writable() {
+        let mut capabilities = Capabilities::default();
+        assert!(capabilities.is_writable("alice@example.com"));
+
+        capabilities.owner = Some("Alice@example.com".to_string());
+        assert!(capabilities.is_writable("alice@example.com"));
+        assert!(!capabilities.is_writable("admin@example.com"));
+    }
+
     #[test]
     fn test_capabilities_case_insensitive() {
         let mut capabilities = Capabilities::default();
@@ -913,23 +2717,458 @@ mod tests {
             println!("  Notify methods: {}", capabilities.notify.join(", "));
         }
 
-        // Verify parsing worked correctly
+        // Verify parsing worked correctly
+        assert_eq!(
+            capabilities.implementation,
+            Some("Dovecot ManageSieve server v2.3.16".to_string())
+        );
+        assert_eq!(
+            capabilities.sasl,
+            vec!["PLAIN", "LOGIN", "DIGEST-MD5", "CRAM-MD5"]
+        );
+        assert_eq!(capabilities.starttls, true);
+        assert_eq!(capabilities.maxredirects, Some(10));
+        assert_eq!(capabilities.version, Some("1.0".to_string()));
+        assert!(capabilities.sieve.contains(&"fileinto".to_string()));
+        assert!(capabilities.sieve.contains(&"vacation".to_string()));
+        assert!(capabilities.sieve.contains(&"variables".to_string()));
+
+        println!("\n✓ All capabilities parsed successfully!");
+    }
+
+    #[test]
+    fn test_verify_tls_capabilities() {
+        let mut capabilities = Capabilities::default();
+        SieveClient::update_capabilities(
+            &mut capabilities,
+            "SASL".to_string(),
+            Some("PLAIN".to_string()),
+        );
+        assert!(SieveClient::verify_tls_capabilities(&capabilities).is_ok());
+
+        // STARTTLS must not be offered again once TLS is active
+        SieveClient::update_capabilities(&mut capabilities, "STARTTLS".to_string(), None);
+        assert!(matches!(
+            SieveClient::verify_tls_capabilities(&capabilities),
+            Err(ConnectError::ProtocolError(_))
+        ));
+
+        // No SASL mechanisms is fine for a session that needs no login
+        assert!(SieveClient::verify_tls_capabilities(&Capabilities::default()).is_ok());
+    }
+
+    // Accepts one connection, sends `greeting` and answers STARTTLS, then
+    // completes the handshake as the server
+    async fn accept_starttls(
+        listener: tokio::net::TcpListener,
+        greeting: &[u8],
+    ) -> tokio_rustls::server::TlsStream<TcpStream> {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream.write_all(greeting).await.unwrap();
+        let mut command = [0u8; 10];
+        stream.read_exact(&mut command).await.unwrap();
+        assert_eq!(&command, b"STARTTLS\r\n");
+        stream.write_all(b"OK\r\n").await.unwrap();
+        test_acceptor().accept(stream).await.unwrap()
+    }
+
+    fn test_acceptor() -> tokio_rustls::TlsAcceptor {
+        let pem = crate::tls::tests::TEST_CLIENT;
+        let key = rustls_pki_types::PrivateKeyDer::from_pem_slice(pem.as_bytes()).unwrap();
+        let config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(tls::parse_certificates(pem).unwrap(), key)
+            .unwrap();
+        tokio_rustls::TlsAcceptor::from(Arc::new(config))
+    }
+
+    // Serves one session, over STARTTLS or implicit TLS, that needs no login:
+    // the capabilities sent over TLS offer no SASL mechanisms
+    async fn serve_preauthenticated(listener: tokio::net::TcpListener, starttls: bool) {
+        let stream = if starttls {
+            accept_starttls(
+                listener,
+                b"\"IMPLEMENTATION\" \"Preauth\"\r\n\"SIEVE\" \"fileinto\"\r\n\"STARTTLS\"\r\nOK\r\n",
+            )
+            .await
+        } else {
+            let (stream, _) = listener.accept().await.unwrap();
+            test_acceptor().accept(stream).await.unwrap()
+        };
+
+        let mut stream = BufReader::new(stream);
+        stream
+            .write_all(b"\"IMPLEMENTATION\" \"Preauth\"\r\n\"SIEVE\" \"fileinto\"\r\nOK\r\n")
+            .await
+            .unwrap();
+        let mut command = String::new();
+        stream.read_line(&mut command).await.unwrap();
+        assert_eq!(command, "LISTSCRIPTS\r\n");
+        stream
+            .write_all(b"\"main\" ACTIVE\r\nOK\r\n")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_preauthenticated_tls() {
+        for tls_mode in [TlsMode::StartTls, TlsMode::Implicit] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let server = tokio::spawn(serve_preauthenticated(
+                listener,
+                tls_mode == TlsMode::StartTls,
+            ));
+
+            let options = ConnectOptions {
+                tls_mode,
+                insecure_skip_verify: true,
+                ..ConnectOptions::default()
+            };
+            let client = SieveClient::connect_anonymous("127.0.0.1".to_string(), port, options)
+                .await
+                .unwrap();
+            assert!(client.is_tls_active());
+            assert_eq!(client.sasl_mechanism(), None);
+            assert_eq!(
+                client.list_scripts().await.unwrap(),
+                vec![("main".to_string(), true)]
+            );
+            server.await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_compare_tls_capabilities() {
+        let capabilities = |sasl: &str, sieve: &str| {
+            let mut capabilities = Capabilities::default();
+            for (name, value) in [("SASL", sasl), ("SIEVE", sieve)] {
+                SieveClient::update_capabilities(
+                    &mut capabilities,
+                    name.to_string(),
+                    Some(value.to_string()),
+                );
+            }
+            capabilities
+        };
+
+        // Plaintext mechanisms are commonly only offered once TLS is up
+        let before = capabilities("SCRAM-SHA-256", "fileinto vacation");
+        let after = capabilities("PLAIN scram-sha-256", "Vacation fileinto");
+        assert!(SieveClient::compare_tls_capabilities(&before, &after).is_ok());
+        assert!(
+            SieveClient::compare_tls_capabilities(&capabilities("", "fileinto vacation"), &after)
+                .is_ok()
+        );
+
+        let after = capabilities("PLAIN", "fileinto vacation");
+        assert!(matches!(
+            SieveClient::compare_tls_capabilities(&before, &after),
+            Err(ConnectError::ProtocolError(message)) if message.ends_with("SCRAM-SHA-256")
+        ));
+
+        let after = capabilities("SCRAM-SHA-256", "fileinto");
+        assert!(matches!(
+            SieveClient::compare_tls_capabilities(&before, &after),
+            Err(ConnectError::ProtocolError(message)) if message.ends_with("vacation")
+        ));
+
+        // Neither are extensions that only show up once TLS is up
+        let after = capabilities("SCRAM-SHA-256", "fileinto vacation editheader");
+        assert!(SieveClient::compare_tls_capabilities(&before, &after).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_starttls_downgrade() {
+        for compare_tls_capabilities in [true, false] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let server = tokio::spawn(async move {
+                let mut stream = accept_starttls(
+                    listener,
+                    b"\"SIEVE\" \"fileinto vacation\"\r\n\"SASL\" \"SCRAM-SHA-256\"\r\n\"STARTTLS\"\r\nOK\r\n",
+                )
+                .await;
+                stream
+                    .write_all(b"\"SIEVE\" \"fileinto vacation\"\r\n\"SASL\" \"PLAIN\"\r\nOK\r\n")
+                    .await
+                    .unwrap();
+                // With the check turned off the client logs in
+                let mut line = String::new();
+                let _ = BufReader::new(stream).read_line(&mut line).await;
+                line
+            });
+
+            let options = ConnectOptions {
+                insecure_skip_verify: true,
+                compare_tls_capabilities,
+                ..ConnectOptions::default()
+            };
+            let result = SieveClient::connect_with_options(
+                "127.0.0.1".to_string(),
+                port,
+                "user",
+                "secret",
+                options,
+            )
+            .await;
+            if compare_tls_capabilities {
+                assert!(matches!(
+                    result,
+                    Err(ConnectError::ProtocolError(message)) if message.contains("downgrade")
+                ));
+                assert_eq!(server.await.unwrap(), "");
+            } else {
+                drop(result);
+                assert!(server.await.unwrap().starts_with("AUTHENTICATE \"PLAIN\""));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_starttls_adds_plain() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let stream = accept_starttls(
+                listener,
+                b"\"SIEVE\" \"fileinto\"\r\n\"SASL\" \"\"\r\n\"STARTTLS\"\r\nOK\r\n",
+            )
+            .await;
+            let mut stream = BufReader::new(stream);
+            // As servers refusing plaintext logins in the clear commonly do
+            stream
+                .write_all(b"\"SIEVE\" \"fileinto\"\r\n\"SASL\" \"PLAIN\"\r\nOK\r\n")
+                .await
+                .unwrap();
+            let mut command = String::new();
+            stream.read_line(&mut command).await.unwrap();
+            stream.write_all(b"OK\r\n").await.unwrap();
+            command
+        });
+
+        // Only the certificate check is relaxed, for the test certificate
+        let options = ConnectOptions {
+            insecure_skip_verify: true,
+            ..ConnectOptions::default()
+        };
+        let client = SieveClient::connect_with_options(
+            "127.0.0.1".to_string(),
+            port,
+            "user",
+            "pass",
+            options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(client.sasl_mechanism(), Some("PLAIN"));
+        assert_eq!(
+            server.await.unwrap(),
+            "AUTHENTICATE \"PLAIN\" \"AHVzZXIAcGFzcw==\"\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_response_lines() {
+        let mut input: &[u8] =
+            b"\"IMPLEMENTATION\" \"Test\"\r\n\"OKAY\"\r\nOK \"Done\"\r\nNOOP\r\n";
+        let lines = read_response_lines(&mut input).await.unwrap();
+        assert_eq!(
+            lines,
+            vec!["\"IMPLEMENTATION\" \"Test\"", "\"OKAY\"", "OK \"Done\""]
+        );
+        // Everything after the status line is left for the next command
+        assert_eq!(input, b"NOOP\r\n");
+
+        let mut input: &[u8] = b"no (NONEXISTENT) \"Unknown command\"\r\n";
+        let lines = read_response_lines(&mut input).await.unwrap();
+        assert_eq!(lines, vec!["no (NONEXISTENT) \"Unknown command\""]);
+
+        // A connection closed before the status line is an error, not an endless loop
+        let mut input: &[u8] = b"\"partial\"\r\n";
+        assert!(read_response_lines(&mut input).await.is_err());
+    }
+
+    // Reads capabilities from `input`, returning them and what is left unread
+    async fn read_capabilities_from(input: &[u8]) -> (Result<Capabilities, ConnectError>, String) {
+        let mut reader = BufReader::new(input);
+        let capabilities = SieveClient::read_capabilities(&mut reader).await;
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).await.unwrap();
+        (capabilities, rest)
+    }
+
+    #[tokio::test]
+    async fn test_read_capabilities_framing() {
+        // Dovecot ends with a text after the OK
+        let (capabilities, rest) = read_capabilities_from(
+            b"\"IMPLEMENTATION\" \"Dovecot Pigeonhole\"\r\n\"SIEVE\" \"fileinto vacation\"\r\n\"NOTIFY\" \"mailto\"\r\n\"SASL\" \"PLAIN LOGIN\"\r\n\"STARTTLS\"\r\n\"VERSION\" \"1.0\"\r\nOK \"Dovecot ready.\"\r\nNOOP\r\n",
+        )
+        .await;
+        let capabilities = capabilities.unwrap();
+        assert_eq!(
+            capabilities.implementation.as_deref(),
+            Some("Dovecot Pigeonhole")
+        );
+        assert_eq!(capabilities.sasl, vec!["PLAIN", "LOGIN"]);
+        assert!(capabilities.starttls);
+        assert_eq!(rest, "NOOP\r\n");
+
+        // Cyrus ends with a bare OK, some versions with an empty line around it
+        for input in [
+            &b"\"IMPLEMENTATION\" \"Cyrus timsieved v3.4\"\r\n\"SASL\" \"PLAIN\"\r\n\"SIEVE\" \"fileinto\"\r\n\"UNAUTHENTICATE\"\r\nOK\r\nNOOP\r\n"[..],
+            b"\"IMPLEMENTATION\" \"Cyrus timsieved v3.4\"\r\n\"SASL\" \"PLAIN\"\r\n\"SIEVE\" \"fileinto\"\r\n\"UNAUTHENTICATE\"\r\n\r\nOK\r\nNOOP\r\n",
+            b"\"IMPLEMENTATION\" \"Cyrus timsieved v3.4\"\r\n\"SASL\" \"PLAIN\"\r\n\"SIEVE\" \"fileinto\"\r\n\"UNAUTHENTICATE\"\r\nOK\r\n\r\nNOOP\r\n",
+        ] {
+            let (capabilities, rest) = read_capabilities_from(input).await;
+            let capabilities = capabilities.unwrap();
+            assert_eq!(capabilities.sasl, vec!["PLAIN"]);
+            assert_eq!(capabilities.sieve, vec!["fileinto"]);
+            assert!(capabilities.has_capability("UNAUTHENTICATE"));
+            // A trailing empty line is left for the next read, which skips it
+            assert!(rest.trim_start() == "NOOP\r\n", "{:?}", rest);
+        }
+
+        // A closed connection or a refusal ends the read instead of looping
+        let (capabilities, _) = read_capabilities_from(b"\"SASL\" \"PLAIN\"\r\n").await;
+        assert!(matches!(
+            capabilities,
+            Err(ConnectError::ConnectionFailed(_))
+        ));
+        let (capabilities, _) = read_capabilities_from(b"BYE \"Too many connections\"\r\n").await;
+        assert!(matches!(
+            capabilities,
+            Err(ConnectError::ProtocolError(message)) if message.contains("Too many connections")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_list_scripts_framing() {
+        // Empty lines around the status line, Cyrus style, must not leak into
+        // the response of the next command
+        let client = scripted_client(
+            b"\"vacation\"\r\n\"main\" ACTIVE\r\n\r\nOK\r\n\r\n\"main\" ACTIVE\r\nOK \"Listscripts completed.\"\r\n\r\nOK \"NOOP completed.\"\r\n",
+        );
         assert_eq!(
-            capabilities.implementation,
-            Some("Dovecot ManageSieve server v2.3.16".to_string())
+            client.list_scripts().await.unwrap(),
+            vec![("vacation".to_string(), false), ("main".to_string(), true)]
         );
         assert_eq!(
-            capabilities.sasl,
-            vec!["PLAIN", "LOGIN", "DIGEST-MD5", "CRAM-MD5"]
+            client.list_scripts().await.unwrap(),
+            vec![("main".to_string(), true)]
         );
-        assert_eq!(capabilities.starttls, true);
-        assert_eq!(capabilities.maxredirects, Some(10));
-        assert_eq!(capabilities.version, Some("1.0".to_string()));
-        assert!(capabilities.sieve.contains(&"fileinto".to_string()));
-        assert!(capabilities.sieve.contains(&"vacation".to_string()));
-        assert!(capabilities.sieve.contains(&"variables".to_string()));
+        assert_eq!(
+            client.raw_command("NOOP").await.unwrap(),
+            vec!["OK \"NOOP completed.\""]
+        );
+    }
 
-        println!("\n✓ All capabilities parsed successfully!");
+    #[test]
+    fn test_parse_response_code() {
+        assert_eq!(ResponseCode::parse("quota"), ResponseCode::Quota);
+        assert_eq!(
+            ResponseCode::parse("QUOTA/MAXSIZE"),
+            ResponseCode::QuotaMaxSize
+        );
+        assert_eq!(ResponseCode::parse("QUOTA/MAXFOO"), ResponseCode::Quota);
+        assert_eq!(
+            ResponseCode::parse("NONEXISTENT"),
+            ResponseCode::NonExistent
+        );
+        assert_eq!(
+            ResponseCode::parse("TAG \"check 1\""),
+            ResponseCode::Tag("check 1".to_string())
+        );
+        assert_eq!(
+            ResponseCode::parse("SASL \"dj1BQQ==\""),
+            ResponseCode::Sasl("dj1BQQ==".to_string())
+        );
+        assert_eq!(
+            ResponseCode::parse("REFERRAL \"sieve://other\""),
+            ResponseCode::Other("REFERRAL \"sieve://other\"".to_string())
+        );
+
+        assert_eq!(
+            ResponseCode::QuotaMaxScripts.to_string(),
+            "QUOTA/MAXSCRIPTS"
+        );
+        assert_eq!(
+            ResponseCode::Tag("a \"b\"".to_string()).to_string(),
+            "TAG \"a \\\"b\\\"\""
+        );
+    }
+
+    #[test]
+    fn test_parse_status_line() {
+        assert_eq!(
+            parse_status_line("OK"),
+            Some((Status::Ok, None, ResponseText::None))
+        );
+        assert_eq!(
+            parse_status_line("no (QUOTA/MAXSIZE) \"Script is \\\"too\\\" big\""),
+            Some((
+                Status::No,
+                Some("QUOTA/MAXSIZE".to_string()),
+                ResponseText::Quoted("Script is \"too\" big".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_status_line("OK (SASL \"a)b\")"),
+            Some((
+                Status::Ok,
+                Some("SASL \"a)b\"".to_string()),
+                ResponseText::None
+            ))
+        );
+        assert_eq!(
+            parse_status_line("BYE {12}"),
+            Some((Status::Bye, None, ResponseText::Literal(12)))
+        );
+        assert_eq!(parse_status_line("\"OKAY\""), None);
+        assert_eq!(parse_status_line("OKAY"), None);
+        assert_eq!(parse_status_line("\"SIEVE\" \"fileinto\""), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_response() {
+        // Capabilities sent again before the status line are skipped
+        let mut input: &[u8] =
+            b"\"IMPLEMENTATION\" \"Test\"\r\n\"SIEVE\" \"fileinto\"\r\nOK \"Done\"\r\nNOOP\r\n";
+        let response = read_response(&mut input).await.unwrap();
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(response.text.as_deref(), Some("Done"));
+        assert_eq!(input, b"NOOP\r\n");
+
+        let mut input: &[u8] = b"NO (QUOTA/MAXSCRIPTS) {12}\r\nToo\r\nmany :(\r\nOK\r\n";
+        let response = read_response(&mut input).await.unwrap();
+        assert_eq!(response.status, Status::No);
+        assert_eq!(response.code, Some(ResponseCode::QuotaMaxScripts));
+        assert!(response.is_quota());
+        assert_eq!(response.message(), "Too\r\nmany :(");
+        assert_eq!(input, b"OK\r\n");
+
+        let mut input: &[u8] = b"NO {1000000000}\r\n";
+        assert!(matches!(
+            read_response(&mut input).await,
+            Err(ManageSieveError::OutOfSync(_))
+        ));
+
+        let mut input: &[u8] = b"\"partial\"\r\n";
+        assert!(read_response(&mut input).await.is_err());
+
+        // Data lines are handed to the caller, blank ones are skipped
+        let mut input: &[u8] = b"\"vacation\" ACTIVE\r\n\r\nOK\r\n";
+        assert!(matches!(
+            read_response_line(&mut input).await.unwrap(),
+            ResponseLine::Data(line) if line == "\"vacation\" ACTIVE"
+        ));
+        assert!(matches!(
+            read_response_line(&mut input).await.unwrap(),
+            ResponseLine::Status(Response {
+                status: Status::Ok,
+                ..
+            })
+        ));
     }
 
     #[test]
@@ -978,6 +3217,894 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_refresh_capabilities() {
+        let client = scripted_client(
+            b"\"IMPLEMENTATION\" \"Test\"\r\n\"SIEVE\" \"fileinto vacation\"\r\n\"UNAUTHENTICATE\"\r\nOK\r\n\
+              NO \"Not now\"\r\n",
+        );
+        assert!(!client.capabilities().has_capability("UNAUTHENTICATE"));
+
+        let capabilities = client.refresh_capabilities().await.unwrap();
+        assert_eq!(capabilities.implementation.as_deref(), Some("Test"));
+        assert_eq!(capabilities.sieve, vec!["fileinto", "vacation"]);
+        assert!(client.capabilities().has_capability("UNAUTHENTICATE"));
+
+        // A refusal keeps the capabilities there were
+        assert!(matches!(
+            client.refresh_capabilities().await,
+            Err(ManageSieveError::ServerError { message, .. }) if message == "Not now"
+        ));
+        assert_eq!(client.capabilities().sieve, vec!["fileinto", "vacation"]);
+    }
+
+    #[test]
+    fn test_parse_script_line() {
+        assert_eq!(
+            parse_script_line("\"main\" ACTIVE"),
+            Some(("main".to_string(), true))
+        );
+        assert_eq!(
+            parse_script_line("\"main\" active"),
+            Some(("main".to_string(), true))
+        );
+        assert_eq!(
+            parse_script_line("\"inactive\""),
+            Some(("inactive".to_string(), false))
+        );
+        assert_eq!(
+            parse_script_line("\"active-filters\""),
+            Some(("active-filters".to_string(), false))
+        );
+        assert_eq!(
+            parse_script_line("\"ACTIVE\" ACTIVE"),
+            Some(("ACTIVE".to_string(), true))
+        );
+        assert_eq!(
+            parse_script_line("\"say \\\"ACTIVE\\\"\""),
+            Some(("say \"ACTIVE\"".to_string(), false))
+        );
+        assert_eq!(parse_script_line("\"main\" INACTIVE"), None);
+        assert_eq!(parse_script_line("\"main\" ACTIVEX"), None);
+        assert_eq!(parse_script_line("main ACTIVE"), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_script_list() {
+        let mut input: &[u8] =
+            b"\"vacation\"\r\n\"main\" ACTIVE\r\nOK \"Listscripts completed.\"\r\nNOOP\r\n";
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        read_script_list(&mut input, &sender).await.unwrap();
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            ("vacation".to_string(), false)
+        );
+        assert_eq!(receiver.try_recv().unwrap(), ("main".to_string(), true));
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(input, b"NOOP\r\n");
+
+        // A dropped receiver still consumes the whole response
+        let mut input: &[u8] = b"\"main\" ACTIVE\r\nOK\r\nNOOP\r\n";
+        let (sender, receiver) = mpsc::unbounded_channel();
+        drop(receiver);
+        read_script_list(&mut input, &sender).await.unwrap();
+        assert_eq!(input, b"NOOP\r\n");
+
+        let mut input: &[u8] = b"NO (TRYLATER) \"Mailbox locked\"\r\n";
+        assert!(matches!(
+            read_script_list(&mut input, &sender).await,
+            Err(ManageSieveError::ServerError { code: Some(ResponseCode::TryLater), message })
+                if message == "Mailbox locked"
+        ));
+    }
+
+    #[test]
+    fn test_literal_length() {
+        assert_eq!(literal_length("{0}"), Some(0));
+        assert_eq!(literal_length("{42}"), Some(42));
+        assert_eq!(literal_length("{42+}"), Some(42));
+        assert_eq!(literal_length("{+}"), None);
+        assert_eq!(literal_length("42"), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_script_non_synchronizing_literal() {
+        let client = scripted_client(b"{6+}\r\nkeep;\n\r\nOK\r\n");
+        assert_eq!(client.get_script("main").await.unwrap(), "keep;\n");
+    }
+
+    #[tokio::test]
+    async fn test_read_script_literal() {
+        let mut input: &[u8] = b"keep;\r\n\r\nOK\r\nNOOP\r\n";
+        let mut content = Vec::new();
+        read_script_literal(&mut input, 7, &mut content)
+            .await
+            .unwrap();
+        assert_eq!(content, b"keep;\r\n");
+        assert_eq!(input, b"NOOP\r\n");
+
+        // Not UTF-8, and ending in what looks like the end of the literal
+        let mut input: &[u8] = b"\xff\xfe\r\n\r\n\r\nOK\r\n";
+        let mut content = Vec::new();
+        read_script_literal(&mut input, 4, &mut content)
+            .await
+            .unwrap();
+        assert_eq!(content, b"\xff\xfe\r\n");
+        assert!(input.is_empty());
+
+        // The server announced fewer bytes than it sent
+        let mut input: &[u8] = b"require \"fileinto\";\r\nfileinto \"Junk\";\r\n\r\nOK\r\n";
+        assert!(matches!(
+            read_script_literal(&mut input, 9, &mut Vec::new()).await,
+            Err(ManageSieveError::OutOfSync(_))
+        ));
+
+        // Cut off right at a line break
+        let mut input: &[u8] = b"keep;\r\nstop;\r\n\r\nOK\r\n";
+        assert!(matches!(
+            read_script_literal(&mut input, 5, &mut Vec::new()).await,
+            Err(ManageSieveError::OutOfSync(_))
+        ));
+
+        // The connection ends inside the literal
+        let mut input: &[u8] = b"keep;";
+        assert!(matches!(
+            read_script_literal(&mut input, 7, &mut Vec::new()).await,
+            Err(ManageSieveError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_script_to() {
+        // Larger than the BufReader's buffer, so it arrives in several chunks
+        let script = "# filler line\r\n".repeat(2000);
+        let response = format!("{{{}}}\r\n{}\r\nOK\r\n", script.len(), script);
+        let client = client_over(
+            Box::new(io::Cursor::new(response.into_bytes())),
+            Box::new(tokio::io::sink()),
+        );
+
+        let mut content = Vec::new();
+        client.get_script_to("main", &mut content).await.unwrap();
+        assert_eq!(content, script.as_bytes());
+
+        // The rest of a script longer than announced must not be taken for the
+        // answer to the next command
+        let client = scripted_client(b"{5}\r\nkeep;\r\nstop;\r\n\r\nOK\r\n\"other\"\r\nOK\r\n");
+        assert!(matches!(
+            client.get_script_to("main", &mut Vec::new()).await,
+            Err(ManageSieveError::OutOfSync(_))
+        ));
+        assert!(matches!(
+            client.list_scripts().await,
+            Err(ManageSieveError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_script_to_write_error() {
+        struct FailingWriter;
+
+        impl AsyncWrite for FailingWriter {
+            fn poll_write(
+                self: std::pin::Pin<&mut Self>,
+                _: &mut std::task::Context<'_>,
+                _: &[u8],
+            ) -> std::task::Poll<io::Result<usize>> {
+                std::task::Poll::Ready(Err(io::Error::other("disk full")))
+            }
+
+            fn poll_flush(
+                self: std::pin::Pin<&mut Self>,
+                _: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<io::Result<()>> {
+                std::task::Poll::Ready(Ok(()))
+            }
+
+            fn poll_shutdown(
+                self: std::pin::Pin<&mut Self>,
+                _: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<io::Result<()>> {
+                std::task::Poll::Ready(Ok(()))
+            }
+        }
+
+        // The whole response is still read, so the next one starts in sync
+        let mut input: &[u8] = b"keep;\r\n\r\nOK\r\nNOOP\r\n";
+        assert!(matches!(
+            read_script_literal(&mut input, 7, &mut FailingWriter).await,
+            Err(ManageSieveError::IoError(err)) if err.to_string() == "disk full"
+        ));
+        assert_eq!(input, b"NOOP\r\n");
+    }
+
+    #[test]
+    fn test_sieve_error_conversion() {
+        let err = SieveError::from(ConnectError::AuthenticationFailed("bad".to_string()));
+        assert_eq!(err, SieveError::Authentication("bad".to_string()));
+        assert_eq!(err.to_string(), "Login failed: bad");
+
+        let err = SieveError::from(ManageSieveError::IoError(io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "reset",
+        )));
+        assert!(matches!(err, SieveError::Network(_)));
+
+        let err = SieveError::from(ManageSieveError::Quota {
+            code: ResponseCode::QuotaMaxSize,
+            message: "too big".to_string(),
+        });
+        assert_eq!(err, SieveError::Quota("too big".to_string()));
+        assert_eq!(
+            SieveError::from(ManageSieveError::InvalidResponse("?".to_string())),
+            SieveError::Protocol("?".to_string())
+        );
+        assert_eq!(
+            SieveError::from(ManageSieveError::ServerClosed("bye".to_string())),
+            SieveError::Disconnected("bye".to_string())
+        );
+    }
+
+    // An authenticated client whose server answers with `responses`, whatever
+    // is sent to it
+    fn scripted_client(responses: &'static [u8]) -> SieveClient {
+        client_over(Box::new(responses), Box::new(tokio::io::sink()))
+    }
+
+    // An authenticated client talking to `fake_server`
+    fn fake_server_client() -> SieveClient {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(fake_server(server));
+        let (reader, writer) = tokio::io::split(client);
+        client_over(Box::new(reader), Box::new(writer))
+    }
+
+    // A minimal ManageSieve server keeping its scripts in memory. It only knows
+    // PUTSCRIPT and GETSCRIPT, and is strict about the literals it is sent.
+    async fn fake_server(stream: tokio::io::DuplexStream) {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+        let mut scripts: HashMap<String, Vec<u8>> = HashMap::new();
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.strip_suffix("\r\n").expect("command without CRLF");
+            let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let (_, name) = parse_quoted_string(rest).expect("script name");
+            let rest = &rest[rest.rfind('"').unwrap() + 1..];
+
+            let response = match command {
+                "PUTSCRIPT" => {
+                    let Some(length) = rest
+                        .trim()
+                        .strip_prefix('{')
+                        .and_then(|literal| literal.strip_suffix("+}"))
+                        .and_then(|length| length.parse().ok())
+                    else {
+                        let response = b"BYE \"Expected a non-synchronizing literal\"\r\n";
+                        writer.write_all(response).await.unwrap();
+                        return;
+                    };
+                    let mut content = vec![0; length];
+                    reader.read_exact(&mut content).await.unwrap();
+                    let mut end = [0; 2];
+                    reader.read_exact(&mut end).await.unwrap();
+                    if &end != b"\r\n" {
+                        let response = b"BYE \"Expected CRLF after the literal\"\r\n";
+                        writer.write_all(response).await.unwrap();
+                        return;
+                    }
+                    scripts.insert(name, content);
+                    b"OK\r\n".to_vec()
+                }
+                "GETSCRIPT" => match scripts.get(&name) {
+                    Some(content) => {
+                        let mut response = format!("{{{}}}\r\n", content.len()).into_bytes();
+                        response.extend_from_slice(content);
+                        response.extend_from_slice(b"\r\nOK\r\n");
+                        response
+                    }
+                    None => b"NO (NONEXISTENT) \"No such script\"\r\n".to_vec(),
+                },
+                _ => b"NO \"Unsupported command\"\r\n".to_vec(),
+            };
+            writer.write_all(&response).await.unwrap();
+        }
+    }
+
+    fn client_over(reader: Reader, writer: Writer) -> SieveClient {
+        SieveClient {
+            connections: vec![Arc::new(Mutex::new((BufReader::new(reader), writer)))],
+            next_connection: AtomicUsize::new(0),
+            server: RwLock::new(Server {
+                capabilities: Arc::new(Capabilities::default()),
+                tls_active: true,
+                peer_certificates: Vec::new(),
+                cert_store: None,
+                mechanism: None,
+            }),
+            options: ConnectOptions::default(),
+            host: "localhost".to_string(),
+            port: 4190,
+            credentials: None,
+            authenticated: true,
+            keepalive: None,
+            logged_out: AtomicBool::new(false),
+            span: Span::none(),
+        }
+    }
+
+    // A client with a session per response script that offers UNAUTHENTICATE
+    fn unauthenticate_client(responses: &[&'static [u8]]) -> SieveClient {
+        let mut client = scripted_client(responses[0]);
+        for response in &responses[1..] {
+            client.connections.push(Arc::new(Mutex::new((
+                BufReader::new(Box::new(*response)),
+                Box::new(tokio::io::sink()),
+            ))));
+        }
+        Arc::make_mut(&mut client.server_mut().capabilities)
+            .other
+            .insert("UNAUTHENTICATE".to_string(), String::new());
+        client.server_mut().mechanism = Some("PLAIN");
+        client
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticate() {
+        let mut client = unauthenticate_client(&[b"OK\r\n", b"OK\r\n"]);
+        client.unauthenticate().await.unwrap();
+        assert!(!client.authenticated);
+        assert_eq!(client.sasl_mechanism(), None);
+        assert!(matches!(
+            client.unauthenticate().await,
+            Err(ManageSieveError::ProtocolError(_))
+        ));
+
+        // The first session is already unauthenticated when the second fails,
+        // neither is used again
+        let mut client = unauthenticate_client(&[
+            b"OK\r\n\"main\" ACTIVE\r\nOK\r\n",
+            b"NO \"Not now\"\r\n\"main\" ACTIVE\r\nOK\r\n",
+        ]);
+        assert!(matches!(
+            client.unauthenticate().await,
+            Err(ManageSieveError::ServerError { .. })
+        ));
+        assert!(client.authenticated);
+        for _ in 0..2 {
+            assert!(matches!(
+                client.list_scripts().await,
+                Err(ManageSieveError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_rolls_back() {
+        // The second session refuses the login, the first is unauthenticated again
+        let mut client = unauthenticate_client(&[
+            b"OK\r\nOK\r\n\"main\" ACTIVE\r\nOK\r\n",
+            b"NO \"Invalid credentials\"\r\n\"main\" ACTIVE\r\nOK\r\n",
+        ]);
+        client.authenticated = false;
+        client.server_mut().mechanism = None;
+        Arc::make_mut(&mut client.server_mut().capabilities).sasl = vec!["PLAIN".to_string()];
+
+        assert!(matches!(
+            client.authenticate("bob", "wrong").await,
+            Err(ConnectError::AuthenticationFailed(_))
+        ));
+        assert!(!client.authenticated);
+        assert!(client.credentials.is_none());
+        assert_eq!(client.sasl_mechanism(), None);
+        // Both sessions are still usable, the UNAUTHENTICATE answer was read
+        let first = client.connections[0].clone();
+        let busy = first.lock().await;
+        assert_eq!(
+            client.list_scripts().await.unwrap(),
+            vec![("main".to_string(), true)]
+        );
+        drop(busy);
+        assert_eq!(
+            client.list_scripts().await.unwrap(),
+            vec![("main".to_string(), true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_script_errors() {
+        scripted_client(b"OK\r\n")
+            .delete_script("old")
+            .await
+            .unwrap();
+        assert!(matches!(
+            scripted_client(b"NO (NONEXISTENT) \"There is no script by that name\"\r\n")
+                .delete_script("old")
+                .await,
+            Err(ManageSieveError::ScriptNotFound(name)) if name == "old"
+        ));
+        assert!(matches!(
+            scripted_client(b"NO (ACTIVE) \"You may not delete an active script\"\r\n")
+                .delete_script("main")
+                .await,
+            Err(ManageSieveError::ScriptActive(name)) if name == "main"
+        ));
+        // Without a code the reason is unknown
+        assert!(matches!(
+            scripted_client(b"NO \"Permission denied\"\r\n")
+                .delete_script("old")
+                .await,
+            Err(ManageSieveError::ServerError { code: None, message })
+                if message == "Permission denied"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_script_command_errors() {
+        let not_found = b"NO (NONEXISTENT) \"There is no script by that name\"\r\n";
+        assert!(matches!(
+            scripted_client(not_found).get_script("old").await,
+            Err(ManageSieveError::ScriptNotFound(name)) if name == "old"
+        ));
+        assert!(matches!(
+            scripted_client(not_found).rename_script("old", "new").await,
+            Err(ManageSieveError::ScriptNotFound(name)) if name == "old"
+        ));
+        assert!(matches!(
+            scripted_client(not_found).set_active_script("old").await,
+            Err(ManageSieveError::ScriptNotFound(name)) if name == "old"
+        ));
+
+        // Refusals for other reasons keep their code instead of claiming the
+        // script is gone
+        assert!(matches!(
+            scripted_client(b"NO (ALREADYEXISTS) \"A script by that name exists\"\r\n")
+                .rename_script("old", "new")
+                .await,
+            Err(ManageSieveError::ServerError {
+                code: Some(ResponseCode::AlreadyExists),
+                ..
+            })
+        ));
+        assert!(matches!(
+            scripted_client(b"NO (TRYLATER) \"Mailbox locked\"\r\n")
+                .get_script("old")
+                .await,
+            Err(ManageSieveError::ServerError {
+                code: Some(ResponseCode::TryLater),
+                ..
+            })
+        ));
+        assert!(matches!(
+            scripted_client(b"NO (TRYLATER) \"Mailbox locked\"\r\n")
+                .set_active_script("old")
+                .await,
+            Err(ManageSieveError::ServerError {
+                code: Some(ResponseCode::TryLater),
+                ..
+            })
+        ));
+        assert!(matches!(
+            scripted_client(b"NO (QUOTA) \"Over quota\"\r\n")
+                .set_active_script("old")
+                .await,
+            Err(ManageSieveError::Quota {
+                code: ResponseCode::Quota,
+                ..
+            })
+        ));
+        assert!(matches!(
+            scripted_client(b"NO (QUOTA/MAXSCRIPTS) \"Too many scripts\"\r\n")
+                .set_active_script("old")
+                .await,
+            Err(ManageSieveError::Quota {
+                code: ResponseCode::QuotaMaxScripts,
+                message,
+            }) if message == "Too many scripts"
+        ));
+        assert!(matches!(
+            scripted_client(b"NO \"Permission denied\"\r\n")
+                .get_script("old")
+                .await,
+            Err(ManageSieveError::ServerError { code: None, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_noop() {
+        scripted_client(b"OK \"Done\"\r\n")
+            .noop(None)
+            .await
+            .unwrap();
+        scripted_client(b"OK (TAG \"check 1\") \"Done\"\r\n")
+            .noop(Some("check 1"))
+            .await
+            .unwrap();
+
+        // An answer to some other NOOP
+        assert!(matches!(
+            scripted_client(b"OK (TAG \"check 0\") \"Done\"\r\n")
+                .noop(Some("check 1"))
+                .await,
+            Err(ManageSieveError::InvalidResponse(_))
+        ));
+        assert!(
+            scripted_client(b"NO \"Not now\"\r\n")
+                .noop(None)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_tls_server_name() {
+        let options = ConnectOptions::default();
+        assert_eq!(
+            tls_server_name("mail.example.com", &options).unwrap(),
+            ServerName::try_from("mail.example.com").unwrap()
+        );
+
+        // Through a tunnel the certificate is still checked for the real server
+        let options = ConnectOptions {
+            tls_server_name: Some("mail.example.com".to_string()),
+            ..options
+        };
+        assert_eq!(
+            tls_server_name("localhost", &options).unwrap(),
+            ServerName::try_from("mail.example.com").unwrap()
+        );
+        assert_eq!(
+            tls_server_name("127.0.0.1", &options).unwrap(),
+            ServerName::try_from("mail.example.com").unwrap()
+        );
+
+        let options = ConnectOptions {
+            tls_server_name: Some("not a name".to_string()),
+            ..options
+        };
+        assert!(matches!(
+            tls_server_name("localhost", &options),
+            Err(ConnectError::ProtocolError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_command_timeout() {
+        // The server stops in the middle of the script and keeps the connection open
+        let (client_side, mut server) = tokio::io::duplex(1024);
+        server.write_all(b"{20}\r\nkeep;").await.unwrap();
+        let (reader, writer) = tokio::io::split(client_side);
+        let mut client = client_over(Box::new(reader), Box::new(writer));
+        client.options.command_timeout = Some(Duration::from_millis(50));
+
+        assert!(matches!(
+            client.get_script("main").await,
+            Err(ManageSieveError::Timeout(timeout)) if timeout == Duration::from_millis(50)
+        ));
+
+        // The rest of the script must not be taken for the answer to the next
+        // command. The client has closed its end by now, so this may fail.
+        let _ = server.write_all(b"\r\nstop;\r\n\r\nOK\r\n").await;
+        assert!(matches!(
+            client.list_scripts().await,
+            Err(ManageSieveError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_logout() {
+        let client = scripted_client(b"OK \"Logout completed\"\r\n");
+        client.logout().await.unwrap();
+        assert!(client.logged_out.load(Ordering::Relaxed));
+
+        // BYE, or closing the connection without a word, count as done too
+        scripted_client(b"BYE \"Bye\"\r\n").logout().await.unwrap();
+        scripted_client(b"").logout().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_tcp_from_local_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let local_address: IpAddr = "127.0.0.1".parse().unwrap();
+
+        let stream = connect_tcp(&address, Some(local_address)).await.unwrap();
+        assert_eq!(stream.local_addr().unwrap().ip(), local_address);
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer, stream.local_addr().unwrap());
+
+        // An IPv6 address can't reach the server's IPv4 address
+        let err = connect_tcp(&address, Some("::1".parse().unwrap()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_connection() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Answers two connections, returning what the second one sent
+        let server = tokio::spawn(async move {
+            let mut command = String::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                stream
+                    .write_all(b"\"IMPLEMENTATION\" \"Test\"\r\n\"SASL\" \"PLAIN\"\r\nOK\r\n")
+                    .await
+                    .unwrap();
+                command.clear();
+                stream.read_line(&mut command).await.unwrap();
+                let _ = stream.write_all(b"OK\r\n").await;
+            }
+            command
+        });
+
+        // Credentials only go out in the clear when TLS isn't required
+        let options = ConnectOptions {
+            tls_mode: TlsMode::None,
+            ..ConnectOptions::default()
+        };
+        let err = SieveClient::connect_with_options(
+            "127.0.0.1".to_string(),
+            port,
+            "user",
+            "pass",
+            options.clone(),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(matches!(err, ConnectError::AuthenticationFailed(_)));
+
+        let client = SieveClient::connect_with_options(
+            "127.0.0.1".to_string(),
+            port,
+            "user",
+            "pass",
+            ConnectOptions {
+                require_tls: false,
+                ..options
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            client.capabilities().implementation.as_deref(),
+            Some("Test")
+        );
+        assert_eq!(
+            server.await.unwrap(),
+            "AUTHENTICATE \"PLAIN\" \"AHVzZXIAcGFzcw==\"\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // The first connection is dropped after the login, the second one
+        // refuses it and the third one, after an upgrade of the server,
+        // answers a LISTSCRIPTS as well
+        tokio::spawn(async move {
+            let sessions: [(&[u8], &[&[u8]]); 3] = [
+                (b"\"IMPLEMENTATION\" \"Old\"\r\n", &[b"OK\r\n"]),
+                (b"\"IMPLEMENTATION\" \"Old\"\r\n", &[b"NO\r\n"]),
+                (
+                    b"\"IMPLEMENTATION\" \"New\"\r\n",
+                    &[b"OK\r\n", b"\"main\" ACTIVE\r\nOK\r\n"],
+                ),
+            ];
+            for (implementation, answers) in sessions {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                stream.write_all(implementation).await.unwrap();
+                stream
+                    .write_all(b"\"SASL\" \"PLAIN\"\r\nOK\r\n")
+                    .await
+                    .unwrap();
+                for answer in answers {
+                    let mut command = String::new();
+                    stream.read_line(&mut command).await.unwrap();
+                    stream.write_all(answer).await.unwrap();
+                }
+            }
+        });
+
+        let options = ConnectOptions {
+            tls_mode: TlsMode::None,
+            require_tls: false,
+            ..ConnectOptions::default()
+        };
+        let client = SieveClient::connect_with_options(
+            "127.0.0.1".to_string(),
+            port,
+            "user",
+            "pass",
+            options,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            client.list_scripts().await,
+            Err(ManageSieveError::IoError(_))
+        ));
+
+        // A failed reconnect leaves everything as it was
+        assert!(matches!(
+            client.reconnect().await,
+            Err(ConnectError::AuthenticationFailed(_))
+        ));
+        assert_eq!(client.capabilities().implementation.as_deref(), Some("Old"));
+        assert_eq!(client.sasl_mechanism(), Some("PLAIN"));
+        assert_eq!(&*client.sasl_mechanisms(), ["PLAIN".to_string()]);
+
+        client.reconnect().await.unwrap();
+        assert_eq!(client.capabilities().implementation.as_deref(), Some("New"));
+        assert_eq!(
+            client.list_scripts().await.unwrap(),
+            vec![("main".to_string(), true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pool_uses_idle_connection() {
+        let mut client = scripted_client(b"NO \"Busy session\"\r\n");
+        client.connections.push(Arc::new(Mutex::new((
+            BufReader::new(Box::new(&b"\"main\" ACTIVE\r\nOK\r\n"[..]) as Reader),
+            Box::new(tokio::io::sink()) as Writer,
+        ))));
+
+        // The first session is held by another command
+        let busy = client.connections[0].clone();
+        let _busy = busy.lock().await;
+        assert_eq!(
+            client.list_scripts().await.unwrap(),
+            vec![("main".to_string(), true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bye_during_command() {
+        const BYE: &[u8] = b"BYE (TRYLATER) \"Server shutting down\"\r\n";
+        fn closed<T: Debug>(result: Result<T, ManageSieveError>) {
+            assert!(
+                matches!(&result, Err(ManageSieveError::ServerClosed(message)) if message == "Server shutting down"),
+                "{:?}",
+                result
+            );
+        }
+
+        closed(scripted_client(BYE).list_scripts().await);
+        closed(scripted_client(BYE).get_script("main").await);
+        closed(scripted_client(BYE).put_script("main", "keep;").await);
+        closed(scripted_client(BYE).check_script("keep;").await);
+        closed(scripted_client(BYE).delete_script("main").await);
+        closed(scripted_client(BYE).rename_script("main", "old").await);
+        closed(scripted_client(BYE).set_active_script("main").await);
+        closed(scripted_client(BYE).have_space("main", 100).await);
+
+        let mut client = scripted_client(BYE);
+        Arc::make_mut(&mut client.server_mut().capabilities)
+            .other
+            .insert("UNAUTHENTICATE".to_string(), String::new());
+        closed(client.unauthenticate().await);
+
+        // A refusal is still told apart from the end of the session
+        let client = scripted_client(b"NO \"Locked\"\r\n");
+        assert!(matches!(
+            client.delete_script("main").await,
+            Err(ManageSieveError::ServerError { .. })
+        ));
+        let client = scripted_client(b"NO \"Locked\"\r\n");
+        assert!(matches!(
+            client.list_scripts().await,
+            Err(ManageSieveError::ServerError { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_check_script() {
+        let client = scripted_client(b"OK\r\n");
+        assert_eq!(
+            client.check_script("keep;").await.unwrap(),
+            CheckResult::Clean
+        );
+
+        let client = scripted_client(b"OK (WARNINGS) \"line 2: fileinto without require\"\r\n");
+        assert_eq!(
+            client.check_script("keep;").await.unwrap(),
+            CheckResult::Warnings {
+                message: Some("line 2: fileinto without require".to_string()),
+            }
+        );
+
+        let client = scripted_client(b"OK (WARNINGS)\r\n");
+        assert_eq!(
+            client.check_script("keep;").await.unwrap(),
+            CheckResult::Warnings { message: None }
+        );
+
+        let client = scripted_client(b"NO \"line 1: syntax error\"\r\n");
+        assert!(matches!(
+            client.check_script("kep;").await,
+            Err(ManageSieveError::ServerError { message, .. }) if message == "line 1: syntax error"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_put_and_get_script_round_trip() {
+        let client = fake_server_client();
+
+        let scripts = [
+            // Quotes and backslashes must not be escaped inside a literal
+            "require \"fileinto\";\r\nif header :contains \"subject\" \"\\\"quoted\\\"\" {\r\n    fileinto \"C:\\\\Inbox\";\r\n}\r\n".to_string(),
+            // Ends in CRLF right at the end of the literal, just before the
+            // CRLF that ends the command
+            "keep;\r\n\r\n".to_string(),
+            // Looks like the end of a response and another literal
+            "# \r\nOK\r\n{5+}\r\nkeep;".to_string(),
+            // Long enough that the length takes several digits
+            format!("# {}\r\nkeep;\r\n", "ä".repeat(600)),
+            String::new(),
+        ];
+
+        for (index, script) in scripts.iter().enumerate() {
+            let name = format!("script {}", index);
+            client.put_script(&name, script).await.unwrap();
+            assert_eq!(&client.get_script(&name).await.unwrap(), script);
+        }
+
+        assert!(matches!(
+            client.get_script("missing").await,
+            Err(ManageSieveError::ScriptNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_put_script_from() {
+        let client = fake_server_client();
+
+        // Larger than the copy buffer, so it is sent in several chunks
+        let script = "# filler line\r\n".repeat(2000);
+        client
+            .put_script_from("main", script.len(), &mut script.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(client.get_script("main").await.unwrap(), script);
+
+        // Input shorter than announced leaves the server waiting for the rest
+        let client = scripted_client(b"OK\r\n\"main\" ACTIVE\r\nOK\r\n");
+        assert!(matches!(
+            client.put_script_from("main", 10, &mut &b"keep;"[..]).await,
+            Err(ManageSieveError::OutOfSync(_))
+        ));
+        assert!(matches!(
+            client.list_scripts().await,
+            Err(ManageSieveError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn test_validate_script_name() {
+        assert!(validate_script_name("vacation").is_ok());
+        assert!(validate_script_name("Ferien – Sommer 2024").is_ok());
+        assert!(matches!(
+            validate_script_name(""),
+            Err(ManageSieveError::InvalidScriptName(_))
+        ));
+        assert!(validate_script_name("line\r\nbreak").is_err());
+        assert!(validate_script_name("tab\there").is_err());
+        assert!(validate_script_name("delete\u{7f}").is_err());
+        assert!(validate_script_name("para\u{2029}graph").is_err());
+    }
+
     #[test]
     fn test_type_aliases() {
         // Test that the type aliases work correctly
@@ -1009,6 +4136,38 @@ mod tests {
         assert_eq!(decoded_string, "\0testuser\0testpass");
     }
 
+    #[test]
+    fn test_secret_password_debug_redacted() {
+        // The password must never leak through Debug
+        let mut client = client_over(Box::new(tokio::io::empty()), Box::new(tokio::io::sink()));
+        client.credentials = Some(("alice".to_string(), SecretString::from("hunter2")));
+        let debug = format!("{:?}", client);
+        assert!(debug.contains("alice"));
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn test_authentication_rejected() {
+        let client = scripted_client(b"");
+        let credentials = ("user".to_string(), SecretString::from("pass"));
+        let capabilities = Capabilities {
+            sasl: vec!["PLAIN".to_string()],
+            ..Capabilities::default()
+        };
+
+        let reader: Reader = Box::new(&b"NO (AUTH-TOO-WEAK) \"Use a stronger mechanism\"\r\n"[..]);
+        let writer: Writer = Box::new(tokio::io::sink());
+        let mut connection = (BufReader::new(reader), writer);
+        // Only the text of the response is shown, not the status and code
+        assert!(matches!(
+            client
+                .authenticate_connection(&mut connection, &capabilities, true, Some(&credentials))
+                .await,
+            Err(ConnectError::AuthenticationFailed(message))
+                if message == "Server rejected credentials: Use a stronger mechanism"
+        ));
+    }
+
     #[test]
     fn test_authentication_errors() {
         // Test authentication error types
@@ -1050,6 +4209,123 @@ mod tests {
         assert!(capabilities.sasl.contains(&"PLAIN".to_string()));
     }
 
+    // Runs a LOGIN exchange against `responses`, returning the result and
+    // everything the client sent
+    async fn login_with(responses: &[u8]) -> (Result<Response, ConnectError>, String) {
+        let mut reader = BufReader::new(responses);
+        let mut sent = Vec::new();
+        let mut client = Mechanism::Login
+            .client("user", "pass", CredentialNormalization::None)
+            .unwrap();
+        let result = sasl_exchange(&mut reader, &mut sent, Mechanism::Login, &mut *client).await;
+        (result, String::from_utf8(sent).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_login_exchange() {
+        let (result, sent) =
+            login_with(b"\"VXNlcm5hbWU6\"\r\n\"UGFzc3dvcmQ6\"\r\nOK \"Logged in\"\r\n").await;
+        assert_eq!(result.unwrap().status, Status::Ok);
+        assert_eq!(
+            sent,
+            "AUTHENTICATE \"LOGIN\"\r\n\"dXNlcg==\"\r\n\"cGFzcw==\"\r\n"
+        );
+
+        // Challenges as literals or with IMAP's `+ ` in front
+        let (result, _) = login_with(b"{12}\r\nVXNlcm5hbWU6\r\n+ UGFzc3dvcmQ6\r\nOK\r\n").await;
+        assert_eq!(result.unwrap().status, Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_login_exchange_refused() {
+        // Refused before the password was asked for
+        let (result, sent) = login_with(b"\"VXNlcm5hbWU6\"\r\nNO \"Unknown user\"\r\n").await;
+        let response = result.unwrap();
+        assert_eq!(response.status, Status::No);
+        assert_eq!(response.message(), "Unknown user");
+        assert!(!sent.contains("cGFzcw=="));
+
+        let (result, _) = login_with(b"\"not base64!\"\r\n").await;
+        assert!(matches!(result, Err(ConnectError::ProtocolError(_))));
+
+        // A challenge far too long to be one isn't read into memory
+        let (result, _) = login_with(b"{1000000000}\r\n").await;
+        assert!(matches!(result, Err(ConnectError::ProtocolError(_))));
+
+        // A third challenge is cancelled
+        let (result, sent) = login_with(b"\"\"\r\n\"\"\r\n\"\"\r\nNO\r\n").await;
+        assert!(matches!(result, Err(ConnectError::ProtocolError(_))));
+        assert!(sent.ends_with("\"*\"\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_external_exchange() {
+        let mut reader = BufReader::new(&b"OK \"Welcome\"\r\n"[..]);
+        let mut sent = Vec::new();
+        let mut client = Mechanism::External
+            .client("", "", CredentialNormalization::None)
+            .unwrap();
+        let response = sasl_exchange(&mut reader, &mut sent, Mechanism::External, &mut *client)
+            .await
+            .unwrap();
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(sent, b"AUTHENTICATE \"EXTERNAL\" \"\"\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_scram_exchange() {
+        // RFC 7677 section 3, with the server signature in the OK response
+        let responses = b"\"cj1yT3ByTkdmd0ViZVJXZ2JORWtxTyVodllEcFdVYTJSYVRDQWZ1eEZJbGopaE5sRiRrMCxzPVcyMlphSjBTTlk3c29Fc1VFamI2Z1E9PSxpPTQwOTY=\"\r\n\
+            OK (SASL \"dj02cnJpVFJCaTIzV3BSUi93dHVwK21NaFVaVW4vZEI1bkxUSlJzamw5NUc0PQ==\")\r\n";
+        let mut reader = BufReader::new(&responses[..]);
+        let mut sent = Vec::new();
+        let mut client = ScramSha256::with_nonce(
+            Zeroizing::new("user".to_string()),
+            Zeroizing::new("pencil".to_string()),
+            "rOprNGfwEbeRWgbNEkqO".to_string(),
+        );
+        let response = sasl_exchange(&mut reader, &mut sent, Mechanism::ScramSha256, &mut client)
+            .await
+            .unwrap();
+        assert_eq!(response.status, Status::Ok);
+        assert!(String::from_utf8(sent).unwrap().starts_with(
+            "AUTHENTICATE \"SCRAM-SHA-256\" \"biwsbj11c2VyLHI9ck9wck5HZndFYmVSV2diTkVrcU8=\"\r\n"
+        ));
+
+        // The same exchange with a server that doesn't know the password
+        let forged = b"\"cj1yT3ByTkdmd0ViZVJXZ2JORWtxTyVodllEcFdVYTJSYVRDQWZ1eEZJbGopaE5sRiRrMCxzPVcyMlphSjBTTlk3c29Fc1VFamI2Z1E9PSxpPTQwOTY=\"\r\n\
+            OK (SASL \"dj1BQUFBVFJCaTIzV3BSUi93dHVwK21NaFVaVW4vZEI1bkxUSlJzamw5NUc0PQ==\")\r\n";
+        let mut reader = BufReader::new(&forged[..]);
+        let mut client = ScramSha256::with_nonce(
+            Zeroizing::new("user".to_string()),
+            Zeroizing::new("pencil".to_string()),
+            "rOprNGfwEbeRWgbNEkqO".to_string(),
+        );
+        let result = sasl_exchange(
+            &mut reader,
+            &mut Vec::new(),
+            Mechanism::ScramSha256,
+            &mut client,
+        )
+        .await;
+        assert!(matches!(result, Err(ConnectError::AuthenticationFailed(_))));
+    }
+
+    #[test]
+    fn test_needs_anonymous_login() {
+        let mut capabilities = Capabilities::default();
+        assert!(!SieveClient::needs_anonymous_login(&capabilities).unwrap());
+
+        capabilities.sasl = vec!["PLAIN".to_string(), "anonymous".to_string()];
+        assert!(SieveClient::needs_anonymous_login(&capabilities).unwrap());
+
+        capabilities.sasl = vec!["PLAIN".to_string()];
+        assert!(matches!(
+            SieveClient::needs_anonymous_login(&capabilities),
+            Err(ConnectError::AuthenticationFailed(_))
+        ));
+    }
+
     #[test]
     fn test_authenticate_command_format() {
         // Test the format of the AUTHENTICATE command
@@ -1066,6 +4342,20 @@ mod tests {
         assert!(command.contains(&auth_b64));
     }
 
+    #[test]
+    fn test_authenticate_command() {
+        assert_eq!(
+            authenticate_command("PLAIN", "AHVzZXIAcGFzcw==").as_str(),
+            "AUTHENTICATE \"PLAIN\" \"AHVzZXIAcGFzcw==\"\r\n"
+        );
+
+        let token = "A".repeat(SASL_QUOTED_LIMIT + 4);
+        assert_eq!(
+            authenticate_command("PLAIN", &token).as_str(),
+            format!("AUTHENTICATE \"PLAIN\" {{260+}}\r\n{}\r\n", token)
+        );
+    }
+
     #[test]
     fn test_authentication_response_parsing() {
         // Test parsing different authentication responses
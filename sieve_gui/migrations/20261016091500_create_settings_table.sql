@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS settings (
+    key VARCHAR(255) PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE accounts ADD COLUMN tls_mode VARCHAR(16) NOT NULL DEFAULT 'starttls';
+ALTER TABLE accounts ADD COLUMN accept_invalid_certificates BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- A missing port means the default port from the settings
+ALTER TABLE accounts ADD COLUMN port INTEGER;
+ALTER TABLE accounts ADD COLUMN ca_certificate TEXT;
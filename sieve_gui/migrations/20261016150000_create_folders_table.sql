@@ -0,0 +1,6 @@
+-- Folders an account's scripts file into, offered when writing new rules
+CREATE TABLE IF NOT EXISTS folders (
+    account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    PRIMARY KEY (account_id, name)
+);
@@ -1,14 +1,79 @@
 // #![windows_subsystem = "windows"]
 
-use iced::application;
+use std::time::Duration;
 
-use crate::ui::UIWrapper;
+use iced::{Point, Size, application, window};
+use sqlx::SqlitePool;
+use tracing_subscriber::EnvFilter;
 
+use crate::{settings::WindowGeometry, ui::UIWrapper};
+
+mod settings;
 mod ui;
 
 fn main() {
-    application(UIWrapper::start, UIWrapper::update, UIWrapper::view)
-        .subscription(UIWrapper::subscription)
-        .run()
-        .unwrap();
+    // Startup notes such as the database schema by default, RUST_LOG picks
+    // anything else, e.g. `sieve_client=debug` for the protocol traffic
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("sieve_gui=info")),
+        )
+        .init();
+
+    let geometry = load_window_geometry();
+
+    let mut window = window::Settings {
+        exit_on_close_request: false,
+        ..window::Settings::default()
+    };
+    if let Some(geometry) = geometry {
+        window.size = Size::new(geometry.width, geometry.height);
+        if let Some((x, y)) = geometry.position {
+            window.position = window::Position::Specific(Point::new(x, y));
+        }
+        window.maximized = geometry.maximized;
+    }
+
+    application(
+        move || UIWrapper::start(geometry),
+        UIWrapper::update,
+        UIWrapper::view,
+    )
+    .subscription(UIWrapper::subscription)
+    .theme(UIWrapper::theme)
+    .window(window)
+    .run()
+    .unwrap();
+}
+
+// Short, as the window doesn't show until the geometry is read
+const GEOMETRY_TIMEOUT: Duration = Duration::from_secs(3);
+
+// The window is created before the app's own startup task runs, so the saved
+// geometry is read up front. Any failure just means default placement, a
+// database that doesn't answer is left to the startup task to report.
+fn load_window_geometry() -> Option<WindowGeometry> {
+    let db_path = ui::database_path()?;
+
+    let runtime = tokio::runtime::Runtime::new().ok()?;
+    let geometry = runtime.block_on(async {
+        tokio::time::timeout(GEOMETRY_TIMEOUT, async {
+            if !tokio::fs::try_exists(&db_path).await.ok()? {
+                return None;
+            }
+
+            let pool = SqlitePool::connect(db_path.to_string_lossy().as_ref())
+                .await
+                .ok()?;
+            let geometry = WindowGeometry::load(&pool).await.ok().flatten();
+            pool.close().await;
+            geometry
+        })
+        .await
+        .ok()
+        .flatten()
+    });
+    // A blocked file operation would otherwise keep the runtime from shutting down
+    runtime.shutdown_background();
+    geometry
 }
@@ -0,0 +1,193 @@
+use std::{net::IpAddr, time::Duration};
+
+use sqlx::SqlitePool;
+
+const WINDOW_GEOMETRY: &str = "window_geometry";
+const THEME: &str = "theme";
+const DEFAULT_PORT: &str = "default_port";
+const CONNECT_TIMEOUT: &str = "connect_timeout";
+const TRIM_WHITESPACE: &str = "trim_whitespace_on_save";
+const PROBE_ACCOUNTS: &str = "probe_accounts";
+const LOCAL_ADDRESS: &str = "local_address";
+
+pub async fn get(pool: &SqlitePool, key: &str) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!("SELECT value FROM settings WHERE key = ?", key)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|row| row.value))
+}
+
+pub async fn set(pool: &SqlitePool, key: &str, value: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        key,
+        value
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Where the main window was and how large it was when the app was last closed.
+/// The size and position are those of the window when not maximized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowGeometry {
+    pub width: f32,
+    pub height: f32,
+    /// `None` where the platform doesn't report it, e.g. on Wayland
+    pub position: Option<(f32, f32)>,
+    pub maximized: bool,
+}
+
+impl WindowGeometry {
+    pub async fn load(pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        Ok(get(pool, WINDOW_GEOMETRY)
+            .await?
+            .and_then(|value| Self::parse(&value)))
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        // An unknown position is stored as `- -`
+        let (x, y) = match self.position {
+            Some((x, y)) => (x.to_string(), y.to_string()),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        let value = format!(
+            "{} {} {} {} {}",
+            self.width, self.height, x, y, self.maximized
+        );
+        set(pool, WINDOW_GEOMETRY, &value).await
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split_whitespace();
+        let geometry = Self {
+            width: parts.next()?.parse().ok()?,
+            height: parts.next()?.parse().ok()?,
+            position: match (parts.next()?, parts.next()?) {
+                ("-", "-") => None,
+                (x, y) => Some((x.parse().ok()?, y.parse().ok()?)),
+            },
+            maximized: parts.next()?.parse().ok()?,
+        };
+
+        // A window that can't be seen or grabbed is worse than the default
+        (geometry.width >= 100.0 && geometry.height >= 100.0).then_some(geometry)
+    }
+}
+
+/// App wide preferences, edited on the settings screen
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preferences {
+    pub theme: ThemeChoice,
+    /// Port used when connecting to an account
+    pub default_port: u16,
+    /// How long to wait for a server before giving up
+    pub connect_timeout: Duration,
+    /// Strip trailing whitespace from every line and end scripts with exactly
+    /// one newline before uploading them
+    pub trim_whitespace_on_save: bool,
+    /// Check in the background whether each account's server is reachable
+    /// while the account list is shown
+    pub probe_accounts: bool,
+    /// Connect from this local address instead of letting the system choose,
+    /// e.g. to go through a particular VPN interface
+    pub local_address: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemeChoice {
+    Light,
+    Dark,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            theme: ThemeChoice::Light,
+            default_port: 4190,
+            connect_timeout: Duration::from_secs(30),
+            trim_whitespace_on_save: false,
+            probe_accounts: true,
+            local_address: None,
+        }
+    }
+}
+
+impl Preferences {
+    /// Loads the stored preferences, anything missing or unreadable keeps its default
+    pub async fn load(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let defaults = Self::default();
+
+        let theme = match get(pool, THEME).await?.as_deref() {
+            Some("dark") => ThemeChoice::Dark,
+            Some("light") => ThemeChoice::Light,
+            _ => defaults.theme,
+        };
+        let default_port = get(pool, DEFAULT_PORT)
+            .await?
+            .and_then(|value| value.parse().ok())
+            .filter(|port| *port != 0)
+            .unwrap_or(defaults.default_port);
+        let connect_timeout = get(pool, CONNECT_TIMEOUT)
+            .await?
+            .and_then(|value| value.parse().ok())
+            .filter(|seconds| *seconds != 0)
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.connect_timeout);
+        let trim_whitespace_on_save = get(pool, TRIM_WHITESPACE)
+            .await?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(defaults.trim_whitespace_on_save);
+        let probe_accounts = get(pool, PROBE_ACCOUNTS)
+            .await?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(defaults.probe_accounts);
+        // Stored empty for "any interface"
+        let local_address = get(pool, LOCAL_ADDRESS)
+            .await?
+            .and_then(|value| value.parse().ok());
+
+        Ok(Self {
+            theme,
+            default_port,
+            connect_timeout,
+            trim_whitespace_on_save,
+            probe_accounts,
+            local_address,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let theme = match self.theme {
+            ThemeChoice::Light => "light",
+            ThemeChoice::Dark => "dark",
+        };
+        set(pool, THEME, theme).await?;
+        set(pool, DEFAULT_PORT, &self.default_port.to_string()).await?;
+        set(
+            pool,
+            CONNECT_TIMEOUT,
+            &self.connect_timeout.as_secs().to_string(),
+        )
+        .await?;
+        set(
+            pool,
+            TRIM_WHITESPACE,
+            &self.trim_whitespace_on_save.to_string(),
+        )
+        .await?;
+        set(pool, PROBE_ACCOUNTS, &self.probe_accounts.to_string()).await?;
+        set(
+            pool,
+            LOCAL_ADDRESS,
+            &self
+                .local_address
+                .map(|address| address.to_string())
+                .unwrap_or_default(),
+        )
+        .await
+    }
+}
@@ -1,114 +1,431 @@
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use iced::{
-    Subscription, Task,
+    Event, Point, Size, Subscription, Task, Theme, event,
     keyboard::{self, key::Named},
-    widget::{center, focus_next, text},
+    task,
+    widget::{button, center, column, focus_next, row, text},
+    window,
+};
+use sieve_client::{
+    Capabilities, ConnectOptions, SieveClient, SieveError, TlsMode, parse_certificates,
+};
+use sqlx::{
+    Sqlite, SqlitePool,
+    migrate::{MigrateDatabase, MigrateError, Migrator},
 };
-use sieve_client::SieveClient;
-use sqlx::{Sqlite, SqlitePool, migrate::MigrateDatabase};
 use tokio::fs::create_dir_all;
 
-use crate::ui::{account_select::AccountSelect, add_account::AddAccount, manage::Manage};
+use crate::{
+    settings::{Preferences, ThemeChoice, WindowGeometry},
+    ui::{
+        account_select::AccountSelect, add_account::AddAccount, manage::Manage, settings::Settings,
+    },
+};
 
 mod account_select;
 mod add_account;
 mod manage;
+mod settings;
+mod toast;
+
+// Servers drop idle sessions after a few minutes, which would leave the
+// Manage screen holding a dead client
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+// A server that stalls in the middle of a response would otherwise leave the
+// Manage screen waiting forever
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn connect_options() -> ConnectOptions {
+    ConnectOptions {
+        keepalive: Some(KEEPALIVE_INTERVAL),
+        command_timeout: Some(COMMAND_TIMEOUT),
+        ..ConnectOptions::default()
+    }
+}
+
+// How an account's TLS mode is stored in the database
+fn tls_mode_name(tls_mode: TlsMode) -> &'static str {
+    match tls_mode {
+        TlsMode::StartTls => "starttls",
+        TlsMode::Implicit => "implicit",
+        TlsMode::None => "none",
+    }
+}
+
+fn parse_tls_mode(name: &str) -> TlsMode {
+    match name {
+        "implicit" => TlsMode::Implicit,
+        "none" => TlsMode::None,
+        _ => TlsMode::StartTls,
+    }
+}
+
+// How an account's last known capabilities are cached in the database
+fn capabilities_json(client: &SieveClient) -> Option<String> {
+    serde_json::to_string(&*client.capabilities()).ok()
+}
+
+// A cache written by an older version that no longer parses is simply ignored
+fn parse_capabilities(json: Option<&str>) -> Option<Capabilities> {
+    json.and_then(|json| serde_json::from_str(json).ok())
+}
+
+/// Where and how to connect for an account, as stored with it
+#[derive(Debug, Clone)]
+pub struct ConnectionSpec {
+    pub host: String,
+    /// `None` uses the default port from the preferences
+    pub port: Option<u16>,
+    pub tls_mode: TlsMode,
+    pub accept_invalid_certificates: bool,
+    /// PEM encoded CA certificate trusted for this account only
+    pub ca_certificate: Option<String>,
+}
+
+impl ConnectionSpec {
+    fn options(&self, preferences: &Preferences) -> Result<ConnectOptions, SieveError> {
+        let extra_root_certificates = match &self.ca_certificate {
+            Some(pem) => parse_certificates(pem).map_err(|err| {
+                SieveError::Tls(format!("The CA certificate can't be read: {}", err))
+            })?,
+            None => Vec::new(),
+        };
+
+        Ok(ConnectOptions {
+            tls_mode: self.tls_mode,
+            // Without TLS there is no certificate to accept
+            insecure_skip_verify: self.accept_invalid_certificates
+                && self.tls_mode != TlsMode::None,
+            extra_root_certificates,
+            // Choosing no TLS for an account is the user's explicit consent
+            require_tls: self.tls_mode != TlsMode::None,
+            local_address: preferences.local_address,
+            ..connect_options()
+        })
+    }
+}
+
+// Opens a session within the timeout set in the preferences
+async fn connect(
+    preferences: &Preferences,
+    spec: &ConnectionSpec,
+    username: &str,
+    password: &str,
+) -> Result<SieveClient, SieveError> {
+    let connect = SieveClient::connect_with_options(
+        spec.host.clone(),
+        spec.port.unwrap_or(preferences.default_port),
+        username,
+        password,
+        spec.options(preferences)?,
+    );
+
+    match tokio::time::timeout(preferences.connect_timeout, connect).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(SieveError::Network(format!(
+            "No answer within {} seconds",
+            preferences.connect_timeout.as_secs()
+        ))),
+    }
+}
+
+// Connects to an account's server without logging in, within the same timeout
+async fn probe(preferences: &Preferences, spec: &ConnectionSpec) -> Result<(), SieveError> {
+    let options = spec.options(preferences)?;
+    let probe = SieveClient::probe(
+        &spec.host,
+        spec.port.unwrap_or(preferences.default_port),
+        &options,
+    );
+
+    match tokio::time::timeout(preferences.connect_timeout, probe).await {
+        Ok(result) => result.map(|_| ()).map_err(SieveError::from),
+        Err(_) => Err(SieveError::Network(format!(
+            "No answer within {} seconds",
+            preferences.connect_timeout.as_secs()
+        ))),
+    }
+}
+
+const DATABASE_FILE: &str = "sieve_accounts.sqlite";
+
+// A data directory on a stalled network mount would otherwise keep the app on
+// "Loading..." forever
+const INIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+pub fn data_dir() -> Option<PathBuf> {
+    directories_next::ProjectDirs::from("de", "it-rahn", "sieve-gui")
+        .map(|dirs| dirs.data_dir().to_path_buf())
+}
+
+pub fn database_path() -> Option<PathBuf> {
+    data_dir().map(|data_dir| data_dir.join(DATABASE_FILE))
+}
+
+/// Why the app couldn't start, shown instead of the UI
+#[derive(Debug, Clone)]
+pub struct InitError {
+    message: String,
+    // Set when the database file can't be used as it is, e.g. because SQLite
+    // can't read it. It can then be moved aside to start over.
+    unusable_database: Option<PathBuf>,
+}
+
+impl From<String> for InitError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            unusable_database: None,
+        }
+    }
+}
+
+// The primary SQLite result code of a failed query, see
+// https://www.sqlite.org/rescode.html
+fn sqlite_code(err: &sqlx::Error) -> Option<i32> {
+    err.as_database_error()
+        .and_then(|err| err.code())
+        .and_then(|code| code.parse::<i32>().ok())
+        .map(|code| code & 0xff)
+}
+
+// SQLITE_BUSY, SQLITE_LOCKED
+fn is_locked(err: &sqlx::Error) -> bool {
+    matches!(sqlite_code(err), Some(5 | 6))
+}
+
+// Tells a locked or damaged database apart from other failures
+fn database_error(context: &str, err: &sqlx::Error, path: &Path) -> InitError {
+    if is_locked(err) {
+        return format!(
+            "{}: the database is locked, is Sieve GUI already running? ({})",
+            context, err
+        )
+        .into();
+    }
+
+    match sqlite_code(err) {
+        // SQLITE_CORRUPT, SQLITE_NOTADB
+        Some(11 | 26) => InitError {
+            message: format!(
+                "{}: {} is damaged or not a database ({})",
+                context,
+                path.display(),
+                err
+            ),
+            unusable_database: Some(path.to_path_buf()),
+        },
+        _ => format!("{}: {}", context, err).into(),
+    }
+}
+
+// The newest migration applied to the database, `None` for a new database
+async fn schema_version(pool: &SqlitePool) -> Option<i64> {
+    sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(pool)
+        .await
+        .ok()
+        .flatten()
+}
+
+// Explains why the schema couldn't be brought to `expected`. Apart from a
+// locked database, all of these leave a database this version can't use.
+fn migration_error(
+    err: MigrateError,
+    current: Option<i64>,
+    expected: i64,
+    path: &Path,
+) -> InitError {
+    let current = current.map_or("none".to_string(), |version| version.to_string());
+    let message = match &err {
+        MigrateError::Execute(inner) | MigrateError::ExecuteMigration(inner, _)
+            if is_locked(inner) =>
+        {
+            return database_error("Failed to upgrade the database", inner, path);
+        }
+        MigrateError::VersionMissing(_) => format!(
+            "The database was last used by a newer version of Sieve GUI (schema {}, this version \
+             knows up to {}). Update Sieve GUI or start over with a new database.",
+            current, expected
+        ),
+        MigrateError::VersionMismatch(version) => format!(
+            "Schema {} of the database doesn't match the one of this version of Sieve GUI \
+             (database at schema {}, this version knows up to {})",
+            version, current, expected
+        ),
+        MigrateError::Dirty(version) => format!(
+            "An earlier upgrade of the database to schema {} didn't finish (database at schema \
+             {}, this version knows up to {})",
+            version, current, expected
+        ),
+        _ => format!(
+            "Failed to upgrade the database from schema {} to {}: {}",
+            current, expected, err
+        ),
+    };
+
+    InitError {
+        message,
+        unusable_database: Some(path.to_path_buf()),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum MessageWrapper {
     Ui(Message),
-    Error(String),
-    Pool(Arc<SqlitePool>),
+    Error(InitError),
+    Pool(Arc<SqlitePool>, Preferences),
+    Retry,
+    CancelInit,
+    MoveDatabaseAside(PathBuf),
     Tab,
+    WindowChanged(window::Id, Option<Size>, Option<Point>),
+    // The geometry of the window while it isn't maximized
+    NormalWindowChanged(Option<Size>, Option<Point>),
+    CloseRequested(window::Id),
+    SaveGeometry(window::Id, bool),
+}
+
+// Creates the database on first start and brings its schema up to date
+async fn init_database() -> Result<(SqlitePool, Preferences), InitError> {
+    let data_dir = data_dir().ok_or_else(|| "Failed to get data directory".to_string())?;
+    create_dir_all(&data_dir)
+        .await
+        .map_err(|err| format!("Failed to create data directory: {}", err))?;
+
+    let db_path = data_dir.join(DATABASE_FILE);
+    let exists = tokio::fs::try_exists(&db_path)
+        .await
+        .map_err(|err| format!("Failed to check if database exists: {}", err))?;
+    if !exists {
+        Sqlite::create_database(db_path.to_string_lossy().as_ref())
+            .await
+            .map_err(|err| database_error("Failed to create database", &err, &db_path))?;
+    }
+
+    let pool = SqlitePool::connect(db_path.as_os_str().to_string_lossy().as_ref())
+        .await
+        .map_err(|err| database_error("Failed to connect to database", &err, &db_path))?;
+
+    let current = schema_version(&pool).await;
+    let expected = MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default();
+    tracing::info!(
+        "Database schema {}, expected {}",
+        current.map_or("none".to_string(), |version| version.to_string()),
+        expected
+    );
+    if let Err(err) = MIGRATOR.run(&pool).await {
+        return Err(migration_error(err, current, expected, &db_path));
+    }
+
+    let preferences = Preferences::load(&pool)
+        .await
+        .map_err(|err| database_error("Failed to load settings", &err, &db_path))?;
+    Ok((pool, preferences))
+}
+
+// Renames a database this version can't use so the next start creates a new
+// one, keeping the old file as a backup
+async fn move_database_aside(path: &Path) -> std::io::Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".backup-{}", timestamp));
+    tokio::fs::rename(path, backup).await
 }
 
 enum WrapperScreen {
     Loading,
-    Error(String),
+    Error(InitError),
     Ui(UI),
 }
 
 pub struct UIWrapper {
     screen: WrapperScreen,
+    // Aborts the database setup when dropped, set while it runs
+    init: Option<task::Handle>,
+    // Tracked from window events so it can be saved on close. Both are kept
+    // from before the window was maximized, so it can be restored to them.
+    window_size: Option<Size>,
+    window_position: Option<Point>,
 }
 
 impl UIWrapper {
-    pub fn start() -> (Self, Task<MessageWrapper>) {
-        (
-            Self {
-                screen: WrapperScreen::Loading,
-            },
-            Task::future(async {
-                if let Some(dirs) =
-                    directories_next::ProjectDirs::from("de", "it-rahn", "sieve-gui")
-                {
-                    let data_dir = dirs.data_dir();
-
-                    if let Err(err) = create_dir_all(data_dir).await {
-                        return MessageWrapper::Error(format!(
-                            "Failed to create data directory: {}",
-                            err
-                        ));
-                    }
-
-                    let mut db_path = data_dir.to_path_buf();
-                    db_path.push("sieve_accounts.sqlite");
-
-                    match tokio::fs::try_exists(&db_path).await {
-                        Err(err) => {
-                            return MessageWrapper::Error(format!(
-                                "Failed to check if database exists: {}",
-                                err
-                            ));
-                        }
-                        Ok(false) => {
-                            match Sqlite::create_database(db_path.to_string_lossy().as_ref()).await
-                            {
-                                Err(err) => {
-                                    return MessageWrapper::Error(format!(
-                                        "Failed to create database directory: {}",
-                                        err
-                                    ));
-                                }
-                                Ok(_) => {}
-                            }
-                        }
-                        Ok(true) => {}
-                    }
+    pub fn start(geometry: Option<WindowGeometry>) -> (Self, Task<MessageWrapper>) {
+        let mut wrapper = Self {
+            screen: WrapperScreen::Loading,
+            init: None,
+            window_size: geometry.map(|geometry| Size::new(geometry.width, geometry.height)),
+            window_position: geometry
+                .and_then(|geometry| geometry.position)
+                .map(|(x, y)| Point::new(x, y)),
+        };
+        let task = wrapper.init();
+        (wrapper, task)
+    }
 
-                    match SqlitePool::connect(db_path.as_os_str().to_string_lossy().as_ref()).await
-                    {
-                        Err(err) => {
-                            MessageWrapper::Error(format!("Failed to connect to database: {}", err))
-                        }
-                        Ok(pool) => {
-                            if let Err(err) = sqlx::migrate!("./migrations").run(&pool).await {
-                                return MessageWrapper::Error(format!(
-                                    "Failed to run migrations: {}",
-                                    err
-                                ));
-                            }
-
-                            MessageWrapper::Pool(Arc::new(pool))
-                        }
-                    }
-                } else {
-                    MessageWrapper::Error("Failed to get data directory".to_string())
-                }
-            }),
-        )
+    fn init(&mut self) -> Task<MessageWrapper> {
+        self.screen = WrapperScreen::Loading;
+        let (task, handle) = Task::future(async {
+            match tokio::time::timeout(INIT_TIMEOUT, init_database()).await {
+                Ok(Ok((pool, preferences))) => MessageWrapper::Pool(Arc::new(pool), preferences),
+                Ok(Err(err)) => MessageWrapper::Error(err),
+                Err(_) => MessageWrapper::Error(
+                    format!(
+                        "Opening the database took longer than {} seconds, \
+                         is the data directory on a network drive that isn't reachable?",
+                        INIT_TIMEOUT.as_secs()
+                    )
+                    .into(),
+                ),
+            }
+        })
+        .abortable();
+        self.init = Some(handle.abort_on_drop());
+        task
     }
 
     pub fn update(&mut self, message: MessageWrapper) -> Task<MessageWrapper> {
         match message {
             MessageWrapper::Error(error) => {
+                self.init = None;
                 self.screen = WrapperScreen::Error(error);
                 Task::none()
             }
-            MessageWrapper::Pool(pool) => {
+            MessageWrapper::Retry => self.init(),
+            MessageWrapper::CancelInit => {
+                if let Some(handle) = self.init.take() {
+                    handle.abort();
+                }
+                self.screen =
+                    WrapperScreen::Error("Opening the database was cancelled".to_string().into());
+                Task::none()
+            }
+            MessageWrapper::MoveDatabaseAside(path) => Task::future(async move {
+                match move_database_aside(&path).await {
+                    Ok(()) => MessageWrapper::Retry,
+                    Err(err) => MessageWrapper::Error(
+                        format!("Failed to move {} aside: {}", path.display(), err).into(),
+                    ),
+                }
+            }),
+            MessageWrapper::Pool(pool, preferences) => {
+                self.init = None;
                 if let Some(pool) = Arc::into_inner(pool) {
-                    let (ui, task) = UI::new(pool);
+                    let (ui, task) = UI::new(pool, preferences);
                     self.screen = WrapperScreen::Ui(ui);
                     task.map(MessageWrapper::Ui)
                 } else {
@@ -123,26 +440,144 @@ impl UIWrapper {
                 }
             }
             MessageWrapper::Tab => focus_next(),
+            MessageWrapper::WindowChanged(id, size, position) => {
+                window::is_maximized(id).then(move |maximized| {
+                    if maximized {
+                        Task::none()
+                    } else {
+                        Task::done(MessageWrapper::NormalWindowChanged(size, position))
+                    }
+                })
+            }
+            MessageWrapper::NormalWindowChanged(size, position) => {
+                if size.is_some() {
+                    self.window_size = size;
+                }
+                if position.is_some() {
+                    self.window_position = position;
+                }
+                Task::none()
+            }
+            MessageWrapper::CloseRequested(id) => window::is_maximized(id)
+                .map(move |maximized| MessageWrapper::SaveGeometry(id, maximized)),
+            MessageWrapper::SaveGeometry(id, maximized) => {
+                let pool = match &self.screen {
+                    WrapperScreen::Ui(ui) => Some(ui.pool.clone()),
+                    _ => None,
+                };
+
+                // Wayland never reports the position, the size is still worth keeping
+                match (pool, self.window_size) {
+                    (Some(pool), Some(size)) => {
+                        let geometry = WindowGeometry {
+                            width: size.width,
+                            height: size.height,
+                            position: self
+                                .window_position
+                                .map(|position| (position.x, position.y)),
+                            maximized,
+                        };
+                        Task::future(async move {
+                            // Closing must not be blocked by a failed write
+                            let _ = geometry.save(&pool).await;
+                        })
+                        .then(move |_| window::close(id))
+                    }
+                    _ => window::close(id),
+                }
+            }
         }
     }
 
     pub fn view(&self) -> iced::Element<MessageWrapper> {
         match &self.screen {
-            WrapperScreen::Loading => center(text("Loading...")).into(),
-            WrapperScreen::Error(error) => center(text(error)).into(),
+            WrapperScreen::Loading => center(
+                column![
+                    text("Loading..."),
+                    button(text("Cancel")).on_press(MessageWrapper::CancelInit)
+                ]
+                .spacing(10),
+            )
+            .into(),
+            WrapperScreen::Error(error) => {
+                let mut actions =
+                    row![button(text("Retry")).on_press(MessageWrapper::Retry)].spacing(10);
+                if let Some(path) = &error.unusable_database {
+                    actions = actions.push(
+                        button(text("Back up the database and start over"))
+                            .style(button::danger)
+                            .on_press(MessageWrapper::MoveDatabaseAside(path.clone())),
+                    );
+                }
+                center(column![text(&error.message), actions].spacing(10)).into()
+            }
             WrapperScreen::Ui(ui) => ui.view().map(MessageWrapper::Ui),
         }
     }
 
+    pub fn theme(&self) -> Theme {
+        let theme = match &self.screen {
+            WrapperScreen::Ui(ui) => ui.preferences.theme,
+            _ => Preferences::default().theme,
+        };
+
+        match theme {
+            ThemeChoice::Light => Theme::Light,
+            ThemeChoice::Dark => Theme::Dark,
+        }
+    }
+
     pub fn subscription(&self) -> Subscription<MessageWrapper> {
-        keyboard::on_key_press(|key, _modifiers| match key {
+        // Manage shortcuts are dropped by `UI::update` unless that screen is open
+        let keys = keyboard::on_key_press(|key, modifiers| match key {
             keyboard::Key::Named(named) => match named {
                 Named::Tab => Some(MessageWrapper::Tab),
+                Named::F5 => Some(MessageWrapper::Ui(Message::Manage(
+                    manage::Message::RefreshScripts,
+                ))),
                 _ => None,
             },
+            keyboard::Key::Character(character) if modifiers.command() => {
+                match character.as_str() {
+                    "s" => Some(MessageWrapper::Ui(Message::Manage(manage::Message::Save))),
+                    "r" => Some(MessageWrapper::Ui(Message::Manage(
+                        manage::Message::RefreshScripts,
+                    ))),
+                    "+" | "=" => Some(MessageWrapper::Ui(Message::Manage(
+                        manage::Message::FontSize(1),
+                    ))),
+                    "-" => Some(MessageWrapper::Ui(Message::Manage(
+                        manage::Message::FontSize(-1),
+                    ))),
+                    _ => None,
+                }
+            }
             keyboard::Key::Character(_) => None,
             keyboard::Key::Unidentified => None,
-        })
+        });
+
+        let window = event::listen_with(|event, _status, id| match event {
+            Event::Window(window::Event::Opened { position, size }) => {
+                Some(MessageWrapper::WindowChanged(id, Some(size), position))
+            }
+            Event::Window(window::Event::Moved(position)) => {
+                Some(MessageWrapper::WindowChanged(id, None, Some(position)))
+            }
+            Event::Window(window::Event::Resized(size)) => {
+                Some(MessageWrapper::WindowChanged(id, Some(size), None))
+            }
+            Event::Window(window::Event::CloseRequested) => {
+                Some(MessageWrapper::CloseRequested(id))
+            }
+            _ => None,
+        });
+
+        let screen = match &self.screen {
+            WrapperScreen::Ui(ui) => ui.subscription().map(MessageWrapper::Ui),
+            _ => Subscription::none(),
+        };
+
+        Subscription::batch([keys, window, screen])
     }
 }
 
@@ -151,25 +586,29 @@ pub enum Message {
     AccountSelect(account_select::Message),
     AddAccount(add_account::Message),
     Manage(manage::Message),
+    Settings(settings::Message),
 }
 
 pub enum Screen {
     AccountSelect(AccountSelect),
     AddAccount(AddAccount),
     Manage(Manage),
+    Settings(Settings),
 }
 
 struct UI {
     pool: SqlitePool,
+    preferences: Preferences,
     screen: Screen,
 }
 
 impl UI {
-    fn new(pool: SqlitePool) -> (Self, Task<Message>) {
-        let (select, task) = AccountSelect::new(pool.clone());
+    fn new(pool: SqlitePool, preferences: Preferences) -> (Self, Task<Message>) {
+        let (select, task) = AccountSelect::new(pool.clone(), preferences.clone());
 
         let ui = Self {
             pool,
+            preferences,
             screen: Screen::AccountSelect(select),
         };
         (ui, task.map(Message::AccountSelect))
@@ -182,12 +621,19 @@ impl UI {
                     match select.update(message) {
                         account_select::Action::None => Task::none(),
                         account_select::Action::Run(task) => task.map(Message::AccountSelect),
-                        account_select::Action::Selected(client) => self.to_manage(client),
+                        account_select::Action::Selected(id, client) => self.to_manage(id, client),
                         account_select::Action::AddAccount => {
-                            let (add_account, task) = AddAccount::new(self.pool.clone());
+                            let (add_account, task) =
+                                AddAccount::new(self.pool.clone(), self.preferences.clone());
                             self.screen = Screen::AddAccount(add_account);
                             task.map(Message::AddAccount)
                         }
+                        account_select::Action::Settings => {
+                            let (settings, task) =
+                                Settings::new(self.pool.clone(), &self.preferences);
+                            self.screen = Screen::Settings(settings);
+                            task.map(Message::Settings)
+                        }
                     }
                 } else {
                     Task::none()
@@ -198,7 +644,7 @@ impl UI {
                     match add_account.update(message) {
                         add_account::Action::None => Task::none(),
                         add_account::Action::Back => self.to_account_select(),
-                        add_account::Action::Added(client) => self.to_manage(client),
+                        add_account::Action::Added(id, client) => self.to_manage(id, client),
                         add_account::Action::Run(task) => task.map(Message::AddAccount),
                     }
                 } else {
@@ -209,33 +655,69 @@ impl UI {
                 if let Screen::Manage(manage) = &mut self.screen {
                     match manage.update(message) {
                         manage::Action::None => Task::none(),
-                        manage::Action::Back => self.to_account_select(),
+                        manage::Action::Back => {
+                            // The account list doesn't wait for the server
+                            let client = manage.client();
+                            let logout = Task::future(async move {
+                                let _ = client.logout().await;
+                            })
+                            .then(|_| Task::none());
+                            Task::batch([logout, self.to_account_select()])
+                        }
                         manage::Action::Run(task) => task.map(Message::Manage),
                     }
                 } else {
                     Task::none()
                 }
             }
+            Message::Settings(message) => {
+                if let Screen::Settings(settings) = &mut self.screen {
+                    match settings.update(message) {
+                        settings::Action::None => Task::none(),
+                        settings::Action::Run(task) => task.map(Message::Settings),
+                        settings::Action::Saved(preferences) => {
+                            self.preferences = preferences;
+                            self.to_account_select()
+                        }
+                        settings::Action::Back => self.to_account_select(),
+                    }
+                } else {
+                    Task::none()
+                }
+            }
         }
     }
 
     fn to_account_select(&mut self) -> Task<Message> {
-        let (select, task) = AccountSelect::new(self.pool.clone());
+        let (select, task) = AccountSelect::new(self.pool.clone(), self.preferences.clone());
         self.screen = Screen::AccountSelect(select);
         task.map(Message::AccountSelect)
     }
 
-    fn to_manage(&mut self, client: Arc<SieveClient>) -> Task<Message> {
-        let (manage, task) = Manage::new(client);
+    fn to_manage(&mut self, account_id: i64, client: Arc<SieveClient>) -> Task<Message> {
+        let (manage, task) = Manage::new(
+            account_id,
+            client,
+            self.pool.clone(),
+            self.preferences.trim_whitespace_on_save,
+        );
         self.screen = Screen::Manage(manage);
         task.map(Message::Manage)
     }
 
+    fn subscription(&self) -> Subscription<Message> {
+        match &self.screen {
+            Screen::Manage(manage) => manage.subscription().map(Message::Manage),
+            _ => Subscription::none(),
+        }
+    }
+
     fn view(&self) -> iced::Element<Message> {
         match &self.screen {
             Screen::AccountSelect(select) => select.view().map(Message::AccountSelect),
             Screen::AddAccount(add_account) => add_account.view().map(Message::AddAccount),
             Screen::Manage(manage) => manage.view().map(Message::Manage),
+            Screen::Settings(settings) => settings.view().map(Message::Settings),
         }
     }
 }
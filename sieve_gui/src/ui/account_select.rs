@@ -1,62 +1,114 @@
-use std::{collections::HashMap, fmt::Debug, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+};
 
 use iced::{
     Element, Length, Task,
-    widget::{button, center, column, horizontal_space, row, scrollable, text},
+    widget::{button, center, checkbox, column, horizontal_space, row, scrollable, text},
 };
-use sieve_client::SieveClient;
+use sieve_client::{Capabilities, ExposeSecret, SecretString, SieveClient, SieveError};
 use sqlx::SqlitePool;
 
+use super::ConnectionSpec;
+use crate::settings::Preferences;
+
 #[derive(Debug, Clone)]
 pub enum Message {
     Error(String),
+    OpenFailed(i64, SieveError),
+    DismissError,
     Accounts(Vec<Account>),
     Select(i64),
     Delete(i64),
     ConfirmDelete,
+    ToggleSelected(i64, bool),
+    DeleteSelected,
+    ConfirmDeleteSelected,
+    DeletedSelected,
     Back,
     AddAccount,
-    Opened(Arc<SieveClient>),
+    OpenSettings,
+    Opened(i64, Arc<SieveClient>),
+    Probed(i64, bool),
 }
 
 pub enum Action {
     None,
-    Selected(Arc<SieveClient>),
+    Selected(i64, Arc<SieveClient>),
     AddAccount,
+    Settings,
     Run(Task<Message>),
 }
 
 pub struct AccountSelect {
     pool: SqlitePool,
+    preferences: Preferences,
     error: Option<String>,
+    // The account whose connection failed, so the error can offer a retry
+    failed_open: Option<i64>,
     accounts: HashMap<i64, Account>,
     confirm_delete: Option<i64>,
+    // Accounts ticked for "Delete selected"
+    selected: HashSet<i64>,
+    confirm_delete_selected: bool,
+    // Whether each account's server answered, missing while the probe runs
+    reachable: HashMap<i64, bool>,
 }
 
 #[derive(Clone)]
 pub struct Account {
     id: i64,
-    server: String,
+    spec: ConnectionSpec,
     username: String,
-    password: String,
+    password: SecretString,
+    // Capabilities from the last successful connect, if any
+    capabilities: Option<Capabilities>,
 }
 
 impl Debug for Account {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Account")
-            .field("server", &self.server)
+            .field("spec", &self.spec)
             .field("username", &self.username)
             .finish()
     }
 }
 
+// Summary of the Sieve extensions seen the last time the account was opened
+fn extensions(account: &Account) -> String {
+    let extensions = match &account.capabilities {
+        Some(capabilities) if capabilities.sieve.is_empty() => "No extensions".to_string(),
+        Some(capabilities) => capabilities.sieve.join(", "),
+        None => return format!("{} - not connected yet", account.spec.host),
+    };
+
+    // Only worth mentioning when the scripts belong to someone else
+    match account
+        .capabilities
+        .as_ref()
+        .and_then(|capabilities| capabilities.owner.as_ref())
+    {
+        Some(owner) if *owner != account.username => {
+            format!("{} - scripts of {}", extensions, owner)
+        }
+        _ => extensions,
+    }
+}
+
 impl AccountSelect {
-    pub fn new(pool: SqlitePool) -> (Self, Task<Message>) {
+    pub fn new(pool: SqlitePool, preferences: Preferences) -> (Self, Task<Message>) {
         let self_ = Self {
             pool,
+            preferences,
             error: None,
+            failed_open: None,
             accounts: HashMap::new(),
             confirm_delete: None,
+            selected: HashSet::new(),
+            confirm_delete_selected: false,
+            reachable: HashMap::new(),
         };
         let task = self_.update_profiles();
         (self_, task)
@@ -66,6 +118,18 @@ impl AccountSelect {
         match message {
             Message::Error(err) => {
                 self.error = Some(err);
+                self.failed_open = None;
+                Action::None
+            }
+            Message::OpenFailed(id, err) => {
+                // Retrying won't help while the stored credentials are wrong
+                self.failed_open = (!matches!(err, SieveError::Authentication(_))).then_some(id);
+                self.error = Some(err.to_string());
+                Action::None
+            }
+            Message::DismissError => {
+                self.error = None;
+                self.failed_open = None;
                 Action::None
             }
             Message::Accounts(accounts) => {
@@ -77,9 +141,16 @@ impl AccountSelect {
                         .into_iter()
                         .map(|account| (account.id, account))
                         .collect();
-                    Action::None
+                    self.selected.retain(|id| self.accounts.contains_key(id));
+                    self.reachable
+                        .retain(|id, _| self.accounts.contains_key(id));
+                    Action::Run(self.probe_accounts())
                 }
             }
+            Message::Probed(id, reachable) => {
+                self.reachable.insert(id, reachable);
+                Action::None
+            }
             Message::Delete(id) => {
                 self.confirm_delete = Some(id);
                 Action::None
@@ -91,52 +162,82 @@ impl AccountSelect {
                     Action::None
                 }
             }
+            Message::ToggleSelected(id, selected) => {
+                if selected {
+                    self.selected.insert(id);
+                } else {
+                    self.selected.remove(&id);
+                }
+                Action::None
+            }
+            Message::DeleteSelected => {
+                self.confirm_delete_selected = !self.selected.is_empty();
+                Action::None
+            }
+            Message::ConfirmDeleteSelected => {
+                self.confirm_delete_selected = false;
+                let ids: Vec<i64> = self.selected.iter().copied().collect();
+                Action::Run(self.delete_accounts(ids))
+            }
+            Message::DeletedSelected => {
+                self.selected.clear();
+                Action::None
+            }
             Message::Back => {
-                self.error = None;
                 self.confirm_delete = None;
+                self.confirm_delete_selected = false;
                 Action::None
             }
-            Message::Select(id) => Action::Run(self.open_account(id)),
-            Message::Opened(client) => Action::Selected(client),
+            Message::Select(id) => {
+                self.error = None;
+                self.failed_open = None;
+                Action::Run(self.open_account(id))
+            }
+            Message::Opened(id, client) => Action::Selected(id, client),
             Message::AddAccount => Action::AddAccount,
+            Message::OpenSettings => Action::Settings,
         }
     }
 
     fn update_profiles(&self) -> Task<Message> {
         let pool = self.pool.clone();
         Task::future(async move {
-            match sqlx::query_as!(
-                Account,
-                "SELECT id, server, username, password FROM accounts"
+            match sqlx::query!(
+                "SELECT id, server, port, username, password, tls_mode, accept_invalid_certificates, ca_certificate, capabilities FROM accounts"
             )
             .fetch_all(&pool)
             .await
             {
-                Ok(accounts) => Message::Accounts(accounts),
+                Ok(rows) => Message::Accounts(
+                    rows.into_iter()
+                        .map(|row| Account {
+                            id: row.id,
+                            spec: ConnectionSpec {
+                                host: row.server,
+                                // Anything out of range falls back to the default port
+                                port: row.port.and_then(|port| u16::try_from(port).ok()),
+                                tls_mode: super::parse_tls_mode(&row.tls_mode),
+                                accept_invalid_certificates: row.accept_invalid_certificates,
+                                ca_certificate: row.ca_certificate,
+                            },
+                            username: row.username,
+                            password: SecretString::from(row.password),
+                            capabilities: super::parse_capabilities(row.capabilities.as_deref()),
+                        })
+                        .collect(),
+                ),
                 Err(err) => Message::Error(err.to_string()),
             }
         })
     }
 
     pub fn view(&self) -> Element<Message> {
-        if let Some(err) = &self.error {
-            return center(
-                column![
-                    text("Error"),
-                    text(format!("Error: {}", err)),
-                    button(text("OK")).on_press(Message::Back)
-                ]
-                .spacing(10),
-            )
-            .into();
-        }
-
         if let Some(id) = &self.confirm_delete {
             if let Some(account) = self.accounts.get(&id) {
                 return center(
                     column![
                         text("Are you sure you want to delete this account?"),
-                        text(format!("Server: {}", account.server)),
+                        text(format!("Server: {}", account.spec.host)),
                         text(format!("Username: {}", account.username)),
                         row![
                             button(text("Yes")).on_press(Message::ConfirmDelete),
@@ -150,29 +251,112 @@ impl AccountSelect {
             }
         }
 
-        column![
-            scrollable(
-                column(self.accounts.iter().map(|(_, account)| {
+        if self.confirm_delete_selected {
+            let mut accounts = column![text(format!(
+                "Are you sure you want to delete these {} accounts?",
+                self.selected.len()
+            ))]
+            .spacing(10);
+            for account in self.selected.iter().filter_map(|id| self.accounts.get(id)) {
+                accounts = accounts.push(text(format!(
+                    "{} on {}",
+                    account.username, account.spec.host
+                )));
+            }
+
+            return center(
+                accounts.push(
                     row![
-                        button(text(&account.username))
+                        button(text("Yes")).on_press(Message::ConfirmDeleteSelected),
+                        button(text("No")).on_press(Message::Back)
+                    ]
+                    .spacing(10),
+                ),
+            )
+            .into();
+        }
+
+        let mut content = column![].spacing(20).padding(50);
+        if let Some(err) = &self.error {
+            content = content.push(super::toast::toast(
+                err,
+                Message::DismissError,
+                self.failed_open.map(Message::Select),
+            ));
+        }
+
+        content
+            .push(
+                scrollable(
+                    column(self.accounts.iter().map(|(_, account)| {
+                        row![
+                            checkbox("", self.selected.contains(&account.id)).on_toggle(
+                                move |selected| Message::ToggleSelected(account.id, selected)
+                            ),
+                            self.view_reachable(account.id),
+                            button(column![
+                                text(&account.username),
+                                text(extensions(account)).size(12),
+                            ])
                             .width(Length::Fill)
                             .on_press(Message::Select(account.id)),
-                        button(text("Delete")).on_press(Message::Delete(account.id))
-                    ]
-                    .spacing(5)
-                    .into()
-                }))
+                            button(text("Delete")).on_press(Message::Delete(account.id))
+                        ]
+                        .spacing(5)
+                        .into()
+                    }))
+                    .spacing(10),
+                )
+                .height(Length::Fill),
+            )
+            .push(
+                row![
+                    button(text(format!("Delete selected ({})", self.selected.len())))
+                        .on_press_maybe(
+                            (!self.selected.is_empty()).then_some(Message::DeleteSelected)
+                        ),
+                    horizontal_space(),
+                    button(text("Settings")).on_press(Message::OpenSettings),
+                    button(text("Add")).on_press(Message::AddAccount)
+                ]
                 .spacing(10),
             )
-            .height(Length::Fill),
-            row![
-                horizontal_space(),
-                button(text("Add")).on_press(Message::AddAccount)
-            ]
-        ]
-        .spacing(20)
-        .padding(50)
-        .into()
+            .into()
+    }
+
+    // A dot in front of each account, empty until its probe is done
+    fn view_reachable(&self, id: i64) -> Element<Message> {
+        if !self.preferences.probe_accounts {
+            return horizontal_space().width(0).into();
+        }
+
+        match self.reachable.get(&id) {
+            Some(true) => text("●").style(text::success).into(),
+            Some(false) => text("●").style(text::danger).into(),
+            None => text("○").into(),
+        }
+    }
+
+    // Probes every account not probed yet, all at once so a server that doesn't
+    // answer only delays its own dot. Rendering never waits for them.
+    fn probe_accounts(&self) -> Task<Message> {
+        if !self.preferences.probe_accounts {
+            return Task::none();
+        }
+
+        Task::batch(
+            self.accounts
+                .values()
+                .filter(|account| !self.reachable.contains_key(&account.id))
+                .map(|account| {
+                    let id = account.id;
+                    let spec = account.spec.clone();
+                    let preferences = self.preferences.clone();
+                    Task::future(async move {
+                        Message::Probed(id, super::probe(&preferences, &spec).await.is_ok())
+                    })
+                }),
+        )
     }
 
     fn delete_account(&self, id: i64) -> Task<Message> {
@@ -189,20 +373,50 @@ impl AccountSelect {
         .chain(self.update_profiles())
     }
 
+    fn delete_accounts(&self, ids: Vec<i64>) -> Task<Message> {
+        let pool = self.pool.clone();
+        Task::future(async move {
+            for id in ids {
+                if let Err(err) = sqlx::query!("DELETE FROM accounts WHERE id = $1", id)
+                    .execute(&pool)
+                    .await
+                {
+                    return Message::Error(err.to_string());
+                }
+            }
+            Message::DeletedSelected
+        })
+        .chain(self.update_profiles())
+    }
+
     fn open_account(&self, id: i64) -> Task<Message> {
         if let Some(account) = self.accounts.get(&id).cloned() {
             let account = account.clone();
+            let pool = self.pool.clone();
+            let preferences = self.preferences.clone();
             Task::future(async move {
-                match SieveClient::connect(
-                    account.server,
-                    4190,
+                match super::connect(
+                    &preferences,
+                    &account.spec,
                     &account.username,
-                    &account.password,
+                    account.password.expose_secret(),
                 )
                 .await
                 {
-                    Ok(client) => Message::Opened(Arc::new(client)),
-                    Err(err) => Message::Error(err.to_string()),
+                    Ok(client) => {
+                        // The cache is only informational, failing to refresh
+                        // it mustn't keep the account from opening
+                        let capabilities = super::capabilities_json(&client);
+                        let _ = sqlx::query!(
+                            "UPDATE accounts SET capabilities = ? WHERE id = ?",
+                            capabilities,
+                            id
+                        )
+                        .execute(&pool)
+                        .await;
+                        Message::Opened(id, Arc::new(client))
+                    }
+                    Err(err) => Message::OpenFailed(id, err),
                 }
             })
         } else {
@@ -1,27 +1,39 @@
 use std::sync::Arc;
 
 use iced::{
-    Element, Task,
-    widget::{button, center, column, horizontal_space, row, text, text_input, vertical_space},
+    Alignment, Element, Task, task,
+    widget::{
+        button, center, checkbox, column, horizontal_space, radio, row, text, text_input,
+        vertical_space,
+    },
 };
-use sieve_client::SieveClient;
+use sieve_client::{ExposeSecret, SecretString, SieveClient, TlsMode};
 use sqlx::SqlitePool;
 
+use super::ConnectionSpec;
+use crate::settings::Preferences;
+
 #[derive(Debug, Clone)]
 pub enum Message {
     Server(String),
+    Port(String),
     Username(String),
-    Password(String),
+    Password(SecretString),
+    TlsMode(TlsMode),
+    AcceptInvalidCertificates(bool),
+    CaCertificatePath(String),
     Error(String),
-    AccountAdded(Arc<SieveClient>),
+    DismissError,
+    AccountAdded(i64, Arc<SieveClient>),
     Back,
     Add,
+    Cancel,
 }
 
 pub enum Action {
     None,
     Run(Task<Message>),
-    Added(Arc<SieveClient>),
+    Added(i64, Arc<SieveClient>),
     Back,
 }
 
@@ -29,26 +41,41 @@ pub enum Action {
 pub enum State {
     Input,
     Connecting,
-    Error(String),
 }
 
 pub struct AddAccount {
     pool: SqlitePool,
+    preferences: Preferences,
     state: State,
+    // Aborts the running connect when cancelled or when the form is closed
+    connecting: Option<task::Handle>,
+    error: Option<String>,
     server: String,
+    // Empty for the default port from the preferences
+    port: String,
     username: String,
-    password: String,
+    password: SecretString,
+    tls_mode: TlsMode,
+    accept_invalid_certificates: bool,
+    ca_certificate_path: String,
 }
 
 impl AddAccount {
-    pub fn new(pool: SqlitePool) -> (Self, Task<Message>) {
+    pub fn new(pool: SqlitePool, preferences: Preferences) -> (Self, Task<Message>) {
         (
             Self {
                 pool,
+                preferences,
                 state: State::Input,
+                connecting: None,
+                error: None,
                 server: String::new(),
+                port: String::new(),
                 username: String::new(),
-                password: String::new(),
+                password: SecretString::default(),
+                tls_mode: TlsMode::StartTls,
+                accept_invalid_certificates: false,
+                ca_certificate_path: String::new(),
             },
             text_input::focus("server"),
         )
@@ -60,6 +87,10 @@ impl AddAccount {
                 self.server = server;
                 Action::None
             }
+            Message::Port(port) => {
+                self.port = port;
+                Action::None
+            }
             Message::Username(username) => {
                 self.username = username;
                 Action::None
@@ -68,76 +99,180 @@ impl AddAccount {
                 self.password = password;
                 Action::None
             }
+            Message::TlsMode(tls_mode) => {
+                self.tls_mode = tls_mode;
+                Action::None
+            }
+            Message::AcceptInvalidCertificates(accept) => {
+                self.accept_invalid_certificates = accept;
+                Action::None
+            }
+            Message::CaCertificatePath(path) => {
+                self.ca_certificate_path = path;
+                Action::None
+            }
             Message::Add => {
                 if self.state == State::Connecting {
                     return Action::None;
                 }
                 self.state = State::Connecting;
-                Action::Run(self.add_account())
+                self.error = None;
+                let (task, handle) = self.add_account().abortable();
+                self.connecting = Some(handle.abort_on_drop());
+                Action::Run(task)
+            }
+            Message::Cancel => {
+                if let Some(handle) = self.connecting.take() {
+                    handle.abort();
+                }
+                self.state = State::Input;
+                Action::None
             }
             Message::Error(err) => {
-                self.state = State::Error(err);
+                // Back to the form so the entered details can be corrected
+                self.state = State::Input;
+                self.connecting = None;
+                self.error = Some(err);
 
                 Action::None
             }
-            Message::AccountAdded(client) => Action::Added(client),
+            Message::DismissError => {
+                self.error = None;
+                Action::None
+            }
+            Message::AccountAdded(id, client) => {
+                self.connecting = None;
+                Action::Added(id, client)
+            }
             Message::Back => match &self.state {
                 State::Input => Action::Back,
                 State::Connecting => Action::None,
-                State::Error(_) => {
-                    self.state = State::Input;
-                    Action::None
-                }
             },
         }
     }
 
     pub fn view(&self) -> Element<Message> {
         match &self.state {
-            State::Input => column![
-                text_input("Server", &self.server)
-                    .on_input(Message::Server)
-                    .id("server"),
-                text_input("Username", &self.username).on_input(Message::Username),
-                text_input("Password", &self.password)
-                    .secure(true)
-                    .on_input(Message::Password)
-                    .on_submit_maybe(if self.is_valid() {
-                        Some(Message::Add)
-                    } else {
-                        None
-                    }),
-                vertical_space(),
-                row![
-                    horizontal_space(),
-                    button(text("Back").center())
-                        .on_press(Message::Back)
+            State::Input => {
+                let mut content = column![].padding(50).spacing(20);
+                if let Some(err) = &self.error {
+                    // Retrying re-submits the fields as they are now
+                    content = content.push(super::toast::toast(
+                        err,
+                        Message::DismissError,
+                        self.is_valid().then_some(Message::Add),
+                    ));
+                }
+
+                content
+                    .push(
+                        column![
+                            row![
+                                text_input("Server", &self.server)
+                                    .on_input(Message::Server)
+                                    .id("server"),
+                                text_input(
+                                    &format!("Port ({})", self.preferences.default_port),
+                                    &self.port
+                                )
+                                .on_input(Message::Port)
+                                .width(150),
+                            ]
+                            .spacing(10),
+                            text_input("Username", &self.username).on_input(Message::Username),
+                            text_input("Password", self.password.expose_secret())
+                                .secure(true)
+                                .on_input(|password| Message::Password(SecretString::from(
+                                    password
+                                )))
+                                .on_submit_maybe(if self.is_valid() {
+                                    Some(Message::Add)
+                                } else {
+                                    None
+                                }),
+                            self.view_security(),
+                            vertical_space(),
+                            row![
+                                horizontal_space(),
+                                button(text("Back").center())
+                                    .on_press(Message::Back)
+                                    .width(100),
+                                button(text("Add").center())
+                                    .on_press_maybe(if self.is_valid() {
+                                        Some(Message::Add)
+                                    } else {
+                                        None
+                                    })
+                                    .width(100)
+                            ]
+                            .spacing(20)
+                        ]
+                        .spacing(20),
+                    )
+                    .into()
+            }
+            State::Connecting => center(
+                column![
+                    text(format!("Connecting to {}...", self.server)),
+                    button(text("Cancel").center())
+                        .on_press(Message::Cancel)
                         .width(100),
-                    button(text("Add").center())
-                        .on_press_maybe(if self.is_valid() {
-                            Some(Message::Add)
-                        } else {
-                            None
-                        })
-                        .width(100)
                 ]
                 .spacing(20)
-            ]
-            .padding(50)
-            .spacing(20)
+                .align_x(Alignment::Center),
+            )
             .into(),
-            State::Connecting => center(text("Connecting...")).into(),
-            State::Error(err) => column![
-                center(text(format!("Error: {}", err))),
-                row![
-                    horizontal_space(),
-                    button(text("Back")).on_press(Message::Back)
-                ]
+        }
+    }
+
+    fn view_security(&self) -> Element<Message> {
+        let selected = Some(self.tls_mode);
+        let mut content = column![
+            text("Encryption").size(14),
+            row![
+                radio("STARTTLS", TlsMode::StartTls, selected, Message::TlsMode),
+                radio(
+                    "Implicit TLS",
+                    TlsMode::Implicit,
+                    selected,
+                    Message::TlsMode
+                ),
+                radio("None", TlsMode::None, selected, Message::TlsMode),
             ]
-            .padding(50)
-            .spacing(20)
-            .into(),
+            .spacing(20),
+        ]
+        .spacing(10);
+
+        if self.tls_mode == TlsMode::None {
+            content = content.push(
+                text("The password and your scripts will be sent unencrypted")
+                    .size(12)
+                    .style(text::danger),
+            );
+        } else {
+            content = content
+                .push(
+                    checkbox(
+                        "Accept self-signed certificates",
+                        self.accept_invalid_certificates,
+                    )
+                    .on_toggle(Message::AcceptInvalidCertificates),
+                )
+                .push(
+                    text_input("CA certificate file (optional)", &self.ca_certificate_path)
+                        .on_input(Message::CaCertificatePath),
+                );
         }
+
+        if self.port().is_err() {
+            content = content.push(
+                text("The port must be a number between 1 and 65535")
+                    .size(12)
+                    .style(text::danger),
+            );
+        }
+
+        content.into()
     }
 
     fn add_account(&mut self) -> Task<Message> {
@@ -145,19 +280,58 @@ impl AddAccount {
         let username = self.username.clone();
         let password = self.password.clone();
         let pool = self.pool.clone();
+        let preferences = self.preferences.clone();
+        let port = self.port().ok().flatten();
+        let tls_mode = self.tls_mode;
+        let accept_invalid_certificates = self.accept_invalid_certificates;
+        // The certificate only matters when the connection is encrypted
+        let ca_certificate_path = Some(self.ca_certificate_path.trim().to_string())
+            .filter(|path| !path.is_empty() && tls_mode != TlsMode::None);
         Task::future(async move {
-            match SieveClient::connect(server.clone(), 4190, &username, &password).await {
+            // The certificate is stored with the account, so the file may move
+            // or disappear afterwards
+            let ca_certificate = match ca_certificate_path {
+                Some(path) => match tokio::fs::read_to_string(&path).await {
+                    Ok(pem) => Some(pem),
+                    Err(err) => {
+                        return Message::Error(format!("Can't read {}: {}", path, err));
+                    }
+                },
+                None => None,
+            };
+            let spec = ConnectionSpec {
+                host: server,
+                port,
+                tls_mode,
+                accept_invalid_certificates,
+                ca_certificate,
+            };
+
+            match super::connect(&preferences, &spec, &username, password.expose_secret()).await {
                 Ok(client) => {
+                    let port = spec.port.map(i64::from);
+                    let tls_mode_name = super::tls_mode_name(tls_mode);
+                    // Saved as it was used, without TLS there is no certificate to accept
+                    let accept_invalid_certificates =
+                        accept_invalid_certificates && tls_mode != TlsMode::None;
+                    let capabilities = super::capabilities_json(&client);
                     match sqlx::query!(
-                        "INSERT INTO accounts (server, username, password) VALUES (?, ?, ?)",
-                        server,
+                        "INSERT INTO accounts (server, port, username, password, tls_mode, accept_invalid_certificates, ca_certificate, capabilities) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                        spec.host,
+                        port,
                         username,
-                        password
+                        password.expose_secret(),
+                        tls_mode_name,
+                        accept_invalid_certificates,
+                        spec.ca_certificate,
+                        capabilities
                     )
                     .execute(&pool)
                     .await
                     {
-                        Ok(_) => Message::AccountAdded(Arc::new(client)),
+                        Ok(result) => {
+                            Message::AccountAdded(result.last_insert_rowid(), Arc::new(client))
+                        }
                         Err(err) => Message::Error(err.to_string()),
                     }
                 }
@@ -166,7 +340,22 @@ impl AddAccount {
         })
     }
 
+    // `Ok(None)` when the field is empty and the default port applies
+    fn port(&self) -> Result<Option<u16>, ()> {
+        let port = self.port.trim();
+        if port.is_empty() {
+            return Ok(None);
+        }
+        match port.parse() {
+            Ok(0) | Err(_) => Err(()),
+            Ok(port) => Ok(Some(port)),
+        }
+    }
+
     fn is_valid(&self) -> bool {
-        !self.server.is_empty() && !self.username.is_empty() && !self.password.is_empty()
+        !self.server.is_empty()
+            && !self.username.is_empty()
+            && !self.password.expose_secret().is_empty()
+            && self.port().is_ok()
     }
 }
@@ -1,18 +1,129 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
 use iced::{
-    Element, Length, Task,
-    widget::{Container, button, center, column, container, row, scrollable, text},
+    Element, Length, Subscription, Task,
+    widget::{
+        Container, button, center, column, container, horizontal_space, pick_list, progress_bar,
+        row, scrollable, text, text_editor, text_input,
+    },
+};
+use sieve_client::{
+    CheckResult, ManageSieveError, SieveClient, SieveError,
+    parser::{self, ParseError, Summary},
+    validate_script_name,
 };
-use sieve_client::SieveClient;
+use sqlx::SqlitePool;
+use tokio::io::AsyncWriteExt;
+
+use crate::settings;
+
+// HAVESPACE is probed for this name, which is unlikely to exist, so the answer
+// is about room for additional data rather than replacing a script
+const QUOTA_PROBE_NAME: &str = "sieve-gui-quota-probe";
+// Servers that accept this much are treated as having no quota worth showing
+const QUOTA_PROBE_LIMIT: usize = 64 * 1024 * 1024;
+
+const SPINNER_FRAMES: [&str; 4] = ["|", "/", "-", "\\"];
+
+const DEFAULT_FONT_SIZE: u16 = 13;
+const FONT_SIZES: std::ops::RangeInclusive<u16> = 8..=32;
+const FONT_SIZE_SETTING: &str = "editor_font_size";
+const WRAP_SETTING: &str = "editor_wrap";
+
+const SCRIPT_NAME_INPUT: &str = "script-name";
 
 #[derive(Debug, Clone)]
 pub enum Message {
     Back,
     RefreshScripts,
-    ScriptsLoaded(Result<Vec<(String, bool)>, String>),
+    ScriptsLoaded(u64, Result<Vec<(String, bool)>, String>),
     ScriptSelected(String),
-    ScriptContentLoaded(Result<String, String>),
+    ScriptContentLoaded(u64, Result<String, String>),
+    Edit(text_editor::Action),
+    Save,
+    // Results name the script they belong to, it may no longer be the selected one
+    Saved(String, Result<SavedScript, String>),
+    SaveConflict(String),
+    ConfirmOverwrite,
+    CancelOverwrite,
+    DismissError,
+    EditorSettingsLoaded(Option<u16>, Option<bool>),
+    FontSize(i16),
+    ToggleWrap,
+    SettingStored(Result<(), String>),
+    SpinnerTick,
+    Revert,
+    ConfirmRevert,
+    CancelRevert,
+    ConfirmDiscard,
+    CancelDiscard,
+    UsageLoaded(u64, Result<Usage, String>),
+    // A load gave up because a newer one replaced it
+    Superseded,
+    ToggleSortOrder,
+    NewScript,
+    RenameScript,
+    ScriptNameInput(String),
+    ConfirmScriptName,
+    CancelScriptName,
+    // Carry the script selected when the new one was asked for
+    ScriptCreated(Option<String>, Result<String, String>),
+    // Carry the old name
+    ScriptRenamed(String, Result<String, String>),
+    ConnectionClosed(String),
+    Reconnect,
+    Reconnected(Result<(), String>),
+    CheckAll,
+    Checked(Result<Vec<ScriptCheck>, String>),
+    CloseCheckReport,
+    EditExternally,
+    EditedExternally(String, Result<String, String>),
+    CancelExternalSave,
+    FoldersLoaded(Result<Vec<String>, String>),
+    FoldersStored(Result<(), String>),
+    InsertFileInto(String),
+    NewFolderInput(String),
+    RememberFolder,
+    ForgetFolder(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Usage {
+    sizes: Vec<(String, usize)>,
+    // Largest script that still fits, `None` if the server has no limit we could find
+    free: Option<usize>,
+    // Every folder the scripts file into, found while they were downloaded anyway
+    folders: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SavedScript {
+    // The text as uploaded, after trimming whitespace if that is turned on
+    content: String,
+    hash: u64,
+    folders: BTreeSet<String>,
+}
+
+// Outcome of "Check all" for one script
+#[derive(Debug, Clone)]
+pub struct ScriptCheck {
+    name: String,
+    status: CheckStatus,
+}
+
+#[derive(Debug, Clone)]
+enum CheckStatus {
+    Ok,
+    Warnings(Vec<String>),
+    Error(String),
 }
 
 pub enum Action {
@@ -21,59 +132,271 @@ pub enum Action {
     Run(Task<Message>),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortOrder {
+    Name,
+    ActiveFirst,
+}
+
+// What to do once the user agreed to drop the unsaved changes in the editor
+enum AfterDiscard {
+    Select(String),
+    Back,
+    Create,
+}
+
+// The name being entered for a new script or for renaming one
+struct NamePrompt {
+    // The script being renamed, `None` when creating a new one
+    rename: Option<String>,
+    name: String,
+}
+
 #[derive(Debug, Clone)]
 struct ScriptInfo {
     name: String,
     is_active: bool,
+    size: Option<usize>,
 }
 
 pub struct Manage {
+    account_id: i64,
     client: Arc<SieveClient>,
+    pool: SqlitePool,
     scripts: Option<Vec<ScriptInfo>>,
     selected_script: Option<String>,
-    script_content: Option<String>,
+    script_content: Option<text_editor::Content>,
+    // Overview of the editor content, kept up to date while editing
+    summary: Option<Result<Summary, ParseError>>,
+    // The editor holds changes that haven't been uploaded yet
+    dirty: bool,
+    loading_script: bool,
+    spinner_frame: usize,
+    confirm_revert: bool,
+    // Leaving the script was asked for while it has unsaved changes
+    confirm_discard: Option<AfterDiscard>,
+    // Hash of the script as the server had it when it was loaded or last saved
+    loaded_hash: Option<u64>,
+    // Script whose save found the server copy changed, until overwriting it is
+    // confirmed or cancelled
+    confirm_overwrite: Option<String>,
+    saving: bool,
     error_message: Option<String>,
+    // Size of every script, total size and the largest script that still fits.
+    // Downloaded and probed on a full load, then kept up to date by saves,
+    // creates and renames.
+    sizes: HashMap<String, usize>,
+    used: Option<usize>,
+    free_space: Option<usize>,
+    // Download every script and probe HAVESPACE with the next load of the list,
+    // until one such load finished. That takes a download per script and a few
+    // dozen round trips, so it is only done when the list is loaded for the
+    // first time or refreshed by hand, not after every save.
+    reload_usage: bool,
+    sort_order: SortOrder,
+    font_size: u16,
+    wrap: bool,
+    name_prompt: Option<NamePrompt>,
+    trim_whitespace_on_save: bool,
+    // The server ended the session, commands fail until it is reconnected
+    disconnected: bool,
+    reconnecting: bool,
+    checking: bool,
+    // The script is open in an external editor, the built-in one is read-only
+    editing_externally: bool,
+    // The external editor changed the script, offer to upload it
+    confirm_external_save: bool,
+    // Shown instead of the editor until closed or a script is picked from it
+    check_report: Option<Vec<ScriptCheck>>,
+    // Bumped for every load of the selected script and every refresh of the
+    // list, results of older loads are dropped instead of overwriting newer
+    // ones. The tasks aren't aborted, that could cut a command short and leave
+    // the session out of step with the server.
+    script_generation: Arc<AtomicU64>,
+    list_generation: Arc<AtomicU64>,
+    // Folders remembered for this account, offered for new `fileinto` rules as
+    // ManageSieve can't list the mailboxes
+    folders: BTreeSet<String>,
+    new_folder: String,
 }
 
 impl Manage {
-    pub fn new(client: Arc<SieveClient>) -> (Self, Task<Message>) {
+    pub fn new(
+        account_id: i64,
+        client: Arc<SieveClient>,
+        pool: SqlitePool,
+        trim_whitespace_on_save: bool,
+    ) -> (Self, Task<Message>) {
         let manage = Self {
+            account_id,
             client: client.clone(),
+            pool,
             scripts: None,
             selected_script: None,
             script_content: None,
+            summary: None,
+            dirty: false,
+            loading_script: false,
+            spinner_frame: 0,
+            confirm_revert: false,
+            confirm_discard: None,
+            loaded_hash: None,
+            confirm_overwrite: None,
+            saving: false,
             error_message: None,
+            sizes: HashMap::new(),
+            used: None,
+            free_space: None,
+            reload_usage: true,
+            sort_order: SortOrder::ActiveFirst,
+            font_size: DEFAULT_FONT_SIZE,
+            wrap: true,
+            name_prompt: None,
+            trim_whitespace_on_save,
+            disconnected: false,
+            reconnecting: false,
+            checking: false,
+            editing_externally: false,
+            confirm_external_save: false,
+            check_report: None,
+            script_generation: Arc::new(AtomicU64::new(0)),
+            list_generation: Arc::new(AtomicU64::new(0)),
+            folders: BTreeSet::new(),
+            new_folder: String::new(),
         };
 
-        let task = manage.refresh_scripts();
+        let task = Task::batch([
+            manage.refresh_scripts(),
+            manage.load_editor_settings(),
+            manage.load_folders(),
+        ]);
 
         (manage, task)
     }
 
     pub fn update(&mut self, message: Message) -> Action {
         match message {
-            Message::ScriptsLoaded(result) => {
+            Message::ScriptsLoaded(generation, _)
+                if generation != self.list_generation.load(Ordering::Relaxed) =>
+            {
+                Action::None
+            }
+            Message::ScriptsLoaded(generation, result) => match result {
+                Ok(scripts) => {
+                    let names: Vec<String> = scripts.iter().map(|(name, _)| name.clone()).collect();
+                    self.scripts = Some(
+                        scripts
+                            .into_iter()
+                            .map(|(name, is_active)| ScriptInfo {
+                                name,
+                                is_active,
+                                size: None,
+                            })
+                            .collect(),
+                    );
+                    self.sort_scripts();
+                    self.error_message = None;
+
+                    // Keep the selection across a refresh unless the script is gone
+                    let selection_gone = self
+                        .selected_script
+                        .as_ref()
+                        .is_some_and(|selected| !names.contains(selected));
+                    if selection_gone {
+                        self.selected_script = None;
+                        self.script_content = None;
+                    }
+
+                    if self.reload_usage {
+                        self.used = None;
+                        Action::Run(self.load_usage(generation, names))
+                    } else {
+                        self.sizes.retain(|name, _| names.contains(name));
+                        self.apply_sizes();
+                        Action::None
+                    }
+                }
+                Err(err) => {
+                    self.error_message = Some(err);
+                    self.scripts = Some(Vec::new());
+                    Action::None
+                }
+            },
+            Message::UsageLoaded(generation, _)
+                if generation != self.list_generation.load(Ordering::Relaxed) =>
+            {
+                Action::None
+            }
+            Message::UsageLoaded(_, result) => {
+                self.reload_usage = false;
                 match result {
-                    Ok(scripts) => {
-                        self.scripts = Some(
-                            scripts
-                                .into_iter()
-                                .map(|(name, is_active)| ScriptInfo { name, is_active })
-                                .collect(),
-                        );
-                        self.error_message = None;
+                    Ok(usage) => {
+                        self.sizes = usage.sizes.into_iter().collect();
+                        self.free_space = usage.free;
+                        self.apply_sizes();
+                        return Action::Run(self.remember_folders(usage.folders));
                     }
                     Err(err) => {
                         self.error_message = Some(err);
-                        self.scripts = Some(Vec::new());
                     }
                 }
+
+                Action::None
+            }
+            Message::ToggleSortOrder => {
+                self.sort_order = match self.sort_order {
+                    SortOrder::Name => SortOrder::ActiveFirst,
+                    SortOrder::ActiveFirst => SortOrder::Name,
+                };
+                self.sort_scripts();
+
+                Action::None
+            }
+            // Unsaved changes are only dropped once confirmed, like for `Revert`
+            Message::ScriptSelected(script_name)
+                if self.dirty && self.selected_script.as_ref() != Some(&script_name) =>
+            {
+                self.confirm_discard = Some(AfterDiscard::Select(script_name));
+                Action::None
+            }
+            Message::Back if self.dirty => {
+                self.confirm_discard = Some(AfterDiscard::Back);
+                Action::None
+            }
+            // The new script replaces the editor buffer
+            Message::NewScript if self.dirty => {
+                self.confirm_discard = Some(AfterDiscard::Create);
+                Action::None
+            }
+            Message::ConfirmDiscard => match self.confirm_discard.take() {
+                Some(after) => {
+                    self.dirty = false;
+                    match after {
+                        AfterDiscard::Select(script_name) => {
+                            self.update(Message::ScriptSelected(script_name))
+                        }
+                        AfterDiscard::Back => Action::Back,
+                        AfterDiscard::Create => self.update(Message::NewScript),
+                    }
+                }
+                None => Action::None,
+            },
+            Message::CancelDiscard => {
+                self.confirm_discard = None;
                 Action::None
             }
             Message::ScriptSelected(script_name) => {
+                self.check_report = None;
+                self.confirm_discard = None;
+                // Picking another script while one loads replaces that load
                 if self.selected_script.as_ref() != Some(&script_name) {
                     self.selected_script = Some(script_name.clone());
                     self.script_content = None;
+                    self.dirty = false;
+                    self.confirm_external_save = false;
+                    self.confirm_revert = false;
+                    self.confirm_overwrite = None;
+                    self.loading_script = true;
                     self.error_message = None;
 
                     Action::Run(self.load_script_content(script_name))
@@ -81,10 +404,19 @@ impl Manage {
                     Action::None
                 }
             }
-            Message::ScriptContentLoaded(result) => {
+            Message::ScriptContentLoaded(generation, _)
+                if generation != self.script_generation.load(Ordering::Relaxed) =>
+            {
+                Action::None
+            }
+            Message::ScriptContentLoaded(_, result) => {
+                self.loading_script = false;
                 match result {
                     Ok(content) => {
-                        self.script_content = Some(content);
+                        self.loaded_hash = Some(content_hash(&content));
+                        self.script_content = Some(text_editor::Content::with_text(&content));
+                        self.dirty = false;
+                        self.update_summary();
                     }
                     Err(err) => {
                         self.error_message = Some(err);
@@ -94,16 +426,419 @@ impl Manage {
 
                 Action::None
             }
+            Message::Edit(action) => {
+                if let Some(content) = &mut self.script_content {
+                    let is_edit = action.is_edit();
+                    self.dirty |= is_edit;
+                    content.perform(action);
+                    if is_edit {
+                        self.update_summary();
+                    }
+                }
+
+                Action::None
+            }
+            Message::Save => match (&self.selected_script, &self.script_content) {
+                (Some(script_name), Some(content))
+                    if self.dirty && !self.saving && !self.read_only() =>
+                {
+                    self.saving = true;
+                    self.confirm_external_save = false;
+                    Action::Run(self.save_script(
+                        script_name.clone(),
+                        content.text(),
+                        self.loaded_hash,
+                    ))
+                }
+                _ => Action::None,
+            },
+            Message::SaveConflict(script_name) => {
+                self.saving = false;
+                // Another script was opened meanwhile, its buffer wasn't saved
+                if self.selected_script.as_ref() == Some(&script_name) {
+                    self.confirm_overwrite = Some(script_name);
+                }
+                Action::None
+            }
+            Message::ConfirmOverwrite => match (
+                self.confirm_overwrite.take(),
+                &self.selected_script,
+                &self.script_content,
+            ) {
+                (Some(script_name), Some(selected), Some(content))
+                    if *selected == script_name && !self.saving =>
+                {
+                    self.saving = true;
+                    Action::Run(self.save_script(script_name, content.text(), None))
+                }
+                _ => Action::None,
+            },
+            Message::CancelOverwrite => {
+                self.confirm_overwrite = None;
+                Action::None
+            }
+            Message::Saved(script_name, result) => {
+                self.saving = false;
+                match result {
+                    Ok(saved) => {
+                        // The editor only reflects the save if it still shows that script
+                        if self.selected_script.as_ref() == Some(&script_name) {
+                            self.loaded_hash = Some(saved.hash);
+                            let text = self.script_content.as_ref().map(|content| content.text());
+                            match text {
+                                Some(text) if text == saved.content => self.dirty = false,
+                                // Trimming changed the text, the editor shows what the server has now
+                                Some(text)
+                                    if self.trim_whitespace_on_save
+                                        && trim_trailing_whitespace(&text) == saved.content =>
+                                {
+                                    self.script_content =
+                                        Some(text_editor::Content::with_text(&saved.content));
+                                    self.dirty = false;
+                                    self.update_summary();
+                                }
+                                // Edited while saving, those changes are still unsaved
+                                _ => {}
+                            }
+                        }
+                        let listed = self
+                            .scripts
+                            .iter()
+                            .flatten()
+                            .any(|script| script.name == script_name);
+                        self.set_size(script_name, saved.content.len());
+                        let folders = self.remember_folders(saved.folders);
+                        // Saving a script deleted meanwhile created it again
+                        if listed {
+                            Action::Run(folders)
+                        } else {
+                            Action::Run(Task::batch([folders, self.refresh_scripts()]))
+                        }
+                    }
+                    Err(err) => {
+                        self.error_message = Some(err);
+                        Action::None
+                    }
+                }
+            }
+            Message::DismissError => {
+                self.error_message = None;
+                Action::None
+            }
+            Message::Superseded => Action::None,
+            Message::FoldersLoaded(result) => {
+                match result {
+                    Ok(folders) => self.folders.extend(folders),
+                    Err(err) => self.error_message = Some(err),
+                }
+                Action::None
+            }
+            Message::FoldersStored(result) => {
+                if let Err(err) = result {
+                    self.error_message = Some(err);
+                }
+                Action::None
+            }
+            Message::InsertFileInto(folder) => {
+                if let Some(content) = &mut self.script_content {
+                    let folder = folder.replace('\\', "\\\\").replace('"', "\\\"");
+                    content.perform(text_editor::Action::Edit(text_editor::Edit::Paste(
+                        Arc::new(format!("fileinto \"{}\";", folder)),
+                    )));
+                    self.dirty = true;
+                    self.update_summary();
+                }
+                Action::None
+            }
+            Message::NewFolderInput(folder) => {
+                self.new_folder = folder;
+                Action::None
+            }
+            Message::RememberFolder => {
+                let folder = self.new_folder.trim().to_string();
+                self.new_folder.clear();
+                if folder.is_empty() {
+                    Action::None
+                } else {
+                    Action::Run(self.remember_folders([folder]))
+                }
+            }
+            Message::ForgetFolder(folder) => {
+                self.folders.remove(&folder);
+                let pool = self.pool.clone();
+                let account_id = self.account_id;
+                Action::Run(Task::future(async move {
+                    Message::FoldersStored(
+                        sqlx::query!(
+                            "DELETE FROM folders WHERE account_id = ? AND name = ?",
+                            account_id,
+                            folder
+                        )
+                        .execute(&pool)
+                        .await
+                        .map(|_| ())
+                        .map_err(|e| format!("Failed to forget folder: {}", e)),
+                    )
+                }))
+            }
+            Message::ConnectionClosed(reason) => {
+                // Whatever was running has failed with the session
+                self.saving = false;
+                self.loading_script = false;
+                self.checking = false;
+                self.disconnected = true;
+                self.error_message = Some(SieveError::Disconnected(reason).to_string());
+                Action::None
+            }
+            Message::Reconnect => {
+                if self.reconnecting {
+                    return Action::None;
+                }
+                self.reconnecting = true;
+                let client = self.client.clone();
+                Action::Run(Task::future(async move {
+                    Message::Reconnected(
+                        client
+                            .reconnect()
+                            .await
+                            .map_err(|e| format!("Failed to reconnect: {}", SieveError::from(e))),
+                    )
+                }))
+            }
+            Message::Reconnected(result) => {
+                self.reconnecting = false;
+                match result {
+                    Ok(()) => {
+                        self.disconnected = false;
+                        self.error_message = None;
+                        Action::Run(self.refresh_scripts())
+                    }
+                    Err(err) => {
+                        self.error_message = Some(err);
+                        Action::None
+                    }
+                }
+            }
+            Message::EditorSettingsLoaded(font_size, wrap) => {
+                if let Some(font_size) = font_size.filter(|size| FONT_SIZES.contains(size)) {
+                    self.font_size = font_size;
+                }
+                if let Some(wrap) = wrap {
+                    self.wrap = wrap;
+                }
+                Action::None
+            }
+            Message::FontSize(step) => {
+                let font_size = self
+                    .font_size
+                    .saturating_add_signed(step)
+                    .clamp(*FONT_SIZES.start(), *FONT_SIZES.end());
+                if font_size == self.font_size {
+                    return Action::None;
+                }
+                self.font_size = font_size;
+                Action::Run(self.store_setting(FONT_SIZE_SETTING, font_size.to_string()))
+            }
+            Message::ToggleWrap => {
+                self.wrap = !self.wrap;
+                Action::Run(self.store_setting(WRAP_SETTING, self.wrap.to_string()))
+            }
+            Message::Revert => {
+                if self.dirty {
+                    self.confirm_revert = true;
+                    Action::None
+                } else {
+                    self.reload_selected_script()
+                }
+            }
+            Message::ConfirmRevert => {
+                self.confirm_revert = false;
+                self.reload_selected_script()
+            }
+            Message::CancelRevert => {
+                self.confirm_revert = false;
+                Action::None
+            }
+            Message::SpinnerTick => {
+                self.spinner_frame = (self.spinner_frame + 1) % SPINNER_FRAMES.len();
+                Action::None
+            }
+            Message::SettingStored(result) => {
+                if let Err(err) = result {
+                    self.error_message = Some(err);
+                }
+                Action::None
+            }
             Message::RefreshScripts => {
                 self.scripts = None;
                 self.error_message = None;
+                self.used = None;
+                self.free_space = None;
+                self.reload_usage = true;
 
                 Action::Run(self.refresh_scripts())
             }
+            Message::NewScript => {
+                self.name_prompt = Some(NamePrompt {
+                    rename: None,
+                    name: String::new(),
+                });
+                Action::Run(text_input::focus(SCRIPT_NAME_INPUT))
+            }
+            Message::RenameScript => {
+                if let Some(script_name) = &self.selected_script {
+                    self.name_prompt = Some(NamePrompt {
+                        rename: Some(script_name.clone()),
+                        name: script_name.clone(),
+                    });
+                    Action::Run(text_input::focus(SCRIPT_NAME_INPUT))
+                } else {
+                    Action::None
+                }
+            }
+            Message::ScriptNameInput(name) => {
+                if let Some(prompt) = &mut self.name_prompt {
+                    prompt.name = name;
+                }
+                Action::None
+            }
+            Message::ConfirmScriptName => match self.name_prompt.take() {
+                // Invalid names can't be submitted, the prompt shows why
+                Some(prompt) if validate_script_name(&prompt.name).is_err() => {
+                    self.name_prompt = Some(prompt);
+                    Action::None
+                }
+                Some(prompt) => Action::Run(self.name_script(prompt)),
+                None => Action::None,
+            },
+            Message::CancelScriptName => {
+                self.name_prompt = None;
+                Action::None
+            }
+            Message::ScriptCreated(selected, result) => match result {
+                // Another script was picked or edited meanwhile, the new one is
+                // only added to the list
+                Ok(script_name) if self.selected_script != selected || self.dirty => {
+                    self.set_size(script_name, 0);
+                    Action::Run(self.refresh_scripts())
+                }
+                Ok(script_name) => {
+                    self.set_size(script_name.clone(), 0);
+                    // A load still running for the previous script is dropped
+                    self.script_generation.fetch_add(1, Ordering::Relaxed);
+                    self.loading_script = false;
+                    self.selected_script = Some(script_name);
+                    self.script_content = Some(text_editor::Content::new());
+                    self.update_summary();
+                    self.loaded_hash = Some(content_hash(""));
+                    self.dirty = false;
+                    self.confirm_external_save = false;
+                    self.confirm_revert = false;
+                    self.confirm_overwrite = None;
+                    self.error_message = None;
+                    Action::Run(self.refresh_scripts())
+                }
+                Err(err) => {
+                    self.error_message = Some(err);
+                    Action::None
+                }
+            },
+            Message::ScriptRenamed(old_name, result) => match result {
+                Ok(script_name) => {
+                    if let Some(size) = self.sizes.remove(&old_name) {
+                        self.sizes.insert(script_name.clone(), size);
+                    }
+                    // The editor keeps its buffer, it now belongs to the new
+                    // name. Unless another script was picked meanwhile.
+                    if self.selected_script.as_ref() == Some(&old_name) {
+                        self.selected_script = Some(script_name);
+                    }
+                    Action::Run(self.refresh_scripts())
+                }
+                Err(err) => {
+                    self.error_message = Some(err);
+                    Action::None
+                }
+            },
+            Message::CheckAll => {
+                if self.checking {
+                    return Action::None;
+                }
+                self.checking = true;
+                Action::Run(self.check_scripts())
+            }
+            Message::Checked(result) => {
+                self.checking = false;
+                match result {
+                    Ok(report) => self.check_report = Some(report),
+                    Err(err) => self.error_message = Some(err),
+                }
+                Action::None
+            }
+            Message::CloseCheckReport => {
+                self.check_report = None;
+                Action::None
+            }
+            Message::EditExternally => match (&self.selected_script, &self.script_content) {
+                (Some(script_name), Some(content)) if !self.editing_externally => {
+                    self.editing_externally = true;
+                    self.confirm_external_save = false;
+                    let script_name = script_name.clone();
+                    let content = content.text();
+                    Action::Run(Task::future(async move {
+                        let result = edit_externally(&script_name, &content).await;
+                        Message::EditedExternally(script_name, result)
+                    }))
+                }
+                _ => Action::None,
+            },
+            Message::EditedExternally(script_name, result) => {
+                self.editing_externally = false;
+                match result {
+                    // Another script was opened meanwhile, its buffer stays as it is
+                    Ok(_) if self.selected_script.as_ref() != Some(&script_name) => {
+                        self.error_message = Some(format!(
+                            "Changes to '{}' from the external editor were dropped, another script is open",
+                            script_name
+                        ));
+                    }
+                    Ok(edited) => {
+                        let unchanged = self
+                            .script_content
+                            .as_ref()
+                            .is_some_and(|content| content.text() == edited);
+                        if !unchanged {
+                            self.script_content = Some(text_editor::Content::with_text(&edited));
+                            self.dirty = true;
+                            self.confirm_external_save = true;
+                            self.update_summary();
+                        }
+                    }
+                    Err(err) => self.error_message = Some(err),
+                }
+                Action::None
+            }
+            Message::CancelExternalSave => {
+                // The changes stay in the editor, unsaved
+                self.confirm_external_save = false;
+                Action::None
+            }
             Message::Back => Action::Back,
         }
     }
 
+    pub fn client(&self) -> Arc<SieveClient> {
+        self.client.clone()
+    }
+
+    // Scripts of another owner can be read but not saved, see
+    // `Capabilities::is_writable`
+    fn read_only(&self) -> bool {
+        self.client
+            .username()
+            .is_some_and(|username| !self.client.capabilities().is_writable(username))
+    }
+
     pub fn view(&self) -> Element<Message> {
         let left_panel = self.view_script_list();
         let right_panel = self.view_script_content();
@@ -120,10 +855,34 @@ impl Manage {
         let header = row![
             button("Back").on_press(Message::Back),
             text("Scripts").size(20),
-            button("Refresh").on_press(Message::RefreshScripts)
+            button("Refresh").on_press(Message::RefreshScripts),
+            button("New").on_press(Message::NewScript)
         ]
         .spacing(15);
 
+        let sort_label = match self.sort_order {
+            SortOrder::Name => "Sorted by name",
+            SortOrder::ActiveFirst => "Sorted by name, active first",
+        };
+        let sort_toggle = row![
+            button(text(sort_label).size(12))
+                .style(button::text)
+                .on_press(Message::ToggleSortOrder),
+            horizontal_space(),
+            button(
+                text(if self.checking {
+                    "Checking..."
+                } else {
+                    "Check all"
+                })
+                .size(12)
+            )
+            .on_press_maybe(
+                (!self.checking && self.scripts.as_ref().is_some_and(|s| !s.is_empty()))
+                    .then_some(Message::CheckAll)
+            ),
+        ];
+
         let main_content: Element<Message> = match &self.scripts {
             None => text("Loading scripts...").size(14).into(),
             Some(scripts) => {
@@ -133,11 +892,14 @@ impl Manage {
                     column(scripts.iter().map(|script| {
                         let is_selected = self.selected_script.as_ref() == Some(&script.name);
 
-                        let script_text = if script.is_active {
+                        let mut script_text = if script.is_active {
                             format!("● {} (active)", script.name)
                         } else {
                             script.name.clone()
                         };
+                        if let Some(size) = script.size {
+                            script_text.push_str(&format!(" · {}", format_size(size)));
+                        }
 
                         let script_button = button(text(script_text).size(14))
                             .width(Length::Fill)
@@ -155,7 +917,25 @@ impl Manage {
             }
         };
 
-        let content = column![header, main_content].spacing(10);
+        let mut content = column![header].spacing(10);
+        if let Some(prompt) = self.view_name_prompt() {
+            content = content.push(prompt);
+        }
+        content = content.push(sort_toggle).push(main_content);
+        if let Some(usage) = self.view_usage() {
+            content = content.push(usage);
+        }
+        if let Some(mechanism) = self.client.sasl_mechanism() {
+            content = content.push(text(format!("Authenticated via {}", mechanism)).size(12));
+        }
+        if let Some(owner) = &self.client.capabilities().owner {
+            let owner = if self.read_only() {
+                format!("Scripts of {}, read-only", owner)
+            } else {
+                format!("Scripts of {}", owner)
+            };
+            content = content.push(text(owner).size(12));
+        }
 
         container(content)
             .width(350)
@@ -164,35 +944,367 @@ impl Manage {
             .style(container::rounded_box)
     }
 
+    fn view_name_prompt(&self) -> Option<Element<Message>> {
+        let prompt = self.name_prompt.as_ref()?;
+
+        let label = match &prompt.rename {
+            Some(script_name) => format!("Rename '{}' to", script_name),
+            None => "Name of the new script".to_string(),
+        };
+        // Checked while typing so the problem shows before anything is sent
+        let validation = validate_script_name(&prompt.name);
+
+        let mut content = column![
+            text(label).size(14),
+            text_input("Script name", &prompt.name)
+                .id(SCRIPT_NAME_INPUT)
+                .on_input(Message::ScriptNameInput)
+                .on_submit_maybe(validation.is_ok().then_some(Message::ConfirmScriptName)),
+        ]
+        .spacing(5);
+        // An empty field is where every prompt starts, not worth a warning
+        if let (Err(err), false) = (&validation, prompt.name.is_empty()) {
+            content = content.push(text(err.to_string()).size(12).style(text::danger));
+        }
+
+        Some(
+            content
+                .push(
+                    row![
+                        button(if prompt.rename.is_some() {
+                            "Rename"
+                        } else {
+                            "Create"
+                        })
+                        .on_press_maybe(validation.is_ok().then_some(Message::ConfirmScriptName)),
+                        button("Cancel").on_press(Message::CancelScriptName),
+                    ]
+                    .spacing(10),
+                )
+                .into(),
+        )
+    }
+
+    fn update_summary(&mut self) {
+        self.summary = self.script_content.as_ref().map(|content| {
+            parser::parse(&content.text()).map(|expressions| parser::summarize(&expressions))
+        });
+    }
+
+    fn view_summary(&self) -> Option<Element<Message>> {
+        // Left over from the previous script while the next one loads
+        self.script_content.as_ref()?;
+        Some(match self.summary.as_ref()? {
+            Ok(summary) => text(summary.to_string()).size(12).into(),
+            Err(err) => text(format!("No summary: {}", err)).size(12).into(),
+        })
+    }
+
+    // Inserting a `fileinto` for a remembered folder, and keeping that list
+    fn view_folders(&self) -> Element<Message> {
+        let folders: Vec<String> = self.folders.iter().cloned().collect();
+        row![
+            pick_list(folders.clone(), None::<String>, Message::InsertFileInto)
+                .placeholder("Insert fileinto...")
+                .text_size(12),
+            horizontal_space(),
+            text_input("Folder to remember", &self.new_folder)
+                .on_input(Message::NewFolderInput)
+                .on_submit(Message::RememberFolder)
+                .size(12)
+                .width(200),
+            button(text("Remember").size(12)).on_press_maybe(
+                (!self.new_folder.trim().is_empty()).then_some(Message::RememberFolder)
+            ),
+            pick_list(folders, None::<String>, Message::ForgetFolder)
+                .placeholder("Forget...")
+                .text_size(12),
+        ]
+        .spacing(10)
+        .into()
+    }
+
+    // Replaces the editor buffer with the server copy of the selected script
+    fn reload_selected_script(&mut self) -> Action {
+        match self.selected_script.clone() {
+            Some(script_name) if !self.loading_script => {
+                self.script_content = None;
+                self.dirty = false;
+                self.confirm_overwrite = None;
+                self.loading_script = true;
+                self.error_message = None;
+                Action::Run(self.load_script_content(script_name))
+            }
+            _ => Action::None,
+        }
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        if self.loading_script {
+            iced::time::every(Duration::from_millis(100)).map(|_| Message::SpinnerTick)
+        } else {
+            Subscription::none()
+        }
+    }
+
+    fn sort_scripts(&mut self) {
+        if let Some(scripts) = &mut self.scripts {
+            scripts.sort_by_cached_key(|script| script.name.to_lowercase());
+            if self.sort_order == SortOrder::ActiveFirst {
+                // Stable, so the rest stays alphabetical
+                scripts.sort_by_key(|script| !script.is_active);
+            }
+        }
+    }
+
+    fn view_usage(&self) -> Option<Element<Message>> {
+        let used = self.used?;
+
+        // HAVESPACE only tells how large an additional script may be. With a
+        // limit on the total size, which is what servers usually have, that is
+        // the room left, so used and free add up to about the limit.
+        Some(match self.free_space {
+            Some(free) => {
+                let limit = used + free;
+                column![
+                    text(format!(
+                        "{} of about {} used",
+                        format_size(used),
+                        format_size(limit)
+                    ))
+                    .size(12),
+                    progress_bar(0.0..=limit.max(1) as f32, used as f32),
+                ]
+                .spacing(5)
+                .into()
+            }
+            None => text(format!("{} used", format_size(used))).size(12).into(),
+        })
+    }
+
+    fn view_check_report(&self, report: &[ScriptCheck]) -> Element<Message> {
+        let problems = report
+            .iter()
+            .filter(|check| !matches!(check.status, CheckStatus::Ok))
+            .count();
+        let summary = match problems {
+            0 => format!("All {} scripts look fine", report.len()),
+            _ => format!("{} of {} scripts need attention", problems, report.len()),
+        };
+
+        let header = row![
+            text(summary).size(20).width(Length::Fill),
+            button("Close").on_press(Message::CloseCheckReport),
+        ]
+        .spacing(15);
+
+        let entries = column(report.iter().map(|check| {
+            let (status, details): (Element<Message>, &[String]) = match &check.status {
+                CheckStatus::Ok => (text("OK").size(14).into(), &[]),
+                CheckStatus::Warnings(warnings) => (
+                    text(format!("{} warning(s)", warnings.len()))
+                        .size(14)
+                        .into(),
+                    warnings,
+                ),
+                CheckStatus::Error(err) => (
+                    text("Error").size(14).style(text::danger).into(),
+                    std::slice::from_ref(err),
+                ),
+            };
+
+            let mut entry = column![row![text(&check.name).size(14).width(Length::Fill), status]];
+            for detail in details {
+                entry = entry.push(text(detail).size(12));
+            }
+
+            button(entry.spacing(5))
+                .width(Length::Fill)
+                .padding([8, 12])
+                .style(button::text)
+                .on_press(Message::ScriptSelected(check.name.clone()))
+                .into()
+        }))
+        .spacing(5);
+
+        column![header, scrollable(entries).height(Length::Fill)]
+            .spacing(10)
+            .into()
+    }
+
     fn view_script_content(&self) -> Container<Message> {
-        let content: Element<Message> = if let Some(err) = &self.error_message {
-            text(format!("Error: {}", err)).size(14).into()
+        // Without a loaded script the error takes the whole panel
+        let blocking_error = self
+            .error_message
+            .as_ref()
+            .filter(|_| self.script_content.is_none());
+        let content: Element<Message> = if let Some(report) = &self.check_report {
+            self.view_check_report(report)
+        } else if let Some(err) = blocking_error {
+            let mut error = column![text(format!("Error: {}", err)).size(14)].spacing(10);
+            if let Some(reconnect) = self.reconnect_button() {
+                error = error.push(reconnect);
+            }
+            error.into()
         } else if let Some(script_name) = &self.selected_script {
             // Header
-            let header = text(format!("Script: {}", script_name)).size(20);
+            let title = if self.dirty {
+                format!("Script: {} (modified)", script_name)
+            } else {
+                format!("Script: {}", script_name)
+            };
+            let save_label = if self.saving { "Saving..." } else { "Save" };
+            let header = row![
+                text(title).size(20).width(Length::Fill),
+                button("A-").on_press(Message::FontSize(-1)),
+                button("A+").on_press(Message::FontSize(1)),
+                button(if self.wrap { "Wrap: on" } else { "Wrap: off" })
+                    .on_press(Message::ToggleWrap),
+                button("Rename").on_press_maybe(
+                    (!self.loading_script && !self.saving).then_some(Message::RenameScript)
+                ),
+                button(if self.editing_externally {
+                    "Editing..."
+                } else {
+                    "External editor"
+                })
+                .on_press_maybe(
+                    (!self.loading_script
+                        && !self.editing_externally
+                        && self.script_content.is_some())
+                    .then_some(Message::EditExternally)
+                ),
+                button("Revert").on_press_maybe(
+                    (!self.loading_script && !self.saving && !self.editing_externally)
+                        .then_some(Message::Revert)
+                ),
+                button(save_label).on_press_maybe(
+                    (self.dirty
+                        && !self.saving
+                        && !self.read_only()
+                        && self.script_content.is_some())
+                    .then_some(Message::Save)
+                )
+            ]
+            .spacing(15);
 
             // Content
             let content_display: Element<Message> = match &self.script_content {
+                None if self.loading_script => text(format!(
+                    "{} Loading script...",
+                    SPINNER_FRAMES[self.spinner_frame]
+                ))
+                .font(iced::Font::MONOSPACE)
+                .size(14)
+                .into(),
                 None => text("No content available").size(14).into(),
                 Some(content) => {
-                    if content.is_empty() {
-                        text("No content available").size(14).into()
-                    } else {
-                        scrollable(text(content).font(iced::Font::MONOSPACE).size(13))
-                            .width(Length::Fill)
-                            .height(Length::Fill)
-                            .into()
+                    let mut editor = text_editor(content)
+                        .font(iced::Font::MONOSPACE)
+                        .size(self.font_size)
+                        .wrapping(if self.wrap {
+                            text::Wrapping::Word
+                        } else {
+                            text::Wrapping::None
+                        })
+                        .height(Length::Fill);
+                    // Read-only while the external editor has the script, its
+                    // result would replace anything typed here
+                    if !self.editing_externally {
+                        editor = editor.on_action(Message::Edit);
                     }
+                    editor.into()
                 }
             };
 
-            column![header, content_display].spacing(10).into()
+            // Errors while editing must not hide the unsaved buffer
+            let mut panel = column![header].spacing(10);
+            if self.script_content.is_some() && !self.editing_externally {
+                panel = panel.push(self.view_folders());
+            }
+            if let Some(summary) = self.view_summary() {
+                panel = panel.push(summary);
+            }
+            if self.confirm_revert {
+                panel = panel.push(
+                    row![
+                        text("Discard your unsaved changes and reload the script from the server?")
+                            .size(14)
+                            .width(Length::Fill),
+                        button("Discard").on_press(Message::ConfirmRevert),
+                        button("Cancel").on_press(Message::CancelRevert),
+                    ]
+                    .spacing(10),
+                );
+            }
+            if self.confirm_external_save {
+                panel = panel.push(
+                    row![
+                        text(
+                            "The script was changed in the external editor. Save it to the server?"
+                        )
+                        .size(14)
+                        .width(Length::Fill),
+                        button("Save").on_press_maybe(
+                            (!self.saving && !self.read_only()).then_some(Message::Save)
+                        ),
+                        button("Later").on_press(Message::CancelExternalSave),
+                    ]
+                    .spacing(10),
+                );
+            }
+            if self.confirm_overwrite.is_some() {
+                panel = panel.push(
+                    row![
+                        text("The script was changed on the server since you loaded it. Overwrite those changes?")
+                            .size(14)
+                            .width(Length::Fill),
+                        button("Overwrite").on_press(Message::ConfirmOverwrite),
+                        button("Cancel").on_press(Message::CancelOverwrite),
+                    ]
+                    .spacing(10),
+                );
+            }
+            if let Some(err) = &self.error_message {
+                panel = panel.push(super::toast::toast(
+                    err,
+                    Message::DismissError,
+                    (self.disconnected && !self.reconnecting).then_some(Message::Reconnect),
+                ));
+            }
+            panel.push(content_display).into()
         } else {
             // No script selected
 
             center(text("Select a script from the list to view its content").size(16)).into()
         };
 
+        // Above the check report too, its entries can ask for it
+        let content: Element<Message> = match &self.confirm_discard {
+            Some(after) => column![
+                row![
+                    text(match after {
+                        AfterDiscard::Select(_) => {
+                            "Discard your unsaved changes and open another script?"
+                        }
+                        AfterDiscard::Back => "Discard your unsaved changes and go back?",
+                        AfterDiscard::Create => {
+                            "Discard your unsaved changes and create a new script?"
+                        }
+                    })
+                    .size(14)
+                    .width(Length::Fill),
+                    button("Discard").on_press(Message::ConfirmDiscard),
+                    button("Cancel").on_press(Message::CancelDiscard),
+                ]
+                .spacing(10),
+                content
+            ]
+            .spacing(10)
+            .into(),
+            None => content,
+        };
+
         container(content)
             .padding(15)
             .width(Length::Fill)
@@ -200,28 +1312,587 @@ impl Manage {
             .style(container::rounded_box)
     }
 
+    fn reconnect_button(&self) -> Option<Element<Message>> {
+        self.disconnected.then(|| {
+            button(if self.reconnecting {
+                "Reconnecting..."
+            } else {
+                "Reconnect"
+            })
+            .on_press_maybe((!self.reconnecting).then_some(Message::Reconnect))
+            .into()
+        })
+    }
+
     // Helper method to get a task for loading script content
     fn load_script_content(&self, script_name: String) -> Task<Message> {
         let client = self.client.clone();
+        let generation = self.script_generation.fetch_add(1, Ordering::Relaxed) + 1;
         Task::future(async move {
             match client.get_script(&script_name).await {
-                Ok(content) => Message::ScriptContentLoaded(Ok(content)),
-                Err(e) => Message::ScriptContentLoaded(Err(format!(
-                    "Failed to load script '{}': {}",
-                    script_name, e
-                ))),
+                Ok(content) => Message::ScriptContentLoaded(generation, Ok(content)),
+                Err(e) => failed(e, |e| {
+                    Message::ScriptContentLoaded(
+                        generation,
+                        Err(format!("Failed to load script '{}': {}", script_name, e)),
+                    )
+                }),
+            }
+        })
+    }
+
+    // Helper method to get a task for reading the persisted editor preferences
+    fn load_editor_settings(&self) -> Task<Message> {
+        let pool = self.pool.clone();
+        Task::future(async move {
+            // Missing or unreadable settings just keep the defaults
+            let font_size = settings::get(&pool, FONT_SIZE_SETTING)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|value| value.parse().ok());
+            let wrap = settings::get(&pool, WRAP_SETTING)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|value| value.parse().ok());
+
+            Message::EditorSettingsLoaded(font_size, wrap)
+        })
+    }
+
+    fn store_setting(&self, key: &'static str, value: String) -> Task<Message> {
+        let pool = self.pool.clone();
+        Task::future(async move {
+            Message::SettingStored(
+                settings::set(&pool, key, &value)
+                    .await
+                    .map_err(|e| format!("Failed to save setting: {}", e)),
+            )
+        })
+    }
+
+    fn load_folders(&self) -> Task<Message> {
+        let pool = self.pool.clone();
+        let account_id = self.account_id;
+        Task::future(async move {
+            Message::FoldersLoaded(
+                sqlx::query!(
+                    "SELECT name FROM folders WHERE account_id = ? ORDER BY name",
+                    account_id
+                )
+                .fetch_all(&pool)
+                .await
+                .map(|rows| rows.into_iter().map(|row| row.name).collect())
+                .map_err(|e| format!("Failed to load folders: {}", e)),
+            )
+        })
+    }
+
+    // Adds the folders not remembered yet, in memory right away and in the
+    // database in the background
+    fn remember_folders(&mut self, folders: impl IntoIterator<Item = String>) -> Task<Message> {
+        let new: Vec<String> = folders
+            .into_iter()
+            .filter(|folder| self.folders.insert(folder.clone()))
+            .collect();
+        if new.is_empty() {
+            return Task::none();
+        }
+
+        let pool = self.pool.clone();
+        let account_id = self.account_id;
+        Task::future(async move {
+            for folder in new {
+                if let Err(e) = sqlx::query!(
+                    "INSERT OR IGNORE INTO folders (account_id, name) VALUES (?, ?)",
+                    account_id,
+                    folder
+                )
+                .execute(&pool)
+                .await
+                {
+                    return Message::FoldersStored(Err(format!(
+                        "Failed to remember folder: {}",
+                        e
+                    )));
+                }
+            }
+            Message::FoldersStored(Ok(()))
+        })
+    }
+
+    // Helper method to get a task for uploading the edited script. With
+    // `expected_hash` the server copy is checked first and a changed or deleted
+    // script is reported as a conflict instead of being overwritten.
+    fn save_script(
+        &self,
+        script_name: String,
+        content: String,
+        expected_hash: Option<u64>,
+    ) -> Task<Message> {
+        let client = self.client.clone();
+        // Transformed before uploading, so the literal length and the hash
+        // kept for the overwrite check match what the server stores
+        let content = if self.trim_whitespace_on_save {
+            trim_trailing_whitespace(&content)
+        } else {
+            content
+        };
+        Task::future(async move {
+            if let Some(expected_hash) = expected_hash {
+                match client.get_script(&script_name).await {
+                    Ok(current) if content_hash(&current) != expected_hash => {
+                        return Message::SaveConflict(script_name);
+                    }
+                    Ok(_) => {}
+                    Err(ManageSieveError::ScriptNotFound(_)) => {
+                        return Message::SaveConflict(script_name);
+                    }
+                    Err(e) => {
+                        return failed(e, |e| {
+                            let err = format!(
+                                "Failed to check script '{}' before saving: {}",
+                                script_name, e
+                            );
+                            Message::Saved(script_name, Err(err))
+                        });
+                    }
+                }
+            }
+
+            match client.put_script(&script_name, &content).await {
+                Ok(()) => Message::Saved(
+                    script_name,
+                    Ok(SavedScript {
+                        hash: content_hash(&content),
+                        // Scripts that don't parse just don't add any folders
+                        folders: parser::parse(&content)
+                            .map(|script| parser::summarize(&script).folders)
+                            .unwrap_or_default(),
+                        content,
+                    }),
+                ),
+                Err(e) => failed(e, |e| {
+                    let err = format!("Failed to save script '{}': {}", script_name, e);
+                    Message::Saved(script_name, Err(err))
+                }),
             }
         })
     }
 
+    // Helper method to get a task for creating an empty script or renaming one
+    fn name_script(&self, prompt: NamePrompt) -> Task<Message> {
+        let client = self.client.clone();
+        let selected = self.selected_script.clone();
+        Task::future(async move {
+            let NamePrompt { rename, name } = prompt;
+            match rename {
+                Some(old_name) => match client.rename_script(&old_name, &name).await {
+                    Ok(()) => Message::ScriptRenamed(old_name, Ok(name)),
+                    Err(e) => failed(e, |e| {
+                        let err = format!("Failed to rename script '{}': {}", old_name, e);
+                        Message::ScriptRenamed(old_name, Err(err))
+                    }),
+                },
+                None => match client.put_script(&name, "").await {
+                    Ok(()) => Message::ScriptCreated(selected, Ok(name)),
+                    Err(e) => failed(e, |e| {
+                        Message::ScriptCreated(
+                            selected,
+                            Err(format!("Failed to create script '{}': {}", name, e)),
+                        )
+                    }),
+                },
+            }
+        })
+    }
+
+    // Helper method to get a task for loading script sizes and the largest script
+    // that still fits
+    fn load_usage(&self, generation: u64, names: Vec<String>) -> Task<Message> {
+        let client = self.client.clone();
+        let current = self.list_generation.clone();
+        Task::future(async move {
+            let mut sizes = Vec::new();
+            let mut folders = BTreeSet::new();
+            for name in names {
+                // Downloading every script takes a while, stop once the list
+                // was refreshed again
+                if current.load(Ordering::Relaxed) != generation {
+                    return Message::Superseded;
+                }
+
+                match client.get_script(&name).await {
+                    Ok(content) => {
+                        // Scripts that don't parse just don't add any folders
+                        if let Ok(script) = parser::parse(&content) {
+                            folders.extend(parser::summarize(&script).folders);
+                        }
+                        sizes.push((name, content.len()));
+                    }
+                    Err(e) => {
+                        return failed(e, |e| {
+                            Message::UsageLoaded(
+                                generation,
+                                Err(format!("Failed to load script '{}': {}", name, e)),
+                            )
+                        });
+                    }
+                }
+            }
+
+            match probe_free_space(&client).await {
+                Ok(free) => Message::UsageLoaded(
+                    generation,
+                    Ok(Usage {
+                        sizes,
+                        free,
+                        folders,
+                    }),
+                ),
+                Err(e) => failed(e, |e| {
+                    Message::UsageLoaded(
+                        generation,
+                        Err(format!("Failed to check free space: {}", e)),
+                    )
+                }),
+            }
+        })
+    }
+
+    // Helper method to get a task for checking every script with the built-in
+    // parser and, where the server supports CHECKSCRIPT, with the server
+    fn check_scripts(&self) -> Task<Message> {
+        let client = self.client.clone();
+        let names: Vec<String> = self
+            .scripts
+            .iter()
+            .flatten()
+            .map(|script| script.name.clone())
+            .collect();
+        Task::future(async move {
+            // CHECKSCRIPT came with the VERSION capability
+            let server_checks = client.capabilities().version.is_some();
+            let max_redirects = client.capabilities().maxredirects;
+            let mut report = Vec::new();
+            for name in names {
+                let content = match client.get_script(&name).await {
+                    Ok(content) => content,
+                    Err(e) => {
+                        return failed(e, |e| {
+                            Message::Checked(Err(format!(
+                                "Failed to load script '{}': {}",
+                                name, e
+                            )))
+                        });
+                    }
+                };
+
+                let mut warnings = Vec::new();
+                let mut error = None;
+                match parser::parse(&content) {
+                    Ok(expressions) => {
+                        let findings = parser::lint(&expressions).into_iter().chain(
+                            max_redirects.and_then(|max| parser::lint_redirects(&expressions, max)),
+                        );
+                        warnings.extend(findings.map(|finding| finding.to_string()))
+                    }
+                    // The server knows extensions the built-in parser doesn't,
+                    // so its verdict wins where there is one
+                    Err(err) if server_checks => warnings.push(format!(
+                        "Not understood by the built-in parser from line {}, column {}",
+                        err.line, err.column
+                    )),
+                    Err(err) => {
+                        error = Some(format!(
+                            "Parse error at line {}, column {}",
+                            err.line, err.column
+                        ))
+                    }
+                }
+
+                if server_checks {
+                    match client.check_script(&content).await {
+                        Ok(CheckResult::Warnings {
+                            message: Some(message),
+                        }) => warnings.push(message),
+                        Ok(CheckResult::Warnings { message: None }) => warnings
+                            .push("The server reported warnings but didn't say which".to_string()),
+                        Ok(CheckResult::Clean) => {}
+                        Err(ManageSieveError::ServerError { message, .. }) => error = Some(message),
+                        Err(e) => {
+                            return failed(e, |e| {
+                                Message::Checked(Err(format!(
+                                    "Failed to check script '{}': {}",
+                                    name, e
+                                )))
+                            });
+                        }
+                    }
+                }
+
+                let status = match error {
+                    Some(error) => CheckStatus::Error(error),
+                    None if warnings.is_empty() => CheckStatus::Ok,
+                    None => CheckStatus::Warnings(warnings),
+                };
+                report.push(ScriptCheck { name, status });
+            }
+
+            Message::Checked(Ok(report))
+        })
+    }
+
+    // Records the size of a script uploaded by this session. The room left
+    // shrinks by as much as the script grew.
+    fn set_size(&mut self, script_name: String, size: usize) {
+        let old_size = self.sizes.insert(script_name, size).unwrap_or(0);
+        self.free_space = self
+            .free_space
+            .map(|free| (free + old_size).saturating_sub(size));
+        self.apply_sizes();
+    }
+
+    // Shows the known sizes in the list, the total only if every size is known
+    fn apply_sizes(&mut self) {
+        let Some(scripts) = &mut self.scripts else {
+            return;
+        };
+        for script in scripts.iter_mut() {
+            script.size = self.sizes.get(&script.name).copied();
+        }
+        self.used = scripts.iter().map(|script| script.size).sum();
+    }
+
     // Helper method to get a task for refreshing scripts
     fn refresh_scripts(&self) -> Task<Message> {
         let client = self.client.clone();
+        let generation = self.list_generation.fetch_add(1, Ordering::Relaxed) + 1;
         Task::future(async move {
             match client.list_scripts().await {
-                Ok(scripts) => Message::ScriptsLoaded(Ok(scripts)),
-                Err(e) => Message::ScriptsLoaded(Err(format!("Failed to load scripts: {}", e))),
+                Ok(scripts) => Message::ScriptsLoaded(generation, Ok(scripts)),
+                Err(e) => failed(e, |e| {
+                    Message::ScriptsLoaded(
+                        generation,
+                        Err(format!("Failed to load scripts: {}", e)),
+                    )
+                }),
             }
         })
     }
 }
+
+// Reports a failed command with `report`, unless the server ended the session,
+// stopped answering or got out of step with the client, which have their own
+// message so a reconnect can be offered
+fn failed(error: ManageSieveError, report: impl FnOnce(SieveError) -> Message) -> Message {
+    match error {
+        ManageSieveError::ServerClosed(reason) => Message::ConnectionClosed(reason),
+        error @ (ManageSieveError::Timeout(_) | ManageSieveError::OutOfSync(_)) => {
+            Message::ConnectionClosed(error.to_string())
+        }
+        error => report(SieveError::from(error)),
+    }
+}
+
+// Writes `content` to a temporary file, opens it in the user's editor and reads
+// it back once the editor is closed. The file is removed in any case.
+async fn edit_externally(script_name: &str, content: &str) -> Result<String, String> {
+    // Keep only characters that are safe in a file name on every platform
+    let file_name: String = script_name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let path = write_temp_file(&file_name, content).await?;
+
+    let result = run_editor(&path).await;
+    let result = match result {
+        Ok(()) => tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e)),
+        Err(err) => Err(err),
+    };
+
+    let _ = tokio::fs::remove_file(&path).await;
+    result
+}
+
+// Writes `content` to a new temporary file and returns its path. The file is
+// flushed and closed before returning, so an editor started next reads all of
+// it, and removed again if that fails.
+async fn write_temp_file(file_name: &str, content: &str) -> Result<std::path::PathBuf, String> {
+    let (path, mut file) = create_temp_file(file_name).await?;
+    let written = match file.write_all(content.as_bytes()).await {
+        Ok(()) => file.flush().await,
+        Err(e) => Err(e),
+    };
+    drop(file);
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(format!("Failed to write {}: {}", path.display(), e));
+    }
+    Ok(path)
+}
+
+// Creates a new file in the temporary directory that only the user can read.
+// Other users can write to that directory too, so an existing file or symlink
+// with the same name is never opened, another name is tried instead.
+async fn create_temp_file(
+    file_name: &str,
+) -> Result<(std::path::PathBuf, tokio::fs::File), String> {
+    let mut attempt = 0;
+    loop {
+        let path = std::env::temp_dir().join(format!(
+            "sieve-gui-{}-{}-{}.sieve",
+            std::process::id(),
+            attempt,
+            file_name
+        ));
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        match options.open(&path).await {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(e) => return Err(format!("Failed to create {}: {}", path.display(), e)),
+        }
+    }
+}
+
+// Runs `$VISUAL` or `$EDITOR` on `path`, falling back to the default
+// application of the system where it can be waited for
+async fn run_editor(path: &std::path::Path) -> Result<(), String> {
+    let editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty());
+
+    let mut command = match editor {
+        // May carry arguments, e.g. `code --wait`, and a quoted path with
+        // spaces, so the shell splits it the way it would in a terminal
+        Some(editor) if cfg!(unix) => {
+            let mut command = tokio::process::Command::new("sh");
+            command.args(["-c", &format!("{} \"$@\"", editor), "sh"]);
+            command
+        }
+        // A path with spaces, e.g. under `C:\\Program Files`, or a program with
+        // arguments
+        Some(editor) => {
+            if std::path::Path::new(&editor).is_file() {
+                tokio::process::Command::new(editor)
+            } else {
+                let mut parts = editor.split_whitespace();
+                let mut command = tokio::process::Command::new(parts.next().unwrap_or_default());
+                command.args(parts);
+                command
+            }
+        }
+        None if cfg!(target_os = "macos") => {
+            let mut command = tokio::process::Command::new("open");
+            // Wait for the editor and open it as text, .sieve has no default app
+            command.args(["-W", "-t"]);
+            command
+        }
+        None if cfg!(target_os = "windows") => {
+            let mut command = tokio::process::Command::new("cmd");
+            command.args(["/C", "start", "", "/WAIT"]);
+            command
+        }
+        // xdg-open returns before the editor is closed, so there is nothing to wait for
+        None => {
+            return Err(
+                "No external editor configured, set the EDITOR environment variable".to_string(),
+            );
+        }
+    };
+
+    let status = command
+        .arg(path)
+        .status()
+        .await
+        .map_err(|e| format!("Failed to start the external editor: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("The external editor failed: {}", status))
+    }
+}
+
+// Binary searches HAVESPACE for the largest script the server would still accept
+async fn probe_free_space(
+    client: &SieveClient,
+) -> Result<Option<usize>, sieve_client::ManageSieveError> {
+    if client
+        .have_space(QUOTA_PROBE_NAME, QUOTA_PROBE_LIMIT)
+        .await?
+    {
+        return Ok(None);
+    }
+
+    let mut fits = 0;
+    let mut too_large = QUOTA_PROBE_LIMIT;
+    while too_large - fits > 1 {
+        let size = fits + (too_large - fits) / 2;
+        if client.have_space(QUOTA_PROBE_NAME, size).await? {
+            fits = size;
+        } else {
+            too_large = size;
+        }
+    }
+
+    Ok(Some(fits))
+}
+
+// Strips whitespace at the end of every line and leaves exactly one newline at
+// the end of a non-empty script
+fn trim_trailing_whitespace(content: &str) -> String {
+    let mut trimmed = String::with_capacity(content.len());
+    for line in content.trim_end().lines() {
+        trimmed.push_str(line.trim_end());
+        trimmed.push('\n');
+    }
+    trimmed
+}
+
+// Only compared within one session, so the std hasher is good enough
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_temp_file() {
+        let content = "require \"fileinto\";\n".repeat(1000);
+        let path = write_temp_file("main", &content).await.unwrap();
+
+        let written = tokio::fs::read_to_string(&path).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        assert_eq!(written.unwrap(), content);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{net::IpAddr, time::Duration};
+
+use iced::{
+    Element, Task,
+    widget::{
+        button, checkbox, column, horizontal_space, radio, row, text, text_input, vertical_space,
+    },
+};
+use sqlx::SqlitePool;
+
+use crate::settings::{Preferences, ThemeChoice};
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Theme(ThemeChoice),
+    Port(String),
+    Timeout(String),
+    TrimWhitespace(bool),
+    ProbeAccounts(bool),
+    LocalAddress(String),
+    Save,
+    Saved(Result<Preferences, String>),
+    DismissError,
+    Back,
+}
+
+pub enum Action {
+    None,
+    Run(Task<Message>),
+    Saved(Preferences),
+    Back,
+}
+
+pub struct Settings {
+    pool: SqlitePool,
+    theme: ThemeChoice,
+    // Kept as typed so invalid input can be shown and corrected
+    port: String,
+    timeout: String,
+    trim_whitespace_on_save: bool,
+    probe_accounts: bool,
+    local_address: String,
+    saving: bool,
+    error: Option<String>,
+}
+
+impl Settings {
+    pub fn new(pool: SqlitePool, preferences: &Preferences) -> (Self, Task<Message>) {
+        (
+            Self {
+                pool,
+                theme: preferences.theme,
+                port: preferences.default_port.to_string(),
+                timeout: preferences.connect_timeout.as_secs().to_string(),
+                trim_whitespace_on_save: preferences.trim_whitespace_on_save,
+                probe_accounts: preferences.probe_accounts,
+                local_address: preferences
+                    .local_address
+                    .map(|address| address.to_string())
+                    .unwrap_or_default(),
+                saving: false,
+                error: None,
+            },
+            Task::none(),
+        )
+    }
+
+    pub fn update(&mut self, message: Message) -> Action {
+        match message {
+            Message::Theme(theme) => {
+                self.theme = theme;
+                Action::None
+            }
+            Message::Port(port) => {
+                self.port = port;
+                Action::None
+            }
+            Message::Timeout(timeout) => {
+                self.timeout = timeout;
+                Action::None
+            }
+            Message::TrimWhitespace(trim) => {
+                self.trim_whitespace_on_save = trim;
+                Action::None
+            }
+            Message::ProbeAccounts(probe) => {
+                self.probe_accounts = probe;
+                Action::None
+            }
+            Message::LocalAddress(address) => {
+                self.local_address = address;
+                Action::None
+            }
+            Message::Save => match self.preferences() {
+                Some(preferences) if !self.saving => {
+                    self.saving = true;
+                    self.error = None;
+                    Action::Run(self.save(preferences))
+                }
+                _ => Action::None,
+            },
+            Message::Saved(result) => {
+                self.saving = false;
+                match result {
+                    Ok(preferences) => Action::Saved(preferences),
+                    Err(err) => {
+                        self.error = Some(err);
+                        Action::None
+                    }
+                }
+            }
+            Message::DismissError => {
+                self.error = None;
+                Action::None
+            }
+            Message::Back => Action::Back,
+        }
+    }
+
+    pub fn view(&self) -> Element<Message> {
+        let port_valid = parse_port(&self.port).is_some();
+        let timeout_valid = parse_timeout(&self.timeout).is_some();
+        let local_address_valid = parse_local_address(&self.local_address).is_some();
+
+        let mut content = column![].padding(50).spacing(20);
+        if let Some(err) = &self.error {
+            content = content.push(super::toast::toast(err, Message::DismissError, None));
+        }
+
+        let mut port = column![
+            text("Default port").size(14),
+            text_input("4190", &self.port).on_input(Message::Port),
+        ]
+        .spacing(5);
+        if !port_valid {
+            port = port.push(
+                text("Enter a port between 1 and 65535")
+                    .size(12)
+                    .style(text::danger),
+            );
+        }
+
+        let mut timeout = column![
+            text("Connection timeout in seconds").size(14),
+            text_input("30", &self.timeout).on_input(Message::Timeout),
+        ]
+        .spacing(5);
+        if !timeout_valid {
+            timeout = timeout.push(
+                text("Enter a whole number of seconds greater than 0")
+                    .size(12)
+                    .style(text::danger),
+            );
+        }
+
+        let mut local_address = column![
+            text("Local address to connect from").size(14),
+            text_input("Any interface", &self.local_address).on_input(Message::LocalAddress),
+        ]
+        .spacing(5);
+        if !local_address_valid {
+            local_address = local_address.push(
+                text("Enter an IPv4 or IPv6 address, or leave it empty")
+                    .size(12)
+                    .style(text::danger),
+            );
+        }
+
+        content
+            .push(text("Settings").size(20))
+            .push(
+                column![
+                    text("Theme").size(14),
+                    row![
+                        radio(
+                            "Light",
+                            ThemeChoice::Light,
+                            Some(self.theme),
+                            Message::Theme
+                        ),
+                        radio("Dark", ThemeChoice::Dark, Some(self.theme), Message::Theme),
+                    ]
+                    .spacing(20),
+                ]
+                .spacing(5),
+            )
+            .push(port)
+            .push(timeout)
+            .push(local_address)
+            .push(
+                checkbox(
+                    "Trim trailing whitespace when saving scripts",
+                    self.trim_whitespace_on_save,
+                )
+                .on_toggle(Message::TrimWhitespace),
+            )
+            .push(
+                checkbox(
+                    "Show whether each account's server is reachable",
+                    self.probe_accounts,
+                )
+                .on_toggle(Message::ProbeAccounts),
+            )
+            .push(vertical_space())
+            .push(
+                row![
+                    horizontal_space(),
+                    button(text("Back").center())
+                        .on_press(Message::Back)
+                        .width(100),
+                    button(text(if self.saving { "Saving..." } else { "Save" }).center())
+                        .on_press_maybe(
+                            (port_valid && timeout_valid && local_address_valid && !self.saving)
+                                .then_some(Message::Save)
+                        )
+                        .width(100)
+                ]
+                .spacing(20),
+            )
+            .into()
+    }
+
+    fn preferences(&self) -> Option<Preferences> {
+        Some(Preferences {
+            theme: self.theme,
+            default_port: parse_port(&self.port)?,
+            connect_timeout: parse_timeout(&self.timeout)?,
+            trim_whitespace_on_save: self.trim_whitespace_on_save,
+            probe_accounts: self.probe_accounts,
+            local_address: parse_local_address(&self.local_address)?,
+        })
+    }
+
+    fn save(&self, preferences: Preferences) -> Task<Message> {
+        let pool = self.pool.clone();
+        Task::future(async move {
+            match preferences.save(&pool).await {
+                Ok(()) => Message::Saved(Ok(preferences)),
+                Err(err) => Message::Saved(Err(format!("Failed to save settings: {}", err))),
+            }
+        })
+    }
+}
+
+fn parse_port(port: &str) -> Option<u16> {
+    port.trim().parse().ok().filter(|port| *port != 0)
+}
+
+// `Some(None)` for an empty field, which means any interface
+fn parse_local_address(address: &str) -> Option<Option<IpAddr>> {
+    let address = address.trim();
+    if address.is_empty() {
+        Some(None)
+    } else {
+        address.parse().ok().map(Some)
+    }
+}
+
+fn parse_timeout(timeout: &str) -> Option<Duration> {
+    timeout
+        .trim()
+        .parse()
+        .ok()
+        .filter(|seconds| *seconds != 0)
+        .map(Duration::from_secs)
+}
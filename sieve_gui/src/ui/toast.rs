@@ -0,0 +1,31 @@
+use iced::{
+    Alignment, Element, Length,
+    widget::{button, container, horizontal_space, row, text},
+};
+
+/// A dismissible banner for errors the user can recover from without leaving
+/// the current screen. With `on_retry` set it also offers to repeat the failed
+/// operation.
+pub fn toast<'a, Message: Clone + 'a>(
+    error: &'a str,
+    on_dismiss: Message,
+    on_retry: Option<Message>,
+) -> Element<'a, Message> {
+    let mut content = row![text(error).size(14), horizontal_space()]
+        .spacing(10)
+        .align_y(Alignment::Center);
+    if let Some(on_retry) = on_retry {
+        content = content.push(button(text("Retry").size(14)).on_press(on_retry));
+    }
+    content = content.push(
+        button(text("Dismiss").size(14))
+            .style(button::text)
+            .on_press(on_dismiss),
+    );
+
+    container(content)
+        .width(Length::Fill)
+        .padding([8, 12])
+        .style(container::rounded_box)
+        .into()
+}